@@ -1,6 +1,8 @@
 //! Example program to populate the vault with sample data for testing
 
 use passmngr::{
+    backup::BackupPolicy,
+    crypto::{KdfCost, Session},
     model::{Entry, Vault},
     storage::VaultFile,
 };
@@ -59,7 +61,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Save with test password
     let password = "testpassword";
-    VaultFile::save(&vault_path, &vault, password)?;
+    let session = Session::new(password, None, KdfCost::default())?;
+    VaultFile::save(&vault_path, &vault, &session, &BackupPolicy::default())?;
 
     println!("Vault populated successfully at: {}", vault_path.display());
     println!("Password: {}", password);
@@ -1,10 +1,22 @@
 //! Application state and logic
 
-use crate::model::{Entry, Vault};
-use crate::storage::VaultFile;
+use crate::config::{expand_path, Config, VaultTarget};
+use crate::crypto::{Keyfile, Session};
+use crate::history::{Change, History};
+use crate::lock::LockState;
+use crate::merge::{self, Merge, Side};
+use crate::model::{
+    CustomField, Entry, EntryKind, EntryUrl, Expiry, FieldKind, PasswordPolicy, SortOrder,
+    Template, TrashedEntry, Vault,
+};
+use crate::otp::{self, Otp, OtpKind};
+use crate::storage::{Fingerprint, VaultFile};
+use crate::urlmatch;
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use ratatui::widgets::ListState;
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 use uuid::Uuid;
 
@@ -17,6 +29,11 @@ pub enum Mode {
     Command,
     Detail,
     Locked,
+    Passwd,
+    Conflict,
+    Trash,
+    Folders,
+    Templates,
 }
 
 impl Mode {
@@ -28,6 +45,11 @@ impl Mode {
             Mode::Command => "COMMAND",
             Mode::Detail => "DETAIL",
             Mode::Locked => "LOCKED",
+            Mode::Passwd => "PASSWD",
+            Mode::Conflict => "CONFLICT",
+            Mode::Trash => "TRASH",
+            Mode::Folders => "FOLDERS",
+            Mode::Templates => "TEMPLATES",
         }
     }
 }
@@ -41,6 +63,24 @@ pub enum FormField {
     Url,
     Notes,
     Tags,
+    CardHolder,
+    CardNumber,
+    CardExpiry,
+    CardCvv,
+    FullName,
+    Email,
+    Phone,
+    Street,
+    City,
+    Postcode,
+    Country,
+    PrivateKey,
+    PublicKey,
+    Scopes,
+    /// Label of the custom field at this index
+    CustomLabel(usize),
+    /// Value of the custom field at this index
+    CustomValue(usize),
 }
 
 impl FormField {
@@ -52,49 +92,291 @@ impl FormField {
             FormField::Url => "URL",
             FormField::Notes => "Notes",
             FormField::Tags => "Tags",
+            FormField::CardHolder => "Cardholder",
+            FormField::CardNumber => "Number",
+            FormField::CardExpiry => "Expiry",
+            FormField::CardCvv => "CVV",
+            FormField::FullName => "Full name",
+            FormField::Email => "Email",
+            FormField::Phone => "Phone",
+            FormField::Street => "Street",
+            FormField::City => "City",
+            FormField::Postcode => "Postcode",
+            FormField::Country => "Country",
+            FormField::PrivateKey => "Private key",
+            FormField::PublicKey => "Public key",
+            FormField::Scopes => "Scopes",
+            FormField::CustomLabel(_) => "Label",
+            FormField::CustomValue(_) => "Value",
         }
     }
 
-    pub fn next(&self) -> Self {
+    /// Label in the form for an entry of `kind`
+    pub fn label(&self, kind: &EntryKind) -> &str {
         match self {
-            FormField::Name => FormField::Username,
-            FormField::Username => FormField::Password,
-            FormField::Password => FormField::Url,
-            FormField::Url => FormField::Notes,
-            FormField::Notes => FormField::Tags,
-            FormField::Tags => FormField::Name,
+            FormField::Password => kind.password_label().unwrap_or("Password"),
+            FormField::Url => kind.url_label().unwrap_or("URL"),
+            _ => self.as_str(),
         }
     }
 
-    pub fn prev(&self) -> Self {
+    /// Whether the value is masked until revealed (custom fields aside)
+    pub fn is_secret(&self) -> bool {
+        matches!(
+            self,
+            FormField::Password
+                | FormField::CardNumber
+                | FormField::CardCvv
+                | FormField::PrivateKey
+        )
+    }
+
+    /// The fields of the form for an entry of `kind`, without custom fields
+    pub fn layout(kind: &EntryKind) -> &'static [FormField] {
+        use FormField::*;
+        match kind {
+            EntryKind::Login => &[Name, Username, Password, Url, Notes, Tags],
+            EntryKind::Note => &[Name, Notes, Tags],
+            EntryKind::Card(_) => &[
+                Name, CardHolder, CardNumber, CardExpiry, CardCvv, Notes, Tags,
+            ],
+            EntryKind::Identity(_) => &[
+                Name, FullName, Email, Phone, Street, City, Postcode, Country, Notes, Tags,
+            ],
+            EntryKind::SshKey(_) => &[Name, Username, Password, PublicKey, PrivateKey, Notes, Tags],
+            EntryKind::ApiToken(_) => &[Name, Password, Url, Scopes, Notes, Tags],
+        }
+    }
+
+    /// All fields of a form for `kind` with `custom` custom fields, in order
+    pub fn order(kind: &EntryKind, custom: usize) -> Vec<FormField> {
+        let mut fields = Self::layout(kind).to_vec();
+        for i in 0..custom {
+            fields.push(FormField::CustomLabel(i));
+            fields.push(FormField::CustomValue(i));
+        }
+        fields
+    }
+
+    /// Next field in a form for `kind` with `custom` custom fields
+    pub fn next(&self, kind: &EntryKind, custom: usize) -> Self {
+        let order = Self::order(kind, custom);
+        let i = order.iter().position(|f| f == self).unwrap_or(0);
+        order[(i + 1) % order.len()]
+    }
+
+    /// Previous field in a form for `kind` with `custom` custom fields
+    pub fn prev(&self, kind: &EntryKind, custom: usize) -> Self {
+        let order = Self::order(kind, custom);
+        let i = order.iter().position(|f| f == self).unwrap_or(0);
+        order[(i + order.len() - 1) % order.len()]
+    }
+
+    /// Index of the custom field this belongs to, if any
+    pub fn custom_index(&self) -> Option<usize> {
         match self {
-            FormField::Name => FormField::Tags,
-            FormField::Username => FormField::Name,
-            FormField::Password => FormField::Username,
-            FormField::Url => FormField::Password,
-            FormField::Notes => FormField::Url,
-            FormField::Tags => FormField::Notes,
+            FormField::CustomLabel(i) | FormField::CustomValue(i) => Some(*i),
+            _ => None,
         }
     }
 }
 
+/// A visible row of the folder pane
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FolderRow {
+    /// `None` for the "all entries" row at the top
+    pub id: Option<Uuid>,
+    pub name: String,
+    pub depth: usize,
+    pub has_children: bool,
+    pub collapsed: bool,
+}
+
+/// Inputs on the unlock screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnlockField {
+    #[default]
+    Password,
+    Keyfile,
+}
+
+/// Steps of the `:passwd` master password change prompt
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PasswdStage {
+    #[default]
+    Current,
+    New,
+    Confirm,
+}
+
+impl PasswdStage {
+    pub fn as_str(&self) -> &str {
+        match self {
+            PasswdStage::Current => "Current password",
+            PasswdStage::New => "New password",
+            PasswdStage::Confirm => "Confirm password",
+        }
+    }
+}
+
+/// Input state for the `:passwd` prompt
+#[derive(Debug, Clone, Default)]
+pub struct PasswdForm {
+    pub stage: PasswdStage,
+    pub current: String,
+    pub new: String,
+    pub confirm: String,
+}
+
+impl PasswdForm {
+    /// Get the input for a stage
+    pub fn input(&self, stage: PasswdStage) -> &str {
+        match stage {
+            PasswdStage::Current => &self.current,
+            PasswdStage::New => &self.new,
+            PasswdStage::Confirm => &self.confirm,
+        }
+    }
+
+    /// Get mutable reference to the input of the current stage
+    pub fn input_mut(&mut self) -> &mut String {
+        match self.stage {
+            PasswdStage::Current => &mut self.current,
+            PasswdStage::New => &mut self.new,
+            PasswdStage::Confirm => &mut self.confirm,
+        }
+    }
+}
+
+/// A merge with the on-disk vault waiting for conflicts to be resolved
+#[derive(Debug, Clone)]
+pub struct MergeState {
+    pub merge: Merge,
+    pub choices: Vec<Option<Side>>,
+    pub selected: usize,
+    /// The on-disk vault the merge was made against
+    theirs: Vault,
+    fingerprint: Fingerprint,
+}
+
+impl MergeState {
+    /// Number of conflicts without a choice yet
+    pub fn unresolved(&self) -> usize {
+        self.choices.iter().filter(|c| c.is_none()).count()
+    }
+}
+
 /// Form data for entry creation/editing
 #[derive(Debug, Clone, Default)]
 pub struct FormData {
     pub name: String,
+    /// The kind being edited, holding its kind-specific values
+    pub kind: EntryKind,
     pub username: String,
     pub password: String,
     pub url: String,
     pub notes: String,
     pub tags: String,
+    /// API token scopes, comma separated like the tags
+    pub scopes: String,
+    pub fields: Vec<CustomField>,
+    /// How `Ctrl+G` generates the password (set by templates)
+    pub policy: PasswordPolicy,
     pub editing_id: Option<Uuid>,
 }
 
+impl FormData {
+    /// Kind-specific value of `field`, if the form's kind has it
+    fn detail(&self, field: FormField) -> Option<&String> {
+        use FormField::*;
+        Some(match (field, &self.kind) {
+            (CardHolder, EntryKind::Card(card)) => &card.holder,
+            (CardNumber, EntryKind::Card(card)) => &card.number,
+            (CardExpiry, EntryKind::Card(card)) => &card.expiry,
+            (CardCvv, EntryKind::Card(card)) => &card.cvv,
+            (FullName, EntryKind::Identity(identity)) => &identity.full_name,
+            (Email, EntryKind::Identity(identity)) => &identity.email,
+            (Phone, EntryKind::Identity(identity)) => &identity.phone,
+            (Street, EntryKind::Identity(identity)) => &identity.street,
+            (City, EntryKind::Identity(identity)) => &identity.city,
+            (Postcode, EntryKind::Identity(identity)) => &identity.postcode,
+            (Country, EntryKind::Identity(identity)) => &identity.country,
+            (PrivateKey, EntryKind::SshKey(key)) => &key.private_key,
+            (PublicKey, EntryKind::SshKey(key)) => &key.public_key,
+            _ => return None,
+        })
+    }
+
+    /// Mutable kind-specific value of `field`, if the form's kind has it
+    fn detail_mut(&mut self, field: FormField) -> Option<&mut String> {
+        use FormField::*;
+        Some(match (field, &mut self.kind) {
+            (CardHolder, EntryKind::Card(card)) => &mut card.holder,
+            (CardNumber, EntryKind::Card(card)) => &mut card.number,
+            (CardExpiry, EntryKind::Card(card)) => &mut card.expiry,
+            (CardCvv, EntryKind::Card(card)) => &mut card.cvv,
+            (FullName, EntryKind::Identity(identity)) => &mut identity.full_name,
+            (Email, EntryKind::Identity(identity)) => &mut identity.email,
+            (Phone, EntryKind::Identity(identity)) => &mut identity.phone,
+            (Street, EntryKind::Identity(identity)) => &mut identity.street,
+            (City, EntryKind::Identity(identity)) => &mut identity.city,
+            (Postcode, EntryKind::Identity(identity)) => &mut identity.postcode,
+            (Country, EntryKind::Identity(identity)) => &mut identity.country,
+            (PrivateKey, EntryKind::SshKey(key)) => &mut key.private_key,
+            (PublicKey, EntryKind::SshKey(key)) => &mut key.public_key,
+            _ => return None,
+        })
+    }
+}
+
+/// Split a comma-separated list (tags, scopes), dropping empty items
+fn split_list(text: &str) -> Vec<String> {
+    text.split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Status shown when the vault was written by a newer passmngr
+const NEWER_VERSION_WARNING: &str =
+    "Vault written by a newer passmngr: unknown fields ignored, saving disabled";
+
+/// Status shown when another process holds the vault lock
+fn read_only_message(pid: u32) -> String {
+    format!("Read-only: vault in use by PID {}", pid)
+}
+
 /// Application state
 pub struct App {
     pub vault: Vault,
+    /// Name shown in the header (profile name or file name)
+    pub vault_name: String,
     pub vault_path: PathBuf,
-    pub password: String,
+    /// Keyfile used as second unlock factor, if any
+    pub keyfile_path: Option<PathBuf>,
+    /// Derived key for the unlocked vault (`None` while locked)
+    pub session: Option<Session>,
+    /// Vault as last read from or written to disk (base of a merge)
+    pub base: Vault,
+    /// Undo/redo stacks of edits made since unlocking
+    pub history: History,
+    /// Fingerprint of the vault file as last read or written
+    pub disk_fingerprint: Fingerprint,
+    pub merge_state: Option<MergeState>,
+    /// Selected row of the trash view
+    pub trash_selected: usize,
+    pub sort_order: SortOrder,
+    /// Folder the list is limited to (with its subfolders); `None` shows all
+    pub folder_filter: Option<Uuid>,
+    /// Selected row of the folder pane
+    pub folder_selected: usize,
+    /// Folders whose subfolders are hidden in the folder pane
+    pub collapsed_folders: HashSet<Uuid>,
+    /// Selected row of the template picker (0 is a blank entry)
+    pub template_selected: usize,
+    /// Cross-process lock on the vault; `InUse` makes this session read-only
+    pub vault_lock: LockState,
+    pub config: Config,
     pub mode: Mode,
     pub selected: usize,
     pub search_query: String,
@@ -113,29 +395,59 @@ pub struct App {
     // Auto-lock fields
     pub last_activity: Instant,
     pub unlock_input: String,
+    pub unlock_keyfile: String,
+    pub unlock_field: UnlockField,
+    pub passwd_form: PasswdForm,
 }
 
 impl App {
     /// Create new application with loaded vault
-    pub fn new(vault_path: PathBuf, password: String, vault: Vault) -> Self {
-        let filtered_entries = vault.entries.iter().map(|e| e.id).collect();
+    pub fn new(
+        target: VaultTarget,
+        keyfile_path: Option<PathBuf>,
+        session: Session,
+        vault: Vault,
+        disk_fingerprint: Fingerprint,
+        vault_lock: LockState,
+        config: Config,
+    ) -> Self {
         let mut list_state = ListState::default();
         list_state.select(Some(0));
+        let status_message = match vault_lock {
+            LockState::InUse { pid } => Some(read_only_message(pid)),
+            LockState::Acquired(_) => vault
+                .is_from_newer_version()
+                .then(|| NEWER_VERSION_WARNING.to_string()),
+        };
 
-        Self {
+        let mut app = Self {
+            base: vault.clone(),
+            history: History::default(),
             vault,
-            vault_path,
-            password,
+            vault_name: target.name,
+            vault_path: target.path,
+            keyfile_path,
+            session: Some(session),
+            disk_fingerprint,
+            merge_state: None,
+            trash_selected: 0,
+            sort_order: SortOrder::default(),
+            folder_filter: None,
+            folder_selected: 0,
+            collapsed_folders: HashSet::new(),
+            template_selected: 0,
+            vault_lock,
+            config,
             mode: Mode::Normal,
             selected: 0,
             search_query: String::new(),
             command_buffer: String::new(),
             command_completions: Vec::new(),
             completion_index: 0,
-            status_message: None,
+            status_message,
             should_quit: false,
             dirty: false,
-            filtered_entries,
+            filtered_entries: Vec::new(),
             form_data: FormData::default(),
             focused_field: FormField::Name,
             pending_save: false,
@@ -143,13 +455,18 @@ impl App {
             show_password: false,
             last_activity: Instant::now(),
             unlock_input: String::new(),
-        }
+            unlock_keyfile: String::new(),
+            unlock_field: UnlockField::Password,
+            passwd_form: PasswdForm::default(),
+        };
+        app.update_search();
+        app
     }
 
     /// Generate a secure password for the current field
     pub fn generate_password(&mut self) {
         if self.mode == Mode::Insert && self.focused_field == FormField::Password {
-            let password = crate::crypto::generate_secure_password(20);
+            let password = crate::crypto::generate_password(&self.form_data.policy);
             self.form_data.password = password;
             self.set_status("Generated high-entropy password".to_string());
             self.show_password = true; // Show it so user knows
@@ -173,29 +490,81 @@ impl App {
 
         // Clear sensitive data
         self.vault.entries.clear();
+        self.vault.trash.clear();
+        self.vault.folders.clear();
+        self.vault.templates.clear();
+        self.base.entries.clear();
+        self.base.trash.clear();
+        self.base.folders.clear();
+        self.base.templates.clear();
+        self.folder_filter = None;
+        self.folder_selected = 0;
+        self.collapsed_folders.clear();
+        self.history.clear();
+        self.merge_state = None;
         self.filtered_entries.clear();
         self.search_query.clear();
         self.form_data = FormData::default();
+        self.passwd_form = PasswdForm::default();
         let _ = self.clear_clipboard(); // Ignore error, best effort
 
-        // Zeroize master password from memory
-        // Note: String doesn't guarantee zeroization on drop, but we overwrite it here.
-        // For true security, we'd use `secrecy` crate, but this is a good baseline.
-        self.password = String::new();
+        // Drop the derived key (EncryptionKey zeroizes itself on drop)
+        self.session = None;
 
         self.mode = Mode::Locked;
         self.unlock_input.clear();
+        self.unlock_keyfile = self
+            .keyfile_path
+            .as_ref()
+            .map(|p| p.display().to_string())
+            .unwrap_or_default();
+        self.unlock_field = UnlockField::Password;
         self.set_status("Vault Locked due to inactivity".to_string());
     }
 
+    /// Get mutable reference to the focused unlock input
+    pub fn unlock_input_mut(&mut self) -> &mut String {
+        match self.unlock_field {
+            UnlockField::Password => &mut self.unlock_input,
+            UnlockField::Keyfile => &mut self.unlock_keyfile,
+        }
+    }
+
+    /// Switch focus between the password and keyfile inputs
+    pub fn toggle_unlock_field(&mut self) {
+        self.unlock_field = match self.unlock_field {
+            UnlockField::Password => UnlockField::Keyfile,
+            UnlockField::Keyfile => UnlockField::Password,
+        };
+    }
+
     /// Attempt to unlock the vault
     pub fn unlock(&mut self) -> Result<()> {
-        // Attempt to load vault with provided password
-        // This verifies the password via authentication tag (ChaCha20-Poly1305)
-        match VaultFile::load(&self.vault_path, &self.unlock_input) {
-            Ok(vault) => {
+        let keyfile_path = match self.unlock_keyfile.trim() {
+            "" => None,
+            path => Some(expand_path(path)?),
+        };
+        let keyfile = match keyfile_path.as_deref().map(Keyfile::read).transpose() {
+            Ok(keyfile) => keyfile,
+            Err(e) => {
+                self.set_status(e.to_string());
+                return Err(e);
+            }
+        };
+
+        // Attempt to load vault with provided password and keyfile
+        // This verifies the secrets via authentication tag (ChaCha20-Poly1305)
+        match VaultFile::open_with_fingerprint(
+            &self.vault_path,
+            &self.unlock_input,
+            keyfile.as_ref(),
+        ) {
+            Ok((vault, session, fingerprint)) => {
+                self.base = vault.clone();
                 self.vault = vault;
-                self.password = self.unlock_input.clone(); // Restore password
+                self.session = Some(session);
+                self.disk_fingerprint = fingerprint;
+                self.keyfile_path = keyfile_path;
 
                 // Restore state
                 self.filtered_entries = self.vault.entries.iter().map(|e| e.id).collect();
@@ -203,12 +572,17 @@ impl App {
                 self.mode = Mode::Normal;
                 self.unlock_input.clear();
                 self.last_activity = Instant::now();
-                self.set_status("Vault Unlocked".to_string());
+                if self.vault.is_from_newer_version() {
+                    self.set_status(NEWER_VERSION_WARNING.to_string());
+                } else {
+                    self.set_status("Vault Unlocked".to_string());
+                }
                 Ok(())
             }
             Err(_) => {
-                self.set_status("Incorrect password or vault error".to_string());
+                self.set_status("Incorrect password, keyfile or vault error".to_string());
                 self.unlock_input.clear();
+                self.unlock_field = UnlockField::Password;
                 Err(anyhow::anyhow!("Unlock failed"))
             }
         }
@@ -233,17 +607,35 @@ impl App {
 
     /// Update filtered entries based on search query
     pub fn update_search(&mut self) {
-        self.filtered_entries = self
+        // The folder may have been deleted, undone or merged away
+        if self
+            .folder_filter
+            .is_some_and(|f| self.vault.folder(&f).is_none())
+        {
+            self.folder_filter = None;
+        }
+
+        let selected_id = self.get_selected_id();
+        let mut entries: Vec<&Entry> = self
             .vault
             .search(&self.search_query)
             .into_iter()
-            .map(|e| e.id)
+            .filter(|e| {
+                self.folder_filter
+                    .is_none_or(|folder| self.vault.folder_within(e.folder, &folder))
+            })
             .collect();
+        self.sort_order.sort(&mut entries, Utc::now());
+        self.filtered_entries = entries.iter().map(|e| e.id).collect();
 
-        // Clamp selected index to valid range
+        // Stay on the selected entry if it's still listed, else clamp
         let len = self.filtered_entries.len();
         let non_empty = (len > 0) as usize;
-        self.selected = self.selected.min(len.saturating_sub(1)) * non_empty;
+        self.selected =
+            match selected_id.and_then(|id| self.filtered_entries.iter().position(|e| *e == id)) {
+                Some(pos) => pos,
+                None => self.selected.min(len.saturating_sub(1)) * non_empty,
+            };
 
         // Update list state selection
         self.list_state
@@ -278,25 +670,485 @@ impl App {
         }
     }
 
+    /// Make an edit to the vault, recording it for undo
+    pub fn apply_change(&mut self, change: Change) {
+        self.history.apply(change, &mut self.vault);
+        self.vault_changed();
+    }
+
+    /// Undo the latest edit
+    pub fn undo(&mut self) {
+        let status = match self.history.undo(&mut self.vault) {
+            Some(change) => format!("Undid {}", change.describe()),
+            None => "Already at oldest change".to_string(),
+        };
+        self.vault_changed();
+        self.set_status(status);
+    }
+
+    /// Redo the latest undone edit
+    pub fn redo(&mut self) {
+        let status = match self.history.redo(&mut self.vault) {
+            Some(change) => format!("Redid {}", change.describe()),
+            None => "Already at newest change".to_string(),
+        };
+        self.vault_changed();
+        self.set_status(status);
+    }
+
+    /// Refresh derived state after the vault was modified
+    fn vault_changed(&mut self) {
+        self.dirty = self.vault != self.base;
+        self.move_trash_selection(0);
+        self.update_search();
+    }
+
     /// Add a new entry to the vault
     pub fn add_entry(&mut self, entry: Entry) {
-        self.vault.add_entry(entry);
-        self.dirty = true;
-        self.update_search();
+        self.apply_change(Change::Add(entry));
     }
 
-    /// Delete currently selected entry
+    /// Move the currently selected entry to the trash
     pub fn delete_selected(&mut self) -> Option<Entry> {
-        if let Some(id) = self.get_selected_id() {
-            let entry = self.vault.remove_entry(&id);
-            self.dirty = true;
-            self.update_search();
-            entry
+        let id = self.get_selected_id()?;
+        let index = self.vault.entries.iter().position(|e| e.id == id)?;
+        let entry = self.vault.entries[index].clone();
+        self.apply_change(Change::Trash {
+            index,
+            trashed: TrashedEntry {
+                entry: entry.clone(),
+                deleted: Utc::now(),
+            },
+        });
+        Some(entry)
+    }
+
+    /// Show the trash
+    pub fn enter_trash_mode(&mut self) {
+        self.mode = Mode::Trash;
+        self.trash_selected = self.vault.trash.len().saturating_sub(1);
+    }
+
+    /// Move the trash selection by `delta`
+    pub fn move_trash_selection(&mut self, delta: isize) {
+        let last = self.vault.trash.len().saturating_sub(1);
+        self.trash_selected = self.trash_selected.saturating_add_signed(delta).min(last);
+    }
+
+    /// Put the selected trashed entry back into the vault
+    pub fn restore_selected(&mut self) {
+        let Some(trashed) = self.vault.trash.get(self.trash_selected).cloned() else {
+            return;
+        };
+        self.set_status(format!("Restored '{}'", trashed.entry.name));
+        self.apply_change(Change::Restore {
+            index: self.trash_selected,
+            trashed,
+        });
+    }
+
+    /// Delete the selected trashed entry for good
+    pub fn purge_selected(&mut self) {
+        let Some(trashed) = self.vault.trash.get(self.trash_selected).cloned() else {
+            return;
+        };
+        self.set_status(format!(
+            "Permanently deleted '{}' (u to undo)",
+            trashed.entry.name
+        ));
+        self.apply_change(Change::Purge {
+            index: self.trash_selected,
+            trashed,
+        });
+    }
+
+    /// Days until a trashed entry is purged (`None` if kept forever)
+    pub fn days_until_purge(&self, deleted: DateTime<Utc>) -> Option<i64> {
+        let purge_at = self.config.trash.purge_at(deleted)?;
+        Some((purge_at - Utc::now()).num_days().max(0))
+    }
+
+    /// Record that a value of the selected entry was copied
+    ///
+    /// Usage is saved with the vault like any change, but isn't an edit: undo
+    /// leaves it alone and the list isn't re-sorted until the next search or
+    /// change.
+    fn record_selected_use(&mut self) {
+        if self.read_only_pid().is_some() {
+            return;
+        }
+        if let Some(entry) = self
+            .get_selected_id()
+            .and_then(|id| self.vault.get_entry_mut(&id))
+        {
+            entry.record_use(Utc::now());
+            self.dirty = self.vault != self.base;
+        }
+    }
+
+    /// Whether the unsaved changes are only usage statistics
+    fn only_usage_changed(&self) -> bool {
+        let without_usage = |vault: &Vault| {
+            let mut vault = vault.clone();
+            for entry in &mut vault.entries {
+                entry.last_used = None;
+                entry.use_count = 0;
+            }
+            vault
+        };
+        without_usage(&self.vault) == without_usage(&self.base)
+    }
+
+    /// Pin or unpin the selected entry
+    ///
+    /// Pinning isn't a content edit, so the modified time stays as it is.
+    pub fn toggle_favorite(&mut self) {
+        let Some(before) = self.get_selected_entry().cloned() else {
+            self.set_status("No entry selected".to_string());
+            return;
+        };
+        let mut after = before.clone();
+        after.favorite = !after.favorite;
+        self.set_status(format!(
+            "{} '{}'",
+            if after.favorite { "Pinned" } else { "Unpinned" },
+            after.name
+        ));
+        self.apply_change(Change::Edit {
+            before: Box::new(before),
+            after: Box::new(after),
+        });
+    }
+
+    /// `:expires DATE|INTERVAL|never` - set when the selected entry's password expires
+    fn set_expiry(&mut self, text: &str) -> String {
+        let expiry = match text.trim() {
+            "never" => None,
+            text => match Expiry::parse(text) {
+                Some(expiry) => Some(expiry),
+                None => return "Usage: expires 2025-12-31|90d|never".to_string(),
+            },
+        };
+        let Some(before) = self.get_selected_entry().cloned() else {
+            return "No entry selected".to_string();
+        };
+        let mut after = before.clone();
+        after.expiry = expiry;
+        after.touch();
+        let status = match after.expires() {
+            Some(expires) => format!(
+                "'{}' expires {} ({})",
+                after.name,
+                expires.format("%Y-%m-%d"),
+                expiry.expect("expiry is set")
+            ),
+            None => format!("'{}' no longer expires", after.name),
+        };
+        self.apply_change(Change::Edit {
+            before: Box::new(before),
+            after: Box::new(after),
+        });
+        status
+    }
+
+    /// `:sort ORDER` - change the order of the entry list
+    fn set_sort_order(&mut self, order: &str) -> String {
+        match SortOrder::parse(order.trim()) {
+            Some(order) => {
+                self.sort_order = order;
+                self.update_search();
+                format!("Sorted by {} (pinned entries first)", order.as_str())
+            }
+            None => "Usage: sort frecency|name|modified|created".to_string(),
+        }
+    }
+
+    /// Focus the folder pane
+    pub fn enter_folder_mode(&mut self) {
+        self.mode = Mode::Folders;
+        self.folder_selected = self
+            .folder_rows()
+            .iter()
+            .position(|row| row.id == self.folder_filter)
+            .unwrap_or(0);
+    }
+
+    /// Visible rows of the folder pane: "all entries", then the expanded tree
+    pub fn folder_rows(&self) -> Vec<FolderRow> {
+        let mut rows = vec![FolderRow {
+            id: None,
+            name: "All entries".to_string(),
+            depth: 0,
+            has_children: false,
+            collapsed: false,
+        }];
+        self.push_folder_rows(None, 0, &mut rows);
+        rows
+    }
+
+    fn push_folder_rows(&self, parent: Option<Uuid>, depth: usize, rows: &mut Vec<FolderRow>) {
+        // Deeper than the folder count means a cycle in a damaged vault
+        if depth > self.vault.folders.len() {
+            return;
+        }
+        for folder in self.vault.subfolders(parent) {
+            let collapsed = self.collapsed_folders.contains(&folder.id);
+            rows.push(FolderRow {
+                id: Some(folder.id),
+                name: folder.name.clone(),
+                depth,
+                has_children: !self.vault.subfolders(Some(folder.id)).is_empty(),
+                collapsed,
+            });
+            if !collapsed {
+                self.push_folder_rows(Some(folder.id), depth + 1, rows);
+            }
+        }
+    }
+
+    /// Move the folder pane selection by `delta`
+    pub fn move_folder_selection(&mut self, delta: isize) {
+        let last = self.folder_rows().len() - 1;
+        self.folder_selected = self.folder_selected.saturating_add_signed(delta).min(last);
+    }
+
+    /// Show or hide the subfolders of the selected folder
+    pub fn toggle_folder_collapsed(&mut self) {
+        let Some(id) = self
+            .folder_rows()
+            .get(self.folder_selected)
+            .and_then(|r| r.id)
+        else {
+            return;
+        };
+        if !self.collapsed_folders.remove(&id) {
+            self.collapsed_folders.insert(id);
+        }
+    }
+
+    /// Limit the list to the selected folder and go back to it
+    pub fn select_folder(&mut self) {
+        self.folder_filter = self
+            .folder_rows()
+            .get(self.folder_selected)
+            .and_then(|row| row.id);
+        self.selected = 0;
+        self.mode = Mode::Normal;
+        self.update_search();
+    }
+
+    /// Path of the folder the list is limited to (empty for all entries)
+    pub fn folder_filter_path(&self) -> String {
+        self.folder_filter
+            .map(|id| self.vault.folder_path(&id))
+            .unwrap_or_default()
+    }
+
+    /// `:move PATH` - put the selected entry into a folder, creating it if
+    /// needed (an empty path or `/` is the top level)
+    fn move_selected_to_folder(&mut self, path: &str) -> String {
+        let Some(before) = self.get_selected_entry().cloned() else {
+            return "No entry selected".to_string();
+        };
+
+        let mut folders = self.vault.clone();
+        let folder = folders.create_folder_path(path);
+        let mut changes = Vec::new();
+        if folders.folders != self.vault.folders {
+            changes.push(Change::Folders {
+                before: self.vault.folders.clone(),
+                after: folders.folders.clone(),
+            });
+        }
+        let mut after = before.clone();
+        after.folder = folder;
+        after.touch();
+        changes.push(Change::Edit {
+            before: Box::new(before),
+            after: Box::new(after.clone()),
+        });
+
+        self.apply_change(Change::Batch(changes));
+        match folder {
+            Some(id) => format!("Moved '{}' to {}", after.name, self.vault.folder_path(&id)),
+            None => format!("Moved '{}' to the top level", after.name),
+        }
+    }
+
+    /// `:mkdir PATH` - create a folder and any missing parents
+    fn make_folder(&mut self, path: &str) -> String {
+        let mut folders = self.vault.clone();
+        let Some(id) = folders.create_folder_path(path) else {
+            return "Usage: mkdir <folder/path>".to_string();
+        };
+        if folders.folders == self.vault.folders {
+            return format!("Folder {} already exists", self.vault.folder_path(&id));
+        }
+        self.apply_change(Change::Folders {
+            before: self.vault.folders.clone(),
+            after: folders.folders,
+        });
+        format!("Created folder {}", self.vault.folder_path(&id))
+    }
+
+    /// `:rename-folder NAME` - rename the folder the list is limited to
+    fn rename_folder(&mut self, name: &str) -> String {
+        let Some(id) = self.folder_filter else {
+            return "Select a folder first (f)".to_string();
+        };
+        let name = name.trim();
+        if name.is_empty() || name.contains(crate::model::FOLDER_SEPARATOR) {
+            return "Usage: rename-folder <new name> (without '/')".to_string();
+        }
+        let parent = self.vault.folder(&id).and_then(|f| f.parent);
+        if self
+            .vault
+            .subfolders(parent)
+            .iter()
+            .any(|f| f.id != id && f.name.eq_ignore_ascii_case(name))
+        {
+            return format!("A folder named '{}' already exists there", name);
+        }
+
+        let old = self.vault.folder_path(&id);
+        let mut after = self.vault.folders.clone();
+        if let Some(folder) = after.iter_mut().find(|f| f.id == id) {
+            folder.name = name.to_string();
+        }
+        self.apply_change(Change::Folders {
+            before: self.vault.folders.clone(),
+            after,
+        });
+        format!("Renamed {} to {}", old, self.vault.folder_path(&id))
+    }
+
+    /// `:delete-folder` - delete the folder the list is limited to; its
+    /// entries and subfolders move up to its parent
+    fn delete_folder(&mut self) -> String {
+        let Some(id) = self.folder_filter else {
+            return "Select a folder first (f)".to_string();
+        };
+        let path = self.vault.folder_path(&id);
+
+        let mut vault = self.vault.clone();
+        let parent = vault.delete_folder(&id).and_then(|f| f.parent);
+        let mut changes = vec![Change::Folders {
+            before: self.vault.folders.clone(),
+            after: vault.folders,
+        }];
+        for before in self.vault.entries.iter().filter(|e| e.folder == Some(id)) {
+            let mut after = before.clone();
+            after.folder = parent;
+            after.touch();
+            changes.push(Change::Edit {
+                before: Box::new(before.clone()),
+                after: Box::new(after),
+            });
+        }
+
+        self.apply_change(Change::Batch(changes));
+        self.folder_filter = parent;
+        self.update_search();
+        format!("Deleted folder {}", path)
+    }
+
+    /// Start a new entry, offering the templates first if there are any
+    pub fn new_entry(&mut self) {
+        if self.vault.templates.is_empty() {
+            self.enter_insert_mode();
         } else {
-            None
+            self.mode = Mode::Templates;
+            self.template_selected = 0;
+        }
+    }
+
+    /// Move the template picker selection by `delta`
+    pub fn move_template_selection(&mut self, delta: isize) {
+        let last = self.vault.templates.len();
+        self.template_selected = self
+            .template_selected
+            .saturating_add_signed(delta)
+            .min(last);
+    }
+
+    /// Start a new entry from the selected template (or a blank one)
+    pub fn pick_template(&mut self) {
+        let template = self
+            .template_selected
+            .checked_sub(1)
+            .and_then(|i| self.vault.templates.get(i))
+            .cloned();
+        self.enter_insert_mode();
+        let Some(template) = template else {
+            return;
+        };
+
+        self.form_data.kind = template.kind;
+        self.form_data.tags = template.tags.join(", ");
+        self.form_data.fields = template.fields;
+        self.form_data.policy = template.password;
+        if self.form_data.kind.password_label().is_some() {
+            self.form_data.password = crate::crypto::generate_password(&self.form_data.policy);
+            self.show_password = true;
+        }
+        self.set_status(format!("New entry from template '{}'", template.name));
+    }
+
+    /// `:template save [NAME]` / `:template delete NAME`
+    fn template_command(&mut self, args: &str) -> String {
+        match args.trim().split_once(' ').unwrap_or((args.trim(), "")) {
+            ("save", name) => self.save_template(name.trim()),
+            ("delete", name) if !name.trim().is_empty() => self.delete_template(name),
+            _ => "Usage: template save [NAME] | template delete NAME".to_string(),
         }
     }
 
+    /// Capture the selected entry's shape as a template (named after the
+    /// entry unless `name` is given); a template of the same name is replaced
+    fn save_template(&mut self, name: &str) -> String {
+        let Some(entry) = self.get_selected_entry() else {
+            return "No entry selected".to_string();
+        };
+        let name = if name.is_empty() { &entry.name } else { name };
+        let mut template = Template::from_entry(name.to_string(), entry);
+
+        let mut after = self.vault.templates.clone();
+        match after
+            .iter_mut()
+            .find(|t| t.name.to_lowercase() == template.name.to_lowercase())
+        {
+            Some(existing) => {
+                template.id = existing.id;
+                *existing = template.clone();
+            }
+            None => after.push(template.clone()),
+        }
+        self.apply_change(Change::Templates {
+            before: self.vault.templates.clone(),
+            after,
+        });
+        format!(
+            "Saved template '{}' ({}, password {})",
+            template.name,
+            template.kind.as_str(),
+            template.password
+        )
+    }
+
+    /// Delete the template called `name`
+    fn delete_template(&mut self, name: &str) -> String {
+        let Some(id) = self.vault.find_template(name).map(|t| t.id) else {
+            return format!("No template '{}'", name.trim());
+        };
+        let mut after = self.vault.templates.clone();
+        after.retain(|t| t.id != id);
+        self.apply_change(Change::Templates {
+            before: self.vault.templates.clone(),
+            after,
+        });
+        format!("Deleted template '{}'", name.trim())
+    }
+
     /// Request save operation (sets pending_save flag and shows immediate feedback)
     pub fn request_save(&mut self) {
         self.pending_save = true;
@@ -305,20 +1157,165 @@ impl App {
 
     /// Save vault to disk
     ///
-    /// Note: This operation blocks for ~100-150ms due to Argon2id key derivation,
-    /// which is intentionally memory-hard for security. The delay is unavoidable
-    /// and necessary to protect against brute-force attacks.
+    /// Encrypts with the session key derived at unlock, so no Argon2id run is
+    /// needed here; only a fresh nonce is generated.
+    ///
+    /// If the file changed on disk since it was read, the on-disk version is
+    /// merged in first; conflicting entries switch to `Mode::Conflict` and
+    /// nothing is written until they are resolved.
     ///
     /// This should be called from the main loop after a draw() to ensure the
-    /// "Saving..." status is visible before the blocking operation.
+    /// "Saving..." status is visible before the disk write.
     pub fn save(&mut self) -> Result<()> {
-        VaultFile::save(&self.vault_path, &self.vault, &self.password)?;
+        if let Some(pid) = self.read_only_pid() {
+            return Err(anyhow::anyhow!(read_only_message(pid)));
+        }
+
+        let mut status = "Vault saved";
+        match VaultFile::fingerprint(&self.vault_path)? {
+            Some(fingerprint) if fingerprint != self.disk_fingerprint => {
+                let session = self
+                    .session
+                    .as_mut()
+                    .ok_or_else(|| anyhow::anyhow!("Vault is locked"))?;
+                let (theirs, fingerprint) = VaultFile::reload(&self.vault_path, session)?;
+                if theirs.is_from_newer_version() {
+                    return Err(anyhow::anyhow!(
+                        "Vault on disk was written by a newer passmngr; not merging"
+                    ));
+                }
+
+                let merge = merge::merge(&self.base, &self.vault, &theirs);
+                if !merge.conflicts.is_empty() {
+                    self.start_conflict_resolution(merge, theirs, fingerprint);
+                    return Ok(());
+                }
+                self.adopt_merge(merge.vault, theirs, fingerprint);
+                status = "Merged changes from disk and saved";
+            }
+            _ => {}
+        }
+
+        self.vault.purge_expired(&self.config.trash, Utc::now());
+        self.disk_fingerprint = VaultFile::save(
+            &self.vault_path,
+            &self.vault,
+            self.session()?,
+            &self.config.backups,
+        )?;
+        self.base = self.vault.clone();
         self.dirty = false;
         self.pending_save = false;
-        self.set_status("Vault saved".to_string());
+        self.set_status(status.to_string());
+
+        // Blobs of purged entries and removed attachments, unless undo could
+        // bring them back; a leftover blob is harmless, so errors are ignored
+        let referenced = self
+            .vault
+            .attachment_ids()
+            .chain(self.history.attachment_ids())
+            .collect();
+        let _ = crate::attachment::collect_garbage(&self.vault_path, self.session()?, &referenced);
         Ok(())
     }
 
+    /// Session of the unlocked vault
+    fn session(&self) -> Result<&Session> {
+        self.session
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Vault is locked"))
+    }
+
+    /// Take a merged vault; `theirs` becomes the base for the next merge
+    fn adopt_merge(&mut self, vault: Vault, theirs: Vault, fingerprint: Fingerprint) {
+        self.vault = vault;
+        self.base = theirs;
+        self.disk_fingerprint = fingerprint;
+        self.update_search();
+    }
+
+    /// Hold back the save and let the user resolve conflicting entries
+    fn start_conflict_resolution(&mut self, merge: Merge, theirs: Vault, fingerprint: Fingerprint) {
+        let count = merge.conflicts.len();
+        self.merge_state = Some(MergeState {
+            choices: vec![None; count],
+            merge,
+            selected: 0,
+            theirs,
+            fingerprint,
+        });
+        self.mode = Mode::Conflict;
+        self.pending_save = false;
+        // A :wq must not quit before the conflicts are resolved
+        self.should_quit = false;
+        self.set_status(format!(
+            "Vault changed on disk: {} conflicting {}",
+            count,
+            if count == 1 { "entry" } else { "entries" }
+        ));
+    }
+
+    /// Pick a side for the selected conflict; saves once all are resolved
+    pub fn resolve_conflict(&mut self, side: Side) {
+        let Some(state) = self.merge_state.as_mut() else {
+            return;
+        };
+        state.choices[state.selected] = Some(side);
+
+        // Move on to the next unresolved conflict, if any
+        let len = state.choices.len();
+        if let Some(next) = (1..=len)
+            .map(|offset| (state.selected + offset) % len)
+            .find(|&i| state.choices[i].is_none())
+        {
+            state.selected = next;
+            return;
+        }
+
+        if let Some(state) = self.merge_state.take() {
+            let choices: Vec<Side> = state.choices.into_iter().flatten().collect();
+            let vault = state.merge.resolve(&choices);
+            self.adopt_merge(vault, state.theirs, state.fingerprint);
+            self.mode = Mode::Normal;
+            self.dirty = self.vault != self.base;
+            self.request_save();
+        }
+    }
+
+    /// Pick the same side for every unresolved conflict
+    pub fn resolve_all_conflicts(&mut self, side: Side) {
+        while let Some(state) = self.merge_state.as_mut() {
+            match state.choices.iter().position(|c| c.is_none()) {
+                Some(i) => state.selected = i,
+                None => break,
+            }
+            self.resolve_conflict(side);
+        }
+    }
+
+    /// Move the conflict selection by `delta`
+    pub fn move_conflict_selection(&mut self, delta: isize) {
+        if let Some(state) = self.merge_state.as_mut() {
+            let last = state.choices.len().saturating_sub(1);
+            state.selected = state.selected.saturating_add_signed(delta).min(last);
+        }
+    }
+
+    /// Abandon the merge; edits stay unsaved
+    pub fn cancel_merge(&mut self) {
+        self.merge_state = None;
+        self.mode = Mode::Normal;
+        self.set_status("Merge cancelled, vault not saved".to_string());
+    }
+
+    /// PID of the process holding the vault lock, if this session is read-only
+    pub fn read_only_pid(&self) -> Option<u32> {
+        match self.vault_lock {
+            LockState::InUse { pid } => Some(pid),
+            LockState::Acquired(_) => None,
+        }
+    }
+
     /// Set status message
     pub fn set_status(&mut self, message: String) {
         self.status_message = Some(message);
@@ -362,6 +1359,22 @@ impl App {
             "write",
             "wq",
             "x",
+            "passwd",
+            "trash",
+            "emptytrash",
+            "sort frecency",
+            "sort name",
+            "sort modified",
+            "sort created",
+            "expires never",
+            "expires ",
+            "move ",
+            "mkdir ",
+            "rename-folder ",
+            "delete-folder",
+            "template save ",
+            "template delete ",
+            "attach save ",
             "export firefox ",
             "export json ",
             "export csv ",
@@ -416,10 +1429,34 @@ impl App {
         if cmd.starts_with("export ") {
             return self.handle_export_command(&cmd);
         }
+        if cmd.starts_with("attach ") {
+            return self.handle_attach_command(&cmd);
+        }
+        let folder_status = match cmd.split_once(' ').unwrap_or((&cmd, "")) {
+            ("sort", order) => Some(self.set_sort_order(order)),
+            ("expires", expiry) => Some(self.set_expiry(expiry)),
+            ("move", path) => Some(self.move_selected_to_folder(path)),
+            ("mkdir", path) => Some(self.make_folder(path)),
+            ("rename-folder", name) => Some(self.rename_folder(name)),
+            ("delete-folder", "") => Some(self.delete_folder()),
+            ("template", args) => Some(self.template_command(args)),
+            _ => None,
+        };
+        if let Some(status) = folder_status {
+            self.set_status(status);
+            self.mode = Mode::Normal;
+            self.command_buffer.clear();
+            return Ok(());
+        }
 
         match cmd.as_str() {
             "q" | "quit" => {
-                if self.dirty {
+                if self.dirty && self.only_usage_changed() {
+                    self.set_status(
+                        "Unsaved usage statistics! Use :q! to discard them or :wq to save and quit"
+                            .to_string(),
+                    );
+                } else if self.dirty {
                     self.set_status(
                         "Unsaved changes! Use :q! to force quit or :wq to save and quit"
                             .to_string(),
@@ -438,6 +1475,37 @@ impl App {
                 self.request_save();
                 self.should_quit = true;
             }
+            "passwd" => {
+                self.enter_passwd_mode();
+                self.command_buffer.clear();
+                return Ok(());
+            }
+            "trash" => {
+                self.enter_trash_mode();
+                self.command_buffer.clear();
+                return Ok(());
+            }
+            "emptytrash" => {
+                // Purged front to back, so each one is at index 0 when undone
+                let purges = self
+                    .vault
+                    .trash
+                    .iter()
+                    .map(|trashed| Change::Purge {
+                        index: 0,
+                        trashed: trashed.clone(),
+                    })
+                    .collect::<Vec<_>>();
+                let count = purges.len();
+                if count > 0 {
+                    self.apply_change(Change::Batch(purges));
+                }
+                self.set_status(format!(
+                    "Permanently deleted {} trashed {}",
+                    count,
+                    if count == 1 { "entry" } else { "entries" }
+                ));
+            }
             _ => {
                 self.set_status(format!("Unknown command: {}", cmd));
             }
@@ -448,12 +1516,111 @@ impl App {
         Ok(())
     }
 
+    /// Enter the master password change prompt
+    pub fn enter_passwd_mode(&mut self) {
+        if let Some(pid) = self.read_only_pid() {
+            self.mode = Mode::Normal;
+            self.set_status(read_only_message(pid));
+            return;
+        }
+        self.mode = Mode::Passwd;
+        self.passwd_form = PasswdForm::default();
+    }
+
+    /// Cancel the master password change prompt
+    pub fn cancel_passwd(&mut self) {
+        self.mode = Mode::Normal;
+        self.passwd_form = PasswdForm::default();
+    }
+
+    /// Advance the master password prompt; changes the password after confirmation
+    pub fn submit_passwd_stage(&mut self) {
+        match self.passwd_form.stage {
+            PasswdStage::Current => {
+                self.passwd_form.stage = PasswdStage::New;
+            }
+            PasswdStage::New => {
+                if let Err(e) = crate::crypto::validate_master_password(&self.passwd_form.new) {
+                    self.set_status(e.to_string());
+                    self.passwd_form.new.clear();
+                    return;
+                }
+                self.passwd_form.stage = PasswdStage::Confirm;
+            }
+            PasswdStage::Confirm => {
+                if self.passwd_form.new != self.passwd_form.confirm {
+                    self.set_status("Passwords do not match. Try again.".to_string());
+                    self.passwd_form.new.clear();
+                    self.passwd_form.confirm.clear();
+                    self.passwd_form.stage = PasswdStage::New;
+                    return;
+                }
+                self.change_master_password();
+            }
+        }
+    }
+
+    /// Re-key the vault file with the new master password
+    ///
+    /// Only the on-disk vault is rewritten; unsaved edits stay pending.
+    fn change_master_password(&mut self) {
+        let form = std::mem::take(&mut self.passwd_form);
+        self.mode = Mode::Normal;
+
+        // Re-keying rewrites the file as it is on disk; merge foreign changes first
+        if !matches!(VaultFile::fingerprint(&self.vault_path), Ok(Some(fp)) if fp == self.disk_fingerprint)
+        {
+            self.set_status(
+                "Vault changed on disk; save (:w) before changing the password".to_string(),
+            );
+            return;
+        }
+
+        // The keyfile factor is kept as-is; read it once for old and new key
+        let keyfile = match self.keyfile_path.as_deref().map(Keyfile::read).transpose() {
+            Ok(keyfile) => keyfile,
+            Err(e) => {
+                self.set_status(e.to_string());
+                return;
+            }
+        };
+
+        match VaultFile::change_password(
+            &self.vault_path,
+            &form.current,
+            keyfile.as_ref(),
+            &form.new,
+            keyfile.as_ref(),
+            None,
+            None,
+        ) {
+            Ok(session) => {
+                let had_recovery = self
+                    .session
+                    .as_ref()
+                    .is_some_and(|session| session.recovery().is_some());
+                // Replace the cached key derived from the old password
+                self.session = Some(session);
+                if let Ok(Some(fingerprint)) = VaultFile::fingerprint(&self.vault_path) {
+                    self.disk_fingerprint = fingerprint;
+                }
+                self.set_status(if had_recovery {
+                    "Master password changed; recovery shares have to be set up again".to_string()
+                } else {
+                    "Master password changed".to_string()
+                });
+            }
+            Err(_) => {
+                self.set_status("Incorrect password or vault error".to_string());
+            }
+        }
+    }
+
     /// Handle export command
     /// Format: export <format> <path>
     /// Example: export firefox ~/backup.csv
     fn handle_export_command(&mut self, cmd: &str) -> Result<()> {
         use crate::export::{export_to_file, ExportFormat};
-        use std::path::PathBuf;
 
         let parts: Vec<&str> = cmd.split_whitespace().collect();
 
@@ -479,21 +1646,14 @@ impl App {
             }
         };
 
-        // Expand ~ to home directory
-        let path = if path_str.starts_with("~") {
-            let home = dirs::home_dir()
-                .ok_or_else(|| anyhow::anyhow!("Could not determine home directory"))?;
-            PathBuf::from(path_str.replacen("~", &home.to_string_lossy(), 1))
-        } else {
-            PathBuf::from(path_str)
-        };
+        let path = expand_path(path_str)?;
 
         // Export vault
         match export_to_file(&self.vault, &path, format) {
-            Ok(_) => {
+            Ok(count) => {
                 self.set_status(format!(
                     "⚠ EXPORTED {} PLAINTEXT PASSWORDS to {} - DELETE AFTER USE!",
-                    self.vault.entries.len(),
+                    count,
                     path.display()
                 ));
             }
@@ -507,12 +1667,62 @@ impl App {
         Ok(())
     }
 
+    /// Handle attachment command
+    /// Format: attach save <name|number> <path>
+    /// Example: attach save 1 ~/.ssh/id_ed25519
+    fn handle_attach_command(&mut self, cmd: &str) -> Result<()> {
+        let parts: Vec<&str> = cmd.split_whitespace().collect();
+
+        let status = match parts.as_slice() {
+            ["attach", "save", selector, path] => match self.save_attachment(selector, path) {
+                Ok(status) => status,
+                Err(e) => format!("Saving attachment failed: {}", e),
+            },
+            _ => "Usage: attach save <name|number> <path>".to_string(),
+        };
+        self.set_status(status);
+
+        self.mode = Mode::Normal;
+        self.command_buffer.clear();
+        Ok(())
+    }
+
+    /// Decrypt an attachment of the selected entry into a new file
+    ///
+    /// An existing directory as `path` receives the file under its own name.
+    fn save_attachment(&self, selector: &str, path: &str) -> Result<String> {
+        let entry = self
+            .get_selected_entry()
+            .ok_or_else(|| anyhow::anyhow!("No entry selected"))?;
+        let found = entry
+            .attachment(selector)
+            .ok_or_else(|| anyhow::anyhow!("no attachment '{}'", selector))?;
+
+        let mut path = expand_path(path)?;
+        if path.is_dir() {
+            path.push(Path::new(&found.name).file_name().unwrap_or_default());
+        }
+
+        let contents = crate::attachment::read(&self.vault_path, self.session()?, found)?;
+        crate::attachment::write_file(&path, &contents)?;
+        Ok(format!("Saved {} to {}", found.name, path.display()))
+    }
+
     /// Copy password to clipboard
     pub fn copy_password_to_clipboard(&mut self) -> Result<()> {
         if let Some(entry) = self.get_selected_entry() {
+            let label = match &entry.kind {
+                EntryKind::Card(_) => "Card number",
+                kind => kind.password_label().unwrap_or("Password"),
+            };
+            if entry.secret().is_empty() {
+                self.set_status(format!("'{}' has no {}", entry.name, label.to_lowercase()));
+                return Ok(());
+            }
             let mut clipboard = arboard::Clipboard::new()?;
-            clipboard.set_text(&entry.password)?;
-            self.set_status(format!("Password copied for '{}'", entry.name));
+            clipboard.set_text(entry.secret())?;
+            self.set_status(format!("{} copied for '{}'", label, entry.name));
+            self.record_selected_use();
             Ok(())
         } else {
             self.set_status("No entry selected".to_string());
@@ -520,12 +1730,92 @@ impl App {
         }
     }
 
+    /// Copy the current one-time code to clipboard
+    ///
+    /// For HOTP the stored counter is advanced, so each code is used once.
+    pub fn copy_otp_to_clipboard(&mut self) -> Result<()> {
+        let Some(entry) = self.get_selected_entry() else {
+            self.set_status("No entry selected".to_string());
+            return Ok(());
+        };
+        let Some(field) = otp::otp_field(entry) else {
+            let status = format!("'{}' has no one-time password field", entry.name);
+            self.set_status(status);
+            return Ok(());
+        };
+
+        // A bad secret is reported, not raised: that would end the session
+        let parsed = Otp::parse(&field.value).and_then(|generator| {
+            let advanced = match generator.kind {
+                OtpKind::Hotp { .. } => Some(otp::advance_counter(&field.value)?),
+                OtpKind::Totp { .. } => None,
+            };
+            Ok((generator, advanced))
+        });
+        let (generator, advanced) = match parsed {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                let status = format!("'{}': {}", entry.name, e);
+                self.set_status(status);
+                return Ok(());
+            }
+        };
+        let mut clipboard = arboard::Clipboard::new()?;
+        clipboard.set_text(generator.code())?;
+        let mut status = format!("One-time code copied for '{}'", entry.name);
+
+        if let (OtpKind::Hotp { counter }, Some(value)) = (generator.kind, advanced) {
+            let before = entry.clone();
+            let mut after = before.clone();
+            if let Some(field) = otp::otp_field_mut(&mut after) {
+                field.value = value;
+            }
+            after.touch();
+            status.push_str(&format!(" (counter {} used)", counter));
+            self.apply_change(Change::Edit {
+                before: Box::new(before),
+                after: Box::new(after),
+            });
+        }
+        self.record_selected_use();
+        self.set_status(status);
+        Ok(())
+    }
+
+    /// Copy the `n`th previous password (1 = most recent) to clipboard
+    pub fn copy_previous_password_to_clipboard(&mut self, n: usize) -> Result<()> {
+        let Some(entry) = self.get_selected_entry() else {
+            self.set_status("No entry selected".to_string());
+            return Ok(());
+        };
+
+        match n.checked_sub(1).and_then(|i| entry.password_history.get(i)) {
+            Some(previous) => {
+                let mut clipboard = arboard::Clipboard::new()?;
+                clipboard.set_text(&previous.password)?;
+                let status = format!(
+                    "Previous password #{} copied for '{}' (replaced {})",
+                    n,
+                    entry.name,
+                    previous.replaced.format("%Y-%m-%d")
+                );
+                self.set_status(status);
+            }
+            None => {
+                let status = format!("'{}' has no previous password #{}", entry.name, n);
+                self.set_status(status);
+            }
+        }
+        Ok(())
+    }
+
     /// Copy username to clipboard
     pub fn copy_username_to_clipboard(&mut self) -> Result<()> {
         if let Some(entry) = self.get_selected_entry() {
             let mut clipboard = arboard::Clipboard::new()?;
             clipboard.set_text(&entry.username)?;
             self.set_status(format!("Username copied for '{}'", entry.name));
+            self.record_selected_use();
             Ok(())
         } else {
             self.set_status("No entry selected".to_string());
@@ -551,11 +1841,23 @@ impl App {
     pub fn enter_edit_mode(&mut self) {
         let form_data = self.get_selected_entry().map(|entry| FormData {
             name: entry.name.clone(),
+            kind: entry.kind.clone(),
             username: entry.username.clone(),
             password: entry.password.clone(),
-            url: entry.url.clone().unwrap_or_default(),
+            url: entry
+                .urls
+                .iter()
+                .map(|u| u.to_string())
+                .collect::<Vec<_>>()
+                .join(" "),
             notes: entry.notes.clone().unwrap_or_default(),
             tags: entry.tags.join(", "),
+            scopes: match &entry.kind {
+                EntryKind::ApiToken(token) => token.scopes.join(", "),
+                _ => String::new(),
+            },
+            fields: entry.fields.clone(),
+            policy: PasswordPolicy::default(),
             editing_id: Some(entry.id),
         });
 
@@ -577,6 +1879,10 @@ impl App {
             FormField::Url => &self.form_data.url,
             FormField::Notes => &self.form_data.notes,
             FormField::Tags => &self.form_data.tags,
+            FormField::Scopes => &self.form_data.scopes,
+            FormField::CustomLabel(i) => &self.form_data.fields[i].label,
+            FormField::CustomValue(i) => &self.form_data.fields[i].value,
+            field => self.form_data.detail(field).map_or("", String::as_str),
         }
     }
 
@@ -589,6 +1895,64 @@ impl App {
             FormField::Url => &mut self.form_data.url,
             FormField::Notes => &mut self.form_data.notes,
             FormField::Tags => &mut self.form_data.tags,
+            FormField::Scopes => &mut self.form_data.scopes,
+            FormField::CustomLabel(i) => &mut self.form_data.fields[i].label,
+            FormField::CustomValue(i) => &mut self.form_data.fields[i].value,
+            field => self
+                .form_data
+                .detail_mut(field)
+                .expect("focused field belongs to the form's kind"),
+        }
+    }
+
+    /// Focus the next form field
+    pub fn focus_next_field(&mut self) {
+        let custom = self.form_data.fields.len();
+        self.focused_field = self.focused_field.next(&self.form_data.kind, custom);
+    }
+
+    /// Focus the previous form field
+    pub fn focus_prev_field(&mut self) {
+        let custom = self.form_data.fields.len();
+        self.focused_field = self.focused_field.prev(&self.form_data.kind, custom);
+    }
+
+    /// Append an empty custom field to the form and focus its label
+    pub fn add_custom_field(&mut self) {
+        self.form_data.fields.push(CustomField::default());
+        self.focused_field = FormField::CustomLabel(self.form_data.fields.len() - 1);
+    }
+
+    /// Remove the focused custom field from the form
+    pub fn remove_custom_field(&mut self) {
+        let Some(i) = self.focused_field.custom_index() else {
+            self.set_status("Focus a custom field to remove it".to_string());
+            return;
+        };
+        self.form_data.fields.remove(i);
+        self.focused_field = match self.form_data.fields.len() {
+            0 => FormField::Tags,
+            len => FormField::CustomLabel(i.min(len - 1)),
+        };
+    }
+
+    /// Switch the focused custom field to the next kind
+    pub fn cycle_custom_field_kind(&mut self) {
+        if let Some(i) = self.focused_field.custom_index() {
+            let field = &mut self.form_data.fields[i];
+            field.kind = field.kind.next();
+        }
+    }
+
+    /// Switch the form to the next entry kind
+    ///
+    /// Values specific to the previous kind are dropped; the common ones stay.
+    pub fn cycle_entry_kind(&mut self) {
+        self.form_data.kind = self.form_data.kind.next();
+        if !FormField::layout(&self.form_data.kind).contains(&self.focused_field)
+            && self.focused_field.custom_index().is_none()
+        {
+            self.focused_field = FormField::Name;
         }
     }
 
@@ -600,49 +1964,86 @@ impl App {
             return;
         }
 
-        // Parse tags
-        let tags: Vec<String> = self
+        let tags = split_list(&self.form_data.tags);
+
+        // Values the kind has no field for are kept as they are, so switching
+        // the kind back and forth loses nothing
+        let layout = FormField::layout(&self.form_data.kind);
+        let value = |field: FormField| {
+            layout
+                .contains(&field)
+                .then(|| self.get_field_value(field).to_string())
+        };
+        let username = value(FormField::Username);
+        let password = value(FormField::Password);
+        let urls = value(FormField::Url).map(|text| EntryUrl::parse_list(&text));
+        if let Some(e) = urls
+            .iter()
+            .flatten()
+            .find_map(|url| urlmatch::validate(url).err())
+        {
+            self.set_status(e.to_string());
+            return;
+        }
+        let mut kind = self.form_data.kind.clone();
+        if let EntryKind::ApiToken(token) = &mut kind {
+            token.scopes = split_list(&self.form_data.scopes);
+        }
+
+        // Custom fields without label and value are dropped
+        let fields: Vec<CustomField> = self
             .form_data
-            .tags
-            .split(',')
-            .map(|s| s.trim().to_string())
-            .filter(|s| !s.is_empty())
+            .fields
+            .iter()
+            .filter(|f| !f.label.trim().is_empty() || !f.value.is_empty())
+            .cloned()
             .collect();
+        if let Some((field, e)) = fields
+            .iter()
+            .filter(|f| f.kind == FieldKind::Totp && !f.value.is_empty())
+            .find_map(|f| Some((f, Otp::parse(&f.value).err()?)))
+        {
+            self.set_status(format!("{}: {}", field.label, e));
+            return;
+        }
 
         if let Some(id) = self.form_data.editing_id {
             // Update existing entry
-            if let Some(entry) = self.vault.get_entry_mut(&id) {
+            if let Some(before) = self.vault.get_entry(&id).cloned() {
+                let mut entry = before.clone();
                 entry.name = self.form_data.name.clone();
-                entry.username = self.form_data.username.clone();
-                entry.password = self.form_data.password.clone();
-                entry.url = if self.form_data.url.is_empty() {
-                    None
-                } else {
-                    Some(self.form_data.url.clone())
-                };
+                entry.kind = kind;
+                if let Some(username) = username {
+                    entry.username = username;
+                }
+                if let Some(password) = password {
+                    entry.set_password(password);
+                }
+                if let Some(urls) = urls {
+                    entry.urls = urls;
+                }
                 entry.notes = if self.form_data.notes.is_empty() {
                     None
                 } else {
                     Some(self.form_data.notes.clone())
                 };
                 entry.tags = tags;
+                entry.fields = fields;
                 entry.touch();
 
-                let entry_name = entry.name.clone();
-                self.dirty = true;
-                self.set_status(format!("Updated entry '{}'", entry_name));
+                self.set_status(format!("Updated entry '{}'", entry.name));
+                self.apply_change(Change::Edit {
+                    before: Box::new(before),
+                    after: Box::new(entry),
+                });
             }
         } else {
             // Create new entry
-            let entry = Entry::new(
+            let mut entry = Entry::new(
                 self.form_data.name.clone(),
-                self.form_data.username.clone(),
-                self.form_data.password.clone(),
-                if self.form_data.url.is_empty() {
-                    None
-                } else {
-                    Some(self.form_data.url.clone())
-                },
+                username.unwrap_or_default(),
+                password.unwrap_or_default(),
+                None,
                 if self.form_data.notes.is_empty() {
                     None
                 } else {
@@ -650,6 +2051,11 @@ impl App {
                 },
                 tags,
             );
+            entry.kind = kind;
+            entry.urls = urls.unwrap_or_default();
+            entry.fields = fields;
+            // New entries go into the folder being browsed
+            entry.folder = self.folder_filter;
 
             self.set_status(format!("Created entry '{}'", entry.name));
             self.add_entry(entry);
@@ -0,0 +1,391 @@
+//! Encrypted file attachments
+//!
+//! Attachment contents are kept out of the vault file, one encrypted blob per
+//! attachment in `<vault file>.attachments/`, so they don't count against the
+//! vault size limit. Entries only hold an [`Attachment`] reference with the
+//! blob's ID and the SHA-256 of its contents. Blobs are encrypted with the
+//! vault's data key, and re-encrypted along with the vault when that changes.
+//!
+//! Blobs nothing refers to any more, e.g. of purged entries or removed
+//! attachments, are deleted after a save. Blobs that a backup still refers to
+//! are kept until the backup is pruned, so restoring it brings the
+//! attachments back.
+//!
+//! Blob layout:
+//!
+//! ```text
+//! "PMATTACH" | algorithm length: u8 | algorithm | nonce | ciphertext
+//! ```
+//!
+//! Everything before the ciphertext plus the attachment ID is authenticated,
+//! so blobs cannot be swapped between attachments.
+
+use crate::backup;
+use crate::crypto::{CipherAlgorithm, CipherParams, Session};
+use crate::model::Attachment;
+use crate::storage::{write_atomic, Rekeyed, VaultFile};
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Magic bytes at the start of an attachment blob
+const MAGIC: &[u8; 8] = b"PMATTACH";
+
+/// Largest attachment accepted (64 MiB)
+pub const MAX_ATTACHMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Directory holding the attachment blobs of a vault
+pub fn attachment_dir(vault_path: &Path) -> PathBuf {
+    let mut name = vault_path
+        .file_name()
+        .map(|n| n.to_os_string())
+        .unwrap_or_else(|| "vault".into());
+    name.push(".attachments");
+    vault_path.with_file_name(name)
+}
+
+/// Path of one attachment blob
+fn blob_path(vault_path: &Path, id: &Uuid) -> PathBuf {
+    attachment_dir(vault_path).join(format!("{}.enc", id))
+}
+
+/// Hex encoded SHA-256 of attachment contents
+fn content_hash(contents: &[u8]) -> String {
+    Sha256::digest(contents)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Associated data of a blob: its prefix and the attachment ID
+fn associated_data(prefix: &[u8], id: &Uuid) -> Vec<u8> {
+    let mut aad = prefix.to_vec();
+    aad.extend_from_slice(id.as_bytes());
+    aad
+}
+
+/// Read a file to attach, refusing oversized ones
+pub fn read_source(path: &Path) -> Result<Vec<u8>> {
+    let metadata =
+        fs::metadata(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    if metadata.len() > MAX_ATTACHMENT_SIZE {
+        return Err(anyhow!(
+            "Attachment too large: {} bytes (max {})",
+            metadata.len(),
+            MAX_ATTACHMENT_SIZE
+        ));
+    }
+    Ok(fs::read(path)?)
+}
+
+/// Encrypt `contents` into a new blob and return the reference to store
+pub fn add(
+    vault_path: &Path,
+    session: &Session,
+    name: &str,
+    contents: &[u8],
+) -> Result<Attachment> {
+    if contents.len() as u64 > MAX_ATTACHMENT_SIZE {
+        return Err(anyhow!(
+            "Attachment too large: {} bytes (max {})",
+            contents.len(),
+            MAX_ATTACHMENT_SIZE
+        ));
+    }
+
+    let attachment = Attachment {
+        id: Uuid::new_v4(),
+        name: name.to_string(),
+        size: contents.len() as u64,
+        sha256: content_hash(contents),
+        added: Utc::now(),
+    };
+
+    fs::create_dir_all(attachment_dir(vault_path))?;
+    write_atomic(
+        &blob_path(vault_path, &attachment.id),
+        &seal(session, &attachment.id, contents)?,
+    )?;
+    Ok(attachment)
+}
+
+/// Decrypt an attachment, checking it against its reference
+pub fn read(vault_path: &Path, session: &Session, attachment: &Attachment) -> Result<Vec<u8>> {
+    let path = blob_path(vault_path, &attachment.id);
+    let blob = fs::read(&path)
+        .map_err(|e| anyhow!("Failed to read attachment {}: {}", path.display(), e))?;
+    let corrupted = || anyhow!("Attachment '{}' is corrupted", attachment.name);
+
+    let contents = unseal(session, &attachment.id, &blob).map_err(|_| corrupted())?;
+    if contents.len() as u64 != attachment.size || content_hash(&contents) != attachment.sha256 {
+        return Err(corrupted());
+    }
+    Ok(contents)
+}
+
+/// Encrypt the contents of attachment `id` into a blob
+fn seal(session: &Session, id: &Uuid, contents: &[u8]) -> Result<Vec<u8>> {
+    let cipher = CipherParams::with_algorithm(session.cipher())?;
+    let mut blob = MAGIC.to_vec();
+    blob.push(cipher.algorithm.len() as u8);
+    blob.extend_from_slice(cipher.algorithm.as_bytes());
+    blob.extend_from_slice(&cipher.nonce);
+    let ciphertext = session
+        .key()
+        .encrypt(contents, &cipher, &associated_data(&blob, id))?;
+    blob.extend(ciphertext);
+    Ok(blob)
+}
+
+/// Decrypt the blob of attachment `id`
+fn unseal(session: &Session, id: &Uuid, blob: &[u8]) -> Result<Vec<u8>> {
+    let corrupted = || anyhow!("Attachment blob {} is corrupted", id);
+
+    let rest = blob.strip_prefix(MAGIC).ok_or_else(corrupted)?;
+    let (&algorithm_len, rest) = rest.split_first().ok_or_else(corrupted)?;
+    let algorithm = rest.get(..algorithm_len as usize).ok_or_else(corrupted)?;
+    let algorithm =
+        CipherAlgorithm::parse(std::str::from_utf8(algorithm).map_err(|_| corrupted())?)?;
+
+    let prefix_len = MAGIC.len() + 1 + algorithm_len as usize + algorithm.nonce_size();
+    let prefix = blob.get(..prefix_len).ok_or_else(corrupted)?;
+    let cipher = CipherParams {
+        algorithm: algorithm.as_str().to_string(),
+        nonce: prefix[prefix_len - algorithm.nonce_size()..].to_vec(),
+    };
+
+    session
+        .key()
+        .decrypt(&blob[prefix_len..], &cipher, &associated_data(prefix, id))
+}
+
+/// Stage every blob re-encrypted from `old`'s data key to `session`'s
+///
+/// Blobs `old` can't decrypt are staged for deletion.
+pub(crate) fn rekey(
+    vault_path: &Path,
+    old: &Session,
+    session: &Session,
+    rekeyed: &mut Rekeyed,
+) -> Result<()> {
+    for id in blob_ids(vault_path)? {
+        let path = blob_path(vault_path, &id);
+        match unseal(old, &id, &fs::read(&path)?) {
+            Ok(contents) => rekeyed.stage(&path, &seal(session, &id, &contents)?)?,
+            Err(_) => rekeyed.remove(&path),
+        }
+    }
+    Ok(())
+}
+
+/// Delete an attachment's blob (missing blobs are ignored)
+pub fn remove(vault_path: &Path, attachment: &Attachment) -> Result<()> {
+    match fs::remove_file(blob_path(vault_path, &attachment.id)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Delete blobs whose ID is neither in `referenced` nor used by a backup
+///
+/// Backups are decrypted with the session's data key; if one can't be read,
+/// nothing is deleted. Returns the number of deleted blobs. Files that aren't
+/// blobs are left alone.
+pub fn collect_garbage(
+    vault_path: &Path,
+    session: &Session,
+    referenced: &HashSet<Uuid>,
+) -> Result<usize> {
+    let mut unused: HashSet<Uuid> = blob_ids(vault_path)?
+        .into_iter()
+        .filter(|id| !referenced.contains(id))
+        .collect();
+    if unused.is_empty() {
+        return Ok(0);
+    }
+
+    // Restoring a backup must not leave its attachments dangling
+    for backup in backup::list(vault_path)? {
+        let vault = VaultFile::load_with_session(&backup.path, session)
+            .map_err(|e| anyhow!("Could not read backup {}: {}", backup.path.display(), e))?;
+        for id in vault.attachment_ids() {
+            unused.remove(&id);
+        }
+    }
+
+    for id in &unused {
+        fs::remove_file(blob_path(vault_path, id))?;
+    }
+    Ok(unused.len())
+}
+
+/// IDs of the blobs on disk
+fn blob_ids(vault_path: &Path) -> Result<Vec<Uuid>> {
+    let dir = attachment_dir(vault_path);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut ids = Vec::new();
+    for dir_entry in fs::read_dir(&dir)? {
+        let path = dir_entry?.path();
+        if path.extension().is_some_and(|ext| ext == "enc") {
+            if let Some(id) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| Uuid::parse_str(stem).ok())
+            {
+                ids.push(id);
+            }
+        }
+    }
+    Ok(ids)
+}
+
+/// Write decrypted contents to a new file readable only by the owner
+///
+/// Refuses to overwrite an existing file.
+pub fn write_file(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options
+        .open(path)
+        .map_err(|e| anyhow!("Failed to create {}: {}", path.display(), e))?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backup::BackupPolicy;
+    use crate::crypto::KdfCost;
+    use crate::model::{Entry, Vault};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_add_read_remove() {
+        let temp_dir = TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("vault.enc");
+        let session = Session::new("master_password", None, KdfCost::MIN).unwrap();
+
+        let attachment = add(&vault_path, &session, "id_ed25519", b"private key").unwrap();
+        assert_eq!(attachment.size, 11);
+        let blob = fs::read(blob_path(&vault_path, &attachment.id)).unwrap();
+        assert!(blob.starts_with(MAGIC));
+        assert!(!blob.windows(11).any(|w| w == b"private key"));
+
+        assert_eq!(
+            read(&vault_path, &session, &attachment).unwrap(),
+            b"private key"
+        );
+
+        // Another key, or a blob swapped in from another attachment, fails
+        let other = Session::new("master_password", None, KdfCost::MIN).unwrap();
+        assert!(read(&vault_path, &other, &attachment).is_err());
+        let second = add(&vault_path, &session, "license.txt", b"private key").unwrap();
+        fs::copy(
+            blob_path(&vault_path, &attachment.id),
+            blob_path(&vault_path, &second.id),
+        )
+        .unwrap();
+        assert!(read(&vault_path, &session, &second).is_err());
+
+        remove(&vault_path, &attachment).unwrap();
+        assert!(read(&vault_path, &session, &attachment).is_err());
+        remove(&vault_path, &attachment).unwrap();
+    }
+
+    #[test]
+    fn test_collect_garbage() {
+        let temp_dir = TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("vault.enc");
+        let session = Session::new("master_password", None, KdfCost::MIN).unwrap();
+
+        let kept = add(&vault_path, &session, "kept", b"kept").unwrap();
+        let dropped = add(&vault_path, &session, "dropped", b"dropped").unwrap();
+        let other = attachment_dir(&vault_path).join("notes.txt");
+        fs::write(&other, "not a blob").unwrap();
+
+        let referenced = HashSet::from([kept.id]);
+        assert_eq!(
+            collect_garbage(&vault_path, &session, &referenced).unwrap(),
+            1
+        );
+        assert!(read(&vault_path, &session, &kept).is_ok());
+        assert!(read(&vault_path, &session, &dropped).is_err());
+        assert!(other.exists());
+    }
+
+    #[test]
+    fn test_backups_keep_blobs() {
+        let temp_dir = TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("vault.enc");
+        let session = Session::new("master_password", None, KdfCost::MIN).unwrap();
+        let policy = BackupPolicy::default();
+
+        let mut vault = Vault::new();
+        let mut entry = Entry::new(
+            "Server".to_string(),
+            "root".to_string(),
+            "pw".to_string(),
+            None,
+            None,
+            vec![],
+        );
+        let attachment = add(&vault_path, &session, "id_ed25519", b"private key").unwrap();
+        entry.attachments.push(attachment.clone());
+        vault.add_entry(entry);
+        VaultFile::save(&vault_path, &vault, &session, &policy).unwrap();
+
+        // Removed from the vault, but the backup of the previous save has it
+        vault.entries[0].attachments.clear();
+        VaultFile::save(&vault_path, &vault, &session, &policy).unwrap();
+        let none = HashSet::new();
+        assert_eq!(collect_garbage(&vault_path, &session, &none).unwrap(), 0);
+
+        let backups = backup::list(&vault_path).unwrap();
+        backup::restore(&vault_path, &backups[0], "master_password", None, &policy).unwrap();
+        let restored = VaultFile::load(&vault_path, "master_password", None).unwrap();
+        let found = &restored.entries[0].attachments[0];
+        assert_eq!(read(&vault_path, &session, found).unwrap(), b"private key");
+
+        // An unreadable backup keeps everything
+        let other = Session::new("other", None, KdfCost::MIN).unwrap();
+        assert!(collect_garbage(&vault_path, &other, &none).is_err());
+
+        // Gone once no backup refers to it any more
+        fs::remove_dir_all(backup::backup_dir(&vault_path)).unwrap();
+        assert_eq!(collect_garbage(&vault_path, &session, &none).unwrap(), 1);
+        assert!(read(&vault_path, &session, found).is_err());
+    }
+
+    #[test]
+    fn test_write_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("saved");
+
+        write_file(&path, b"contents").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"contents");
+        assert!(write_file(&path, b"again").is_err());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+}
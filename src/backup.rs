@@ -0,0 +1,323 @@
+//! Rolling backups of the encrypted vault file
+//!
+//! Before every save the current `vault.enc` is copied, still encrypted, into
+//! a `vault.enc.backups/` directory next to it. Generations are named after
+//! their creation time and pruned by count and by age. When the vault switches
+//! to a new data key, e.g. on a password change, its backups are re-encrypted
+//! with it.
+
+use crate::crypto::{Keyfile, Session};
+use crate::model::Vault;
+use crate::storage::{self, Rekeyed, VaultFile};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Timestamp format used in backup file names
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.6fZ";
+
+/// Extension of backup files
+const BACKUP_EXTENSION: &str = "enc";
+
+/// How many backup generations to keep
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackupPolicy {
+    /// Maximum number of generations (0 disables backups)
+    pub keep: usize,
+    /// Delete generations older than this many days
+    pub max_age_days: Option<u32>,
+}
+
+impl Default for BackupPolicy {
+    fn default() -> Self {
+        Self {
+            keep: 10,
+            max_age_days: Some(90),
+        }
+    }
+}
+
+impl BackupPolicy {
+    /// Policy that never creates backups
+    pub fn disabled() -> Self {
+        Self {
+            keep: 0,
+            max_age_days: None,
+        }
+    }
+}
+
+/// A backup generation on disk
+#[derive(Debug, Clone)]
+pub struct Backup {
+    pub path: PathBuf,
+    pub created: DateTime<Utc>,
+    pub size: u64,
+}
+
+/// Directory holding the backups of a vault
+pub fn backup_dir(vault_path: &Path) -> PathBuf {
+    let mut name = vault_path
+        .file_name()
+        .map(|n| n.to_os_string())
+        .unwrap_or_else(|| "vault".into());
+    name.push(".backups");
+    vault_path.with_file_name(name)
+}
+
+/// Copy the current vault file into a new backup generation, then prune
+///
+/// Does nothing if the vault does not exist yet or backups are disabled.
+pub fn create(vault_path: &Path, policy: &BackupPolicy) -> Result<Option<PathBuf>> {
+    if policy.keep == 0 || !vault_path.exists() {
+        return Ok(None);
+    }
+
+    let dir = backup_dir(vault_path);
+    fs::create_dir_all(&dir)?;
+
+    let name = format!(
+        "{}.{}",
+        Utc::now().format(TIMESTAMP_FORMAT),
+        BACKUP_EXTENSION
+    );
+    let backup_path = dir.join(name);
+    if backup_path.exists() {
+        return Err(anyhow!("Backup already exists: {}", backup_path.display()));
+    }
+
+    // Plain copy: the ciphertext is kept as-is, no re-encryption
+    fs::copy(vault_path, &backup_path)?;
+
+    prune(vault_path, policy)?;
+
+    Ok(Some(backup_path))
+}
+
+/// List backup generations, newest first
+pub fn list(vault_path: &Path) -> Result<Vec<Backup>> {
+    let dir = backup_dir(vault_path);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut backups = Vec::new();
+    for dir_entry in fs::read_dir(&dir)? {
+        let dir_entry = dir_entry?;
+        let path = dir_entry.path();
+
+        // Skip anything that isn't one of our generations
+        let created = match parse_backup_name(&path) {
+            Some(created) => created,
+            None => continue,
+        };
+
+        backups.push(Backup {
+            size: dir_entry.metadata()?.len(),
+            path,
+            created,
+        });
+    }
+
+    backups.sort_by_key(|backup| std::cmp::Reverse(backup.created));
+    Ok(backups)
+}
+
+/// Delete generations beyond the policy's count or age limit
+///
+/// Returns the number of deleted generations. An age limit reaching back
+/// before the earliest representable date keeps every generation.
+pub fn prune(vault_path: &Path, policy: &BackupPolicy) -> Result<usize> {
+    let cutoff = policy
+        .max_age_days
+        .and_then(|days| Utc::now().checked_sub_signed(chrono::Duration::days(i64::from(days))));
+
+    let mut removed = 0;
+    for (i, backup) in list(vault_path)?.into_iter().enumerate() {
+        let too_many = i >= policy.keep;
+        let too_old = cutoff.is_some_and(|cutoff| backup.created < cutoff);
+
+        if too_many || too_old {
+            fs::remove_file(&backup.path)?;
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Promote a backup generation to be the current vault
+///
+/// The backup is decrypted first, so a wrong password or a damaged backup
+/// never replaces the vault. The vault being replaced is itself backed up.
+pub fn restore(
+    vault_path: &Path,
+    backup: &Backup,
+    password: &str,
+    keyfile: Option<&Keyfile>,
+    policy: &BackupPolicy,
+) -> Result<Vault> {
+    let vault = VaultFile::load(&backup.path, password, keyfile)
+        .map_err(|e| anyhow!("Could not open backup {}: {}", backup.path.display(), e))?;
+
+    // Read before taking a new backup, which may prune this one
+    let contents = fs::read(&backup.path)?;
+    create(vault_path, policy)?;
+    VaultFile::ensure_dir(vault_path)?;
+    storage::write_atomic(vault_path, &contents)?;
+
+    Ok(vault)
+}
+
+/// Stage every backup re-encrypted from `old`'s data key to `session`'s
+///
+/// Backups `old` can't decrypt are staged for deletion: they would still
+/// open with secrets the vault no longer uses.
+pub(crate) fn rekey(
+    vault_path: &Path,
+    old: &Session,
+    session: &Session,
+    rekeyed: &mut Rekeyed,
+) -> Result<()> {
+    for backup in list(vault_path)? {
+        match VaultFile::reencrypt(&fs::read(&backup.path)?, old, session) {
+            Ok(contents) => rekeyed.stage(&backup.path, &contents)?,
+            Err(_) => rekeyed.remove(&backup.path),
+        }
+    }
+    Ok(())
+}
+
+/// Parse the creation time from a backup file name
+fn parse_backup_name(path: &Path) -> Option<DateTime<Utc>> {
+    if path.extension()? != BACKUP_EXTENSION {
+        return None;
+    }
+    let stem = path.file_stem()?.to_str()?;
+    NaiveDateTime::parse_from_str(stem, TIMESTAMP_FORMAT)
+        .ok()
+        .map(|naive| naive.and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write_backup(vault_path: &Path, created: DateTime<Utc>) {
+        let dir = backup_dir(vault_path);
+        fs::create_dir_all(&dir).unwrap();
+        let name = format!("{}.{}", created.format(TIMESTAMP_FORMAT), BACKUP_EXTENSION);
+        fs::write(dir.join(name), b"old").unwrap();
+    }
+
+    #[test]
+    fn test_create_copies_ciphertext() {
+        let temp_dir = TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("vault.enc");
+
+        // Nothing to back up yet
+        assert!(create(&vault_path, &BackupPolicy::default())
+            .unwrap()
+            .is_none());
+
+        fs::write(&vault_path, b"ciphertext").unwrap();
+        let backup_path = create(&vault_path, &BackupPolicy::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(fs::read(&backup_path).unwrap(), b"ciphertext");
+        assert!(backup_path.starts_with(temp_dir.path().join("vault.enc.backups")));
+
+        let backups = list(&vault_path).unwrap();
+        assert_eq!(backups.len(), 1);
+        assert_eq!(backups[0].size, 10);
+    }
+
+    #[test]
+    fn test_disabled_policy() {
+        let temp_dir = TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("vault.enc");
+        fs::write(&vault_path, b"ciphertext").unwrap();
+
+        assert!(create(&vault_path, &BackupPolicy::disabled())
+            .unwrap()
+            .is_none());
+        assert!(list(&vault_path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_restore() {
+        use crate::crypto::{KdfCost, Session};
+        use crate::model::Entry;
+
+        let temp_dir = TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("vault.enc");
+        let session = Session::new("password123", None, KdfCost::MIN).unwrap();
+        let policy = BackupPolicy::default();
+
+        let mut vault = Vault::new();
+        vault.add_entry(Entry::new(
+            "Kept".to_string(),
+            "user".to_string(),
+            "pass".to_string(),
+            None,
+            None,
+            vec![],
+        ));
+        VaultFile::save(&vault_path, &vault, &session, &policy).unwrap();
+        VaultFile::save(&vault_path, &Vault::new(), &session, &policy).unwrap();
+
+        let backups = list(&vault_path).unwrap();
+        assert_eq!(backups.len(), 1);
+
+        // Wrong password leaves the vault untouched
+        let before = fs::read(&vault_path).unwrap();
+        assert!(restore(&vault_path, &backups[0], "wrong", None, &policy).is_err());
+        assert_eq!(fs::read(&vault_path).unwrap(), before);
+
+        let restored = restore(&vault_path, &backups[0], "password123", None, &policy).unwrap();
+        assert_eq!(restored.entries.len(), 1);
+
+        let loaded = VaultFile::load(&vault_path, "password123", None).unwrap();
+        assert_eq!(loaded.entries[0].name, "Kept");
+        // The replaced (empty) vault became a backup of its own
+        assert_eq!(list(&vault_path).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_prune_by_count_and_age() {
+        let temp_dir = TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("vault.enc");
+
+        let now = Utc::now();
+        for hours in [1, 2, 3, 4] {
+            write_backup(&vault_path, now - chrono::Duration::hours(hours));
+        }
+        write_backup(&vault_path, now - chrono::Duration::days(400));
+        // Unrelated files are left alone
+        fs::write(backup_dir(&vault_path).join("notes.txt"), b"keep me").unwrap();
+
+        let policy = BackupPolicy {
+            keep: 3,
+            max_age_days: Some(30),
+        };
+        assert_eq!(prune(&vault_path, &policy).unwrap(), 2);
+
+        let backups = list(&vault_path).unwrap();
+        assert_eq!(backups.len(), 3);
+        // Newest first
+        assert!(backups[0].created > backups[1].created);
+        assert!(backups[2].created > now - chrono::Duration::hours(4));
+        assert!(backup_dir(&vault_path).join("notes.txt").exists());
+
+        // An age limit too large to subtract only limits the count
+        let policy = BackupPolicy {
+            keep: 2,
+            max_age_days: Some(u32::MAX),
+        };
+        assert_eq!(prune(&vault_path, &policy).unwrap(), 1);
+    }
+}
@@ -0,0 +1,239 @@
+//! User configuration
+//!
+//! Read from `~/.config/passmngr/config.json`. Every field is optional and a
+//! missing file means defaults, e.g.:
+//!
+//! ```json
+//! {
+//!   "backups": { "keep": 20, "max_age_days": 30 },
+//!   "trash": { "purge_after_days": 14 },
+//!   "vaults": { "work": "/home/me/work/vault.enc" }
+//! }
+//! ```
+
+use crate::backup::BackupPolicy;
+use crate::model::TrashPolicy;
+use crate::storage::{self, VaultFile};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// Expand a leading `~` to the home directory
+pub fn expand_path(path: &str) -> Result<PathBuf> {
+    if path.starts_with("~") {
+        let home = dirs::home_dir()
+            .ok_or_else(|| anyhow::anyhow!("Could not determine home directory"))?;
+        Ok(PathBuf::from(path.replacen(
+            "~",
+            &home.to_string_lossy(),
+            1,
+        )))
+    } else {
+        Ok(PathBuf::from(path))
+    }
+}
+
+/// Name of the vault at `VaultFile::default_path`
+pub const DEFAULT_VAULT: &str = "default";
+
+/// User configuration
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub backups: BackupPolicy,
+    pub trash: TrashPolicy,
+    /// Named vault profiles
+    pub vaults: BTreeMap<String, PathBuf>,
+}
+
+/// A vault selected by name or path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VaultTarget {
+    /// Profile name, or the file name for a vault given by path
+    pub name: String,
+    pub path: PathBuf,
+}
+
+impl Config {
+    /// Get the configuration directory
+    pub fn dir() -> Result<PathBuf> {
+        let config_dir =
+            dirs::config_dir().ok_or_else(|| anyhow!("Could not determine config directory"))?;
+        Ok(config_dir.join("passmngr"))
+    }
+
+    /// Get the configuration file path
+    pub fn path() -> Result<PathBuf> {
+        Ok(Self::dir()?.join("config.json"))
+    }
+
+    /// Load the configuration, falling back to defaults if there is none
+    pub fn load() -> Result<Self> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents = fs::read(&path)?;
+        serde_json::from_slice(&contents)
+            .map_err(|e| anyhow!("Invalid config file {}: {}", path.display(), e))
+    }
+
+    /// Write the configuration back to disk
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+        VaultFile::ensure_dir(&path)?;
+        let mut contents = serde_json::to_vec_pretty(self)?;
+        contents.push(b'\n');
+        storage::write_atomic(&path, &contents)
+    }
+
+    /// Resolve `--vault` / `PASSMNGR_VAULT`: a profile name, `default`, or a
+    /// path (anything containing a `/` or ending in `.enc`)
+    pub fn resolve_vault(&self, selector: Option<&str>) -> Result<VaultTarget> {
+        let selector = match selector.map(str::trim) {
+            None | Some("") | Some(DEFAULT_VAULT) => {
+                return Ok(VaultTarget {
+                    name: DEFAULT_VAULT.to_string(),
+                    path: VaultFile::default_path()?,
+                })
+            }
+            Some(selector) => selector,
+        };
+
+        if let Some(path) = self.vaults.get(selector) {
+            return Ok(VaultTarget {
+                name: selector.to_string(),
+                path: path.clone(),
+            });
+        }
+
+        if selector.contains(std::path::MAIN_SEPARATOR) || selector.ends_with(".enc") {
+            let path = expand_path(selector)?;
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_else(|| selector.to_string());
+            return Ok(VaultTarget { name, path });
+        }
+
+        Err(anyhow!(
+            "Unknown vault '{}' (see `passmngr vaults list`)",
+            selector
+        ))
+    }
+
+    /// Register a named vault; by default it lives next to the default vault
+    pub fn add_vault(&mut self, name: &str, path: Option<PathBuf>) -> Result<VaultTarget> {
+        validate_vault_name(name)?;
+        if self.vaults.contains_key(name) {
+            return Err(anyhow!("Vault '{}' already exists", name));
+        }
+
+        let path = match path {
+            Some(path) => path,
+            None => Self::named_vault_path(name)?,
+        };
+        self.vaults.insert(name.to_string(), path.clone());
+
+        Ok(VaultTarget {
+            name: name.to_string(),
+            path,
+        })
+    }
+
+    /// Unregister a named vault, returning its path
+    pub fn remove_vault(&mut self, name: &str) -> Result<PathBuf> {
+        self.vaults
+            .remove(name)
+            .ok_or_else(|| anyhow!("Unknown vault '{}'", name))
+    }
+
+    /// Default location of a named vault
+    fn named_vault_path(name: &str) -> Result<PathBuf> {
+        let default = VaultFile::default_path()?;
+        let data_dir = default
+            .parent()
+            .ok_or_else(|| anyhow!("Could not determine data directory"))?;
+        Ok(data_dir.join("vaults").join(format!("{}.enc", name)))
+    }
+}
+
+/// Vault names are used as file names, so keep them simple
+fn validate_vault_name(name: &str) -> Result<()> {
+    let valid_chars = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    if name.is_empty() || !valid_chars || name == DEFAULT_VAULT {
+        return Err(anyhow!(
+            "Invalid vault name '{}': use letters, digits, '-' and '_' (and not '{}')",
+            name,
+            DEFAULT_VAULT
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_config() {
+        let config: Config = serde_json::from_str(r#"{ "backups": { "keep": 3 } }"#).unwrap();
+        assert_eq!(config.backups.keep, 3);
+        assert_eq!(
+            config.backups.max_age_days,
+            BackupPolicy::default().max_age_days
+        );
+
+        let config: Config = serde_json::from_str("{}").unwrap();
+        assert_eq!(config.backups.keep, BackupPolicy::default().keep);
+        assert_eq!(config.trash.purge_after_days, Some(30));
+
+        let config: Config =
+            serde_json::from_str(r#"{ "trash": { "purge_after_days": null } }"#).unwrap();
+        assert_eq!(config.trash.purge_after_days, None);
+    }
+
+    #[test]
+    fn test_resolve_vault() {
+        let mut config = Config::default();
+        config
+            .vaults
+            .insert("work".to_string(), PathBuf::from("/tmp/work.enc"));
+
+        let target = config.resolve_vault(None).unwrap();
+        assert_eq!(target.name, DEFAULT_VAULT);
+        assert_eq!(target.path, VaultFile::default_path().unwrap());
+        assert_eq!(config.resolve_vault(Some("default")).unwrap(), target);
+
+        let target = config.resolve_vault(Some("work")).unwrap();
+        assert_eq!(target.name, "work");
+        assert_eq!(target.path, PathBuf::from("/tmp/work.enc"));
+
+        let target = config.resolve_vault(Some("/srv/shared.enc")).unwrap();
+        assert_eq!(target.name, "shared.enc");
+        assert_eq!(target.path, PathBuf::from("/srv/shared.enc"));
+
+        assert!(config.resolve_vault(Some("personal")).is_err());
+    }
+
+    #[test]
+    fn test_add_and_remove_vault() {
+        let mut config = Config::default();
+
+        let target = config.add_vault("personal", None).unwrap();
+        assert!(target.path.ends_with("vaults/personal.enc"));
+        assert!(config.add_vault("personal", None).is_err());
+        for name in ["", "default", "a/b", "../x"] {
+            assert!(config.add_vault(name, None).is_err());
+        }
+
+        assert_eq!(config.remove_vault("personal").unwrap(), target.path);
+        assert!(config.remove_vault("personal").is_err());
+    }
+}
@@ -5,14 +5,31 @@
 //! **Argon2id** for key derivation:
 //! - Memory-hard: Resists GPU/ASIC attacks by requiring significant RAM
 //! - Hybrid mode: Combines data-dependent and data-independent memory access
-//! - Parameters: 3 iterations, 64 MiB memory, 4 threads
+//! - Default parameters: 3 iterations, 64 MiB memory, 4 threads
 //! - Time cost: ~100ms on modern hardware (intentional security vs usability trade-off)
+//! - Configurable per vault (see [`KdfCost`]); `passmngr kdf-benchmark` calibrates
+//!   parameters for a target unlock time on the current machine
+//! - Parameters below [`KdfCost::MIN`] are refused on load (downgrade protection)
+//! - Optional keyfile: its SHA-256 digest is passed to Argon2id as the secret
+//!   input, so the password alone cannot derive the key
 //!
-//! **ChaCha20-Poly1305** for authenticated encryption:
+//! **XChaCha20-Poly1305** for authenticated encryption (see [`CipherAlgorithm`]):
 //! - Stream cipher: Fast, constant-time (no timing attacks)
 //! - AEAD: Authentication prevents tampering
-//! - Well-analyzed: IETF RFC 8439 standard
-//! - No known practical attacks
+//! - 192-bit nonce: random nonces are safe however often the vault is saved
+//! - Well-analyzed: ChaCha20-Poly1305 is IETF RFC 8439; the extended nonce
+//!   variant is draft-irtf-cfrg-xchacha
+//! - AES-256-GCM-SIV (RFC 8452) is available as a nonce-misuse-resistant
+//!   alternative; ChaCha20-Poly1305 with a 96-bit nonce is still read for
+//!   older vaults
+//!
+//! **Key wrapping**: the vault payload is encrypted with a random data key.
+//! The password-derived key only wraps (encrypts) that data key, so other
+//! unlock methods such as recovery shares (see [`Session::setup_recovery`])
+//! can wrap the same data key. Changing the password or the recovery shares
+//! switches to a fresh data key (see [`Session::rekey`]), so an old copy of
+//! the vault and the secrets that opened it don't open anything written
+//! afterwards.
 //!
 //! ## Security Properties
 //!
@@ -35,19 +52,76 @@
 //! - Weak master passwords (user responsibility)
 //! - Side-channel attacks (not relevant for local CLI tool)
 
+use crate::shamir::{self, Share};
+use aes_gcm_siv::Aes256GcmSiv;
 use anyhow::{anyhow, Result};
 use argon2::{
     password_hash::{PasswordHasher, SaltString},
     Argon2, ParamsBuilder, Version,
 };
 use chacha20poly1305::{
-    aead::{Aead, KeyInit},
-    ChaCha20Poly1305, Nonce,
+    aead::{self, Aead, KeyInit, Payload},
+    ChaCha20Poly1305, XChaCha20Poly1305,
 };
 use rand::{rngs::OsRng, RngCore};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
 use zeroize::{Zeroize, ZeroizeOnDrop};
 
+/// Rules for generated passwords
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PasswordPolicy {
+    pub length: usize,
+    pub upper: bool,
+    pub lower: bool,
+    pub digits: bool,
+    pub symbols: bool,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            length: 20,
+            upper: true,
+            lower: true,
+            digits: true,
+            symbols: true,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// The policy a password follows: its length and the categories it uses
+    pub fn of(password: &str) -> Self {
+        if password.is_empty() {
+            return Self::default();
+        }
+        Self {
+            length: password.chars().count(),
+            upper: password.chars().any(|c| c.is_uppercase()),
+            lower: password.chars().any(|c| c.is_lowercase()),
+            digits: password.chars().any(|c| c.is_ascii_digit()),
+            symbols: password.chars().any(|c| !c.is_alphanumeric()),
+        }
+    }
+}
+
+impl std::fmt::Display for PasswordPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sets = [
+            (self.upper, "A-Z"),
+            (self.lower, "a-z"),
+            (self.digits, "0-9"),
+            (self.symbols, "symbols"),
+        ];
+        let used: Vec<_> = sets.iter().filter(|(on, _)| *on).map(|(_, s)| *s).collect();
+        write!(f, "{} chars, {}", self.length, used.join(" "))
+    }
+}
+
 /// Generate a cryptographically secure random password
 ///
 /// Uses a high-entropy character set:
@@ -58,65 +132,204 @@ use zeroize::{Zeroize, ZeroizeOnDrop};
 ///
 /// Guarantees at least one character from each category for high density.
 pub fn generate_secure_password(len: usize) -> String {
+    generate_password(&PasswordPolicy {
+        length: len,
+        ..PasswordPolicy::default()
+    })
+}
+
+/// Generate a random password following `policy`
+///
+/// Holds at least one character of every enabled category (when long
+/// enough). A policy with no category enabled uses all of them.
+pub fn generate_password(policy: &PasswordPolicy) -> String {
     const UPPER: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
     const LOWER: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
     const NUMBERS: &[u8] = b"0123456789";
     const SPECIAL: &[u8] = b"!@#$%^&*-_=+";
-    const ALL: &[u8] =
-        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789!@#$%^&*-_=+";
 
-    if len < 4 {
-        // Fallback for tiny lengths (not recommended but handled)
-        use rand::Rng;
-        return (0..len)
-            .map(|_| ALL[OsRng.gen_range(0..ALL.len())] as char)
-            .collect();
-    }
-
-    // Ensure at least one of each type
     use rand::seq::SliceRandom;
-    let mut password = Vec::with_capacity(len);
+    use rand::Rng;
     let mut rng = OsRng;
 
-    password.push(*UPPER.choose(&mut rng).unwrap());
-    password.push(*LOWER.choose(&mut rng).unwrap());
-    password.push(*NUMBERS.choose(&mut rng).unwrap());
-    password.push(*SPECIAL.choose(&mut rng).unwrap());
+    let mut sets: Vec<&[u8]> = [
+        (policy.upper, UPPER),
+        (policy.lower, LOWER),
+        (policy.digits, NUMBERS),
+        (policy.symbols, SPECIAL),
+    ]
+    .into_iter()
+    .filter_map(|(enabled, set)| enabled.then_some(set))
+    .collect();
+    if sets.is_empty() {
+        sets = vec![UPPER, LOWER, NUMBERS, SPECIAL];
+    }
+    let all = sets.concat();
+
+    // Ensure at least one of each type (not for tiny lengths)
+    let mut password = Vec::with_capacity(policy.length);
+    if policy.length >= sets.len() {
+        password.extend(sets.iter().map(|set| *set.choose(&mut rng).unwrap()));
+    }
 
     // Fill the rest
-    use rand::Rng;
-    for _ in 4..len {
-        password.push(ALL[rng.gen_range(0..ALL.len())]);
+    while password.len() < policy.length {
+        password.push(all[rng.gen_range(0..all.len())]);
     }
 
-    // Shuffle to remove pattern of first 4 chars
+    // Shuffle to remove pattern of the first characters
     password.shuffle(&mut rng);
 
     String::from_utf8(password).expect("Valid UTF-8")
 }
 
+/// Minimum accepted master password length
+pub const MIN_MASTER_PASSWORD_LEN: usize = 8;
+
+/// Check a new master password against the minimum requirements
+pub fn validate_master_password(password: &str) -> Result<()> {
+    if password.chars().count() < MIN_MASTER_PASSWORD_LEN {
+        return Err(anyhow!(
+            "Password must be at least {} characters long.",
+            MIN_MASTER_PASSWORD_LEN
+        ));
+    }
+    Ok(())
+}
+
 /// Size of encryption key in bytes (256 bits)
 const KEY_SIZE: usize = 32;
 
-/// Size of nonce in bytes (96 bits for ChaCha20-Poly1305)
-const NONCE_SIZE: usize = 12;
+/// Associated data of wrapped keys
+const KEY_WRAP_AAD: &[u8] = b"passmngr wrapped key";
 
 /// Size of salt in bytes (128 bits)
 const SALT_SIZE: usize = 16;
 
+/// Size of generated keyfiles in bytes
+const KEYFILE_SIZE: usize = 64;
+
+/// Maximum keyfile size read from disk (1 MiB)
+const MAX_KEYFILE_SIZE: u64 = 1024 * 1024;
+
+/// Largest memory cost `calibrate` will pick (1 GiB, in KiB units)
+const CALIBRATION_MAX_MEMORY: u32 = 1024 * 1024;
+
+/// Argon2id cost parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfCost {
+    /// Number of passes over memory
+    pub time_cost: u32,
+    /// Memory in KiB
+    pub memory_cost: u32,
+    /// Number of lanes
+    pub parallelism: u32,
+}
+
+impl KdfCost {
+    /// Weakest parameters a vault may use (OWASP minimum for Argon2id)
+    ///
+    /// Vault files below this are refused on load, so a crafted file cannot
+    /// downgrade the KDF to something cheap to brute-force.
+    pub const MIN: Self = Self {
+        time_cost: 2,
+        memory_cost: 19 * 1024, // 19 MiB
+        parallelism: 1,
+    };
+
+    /// Strongest parameters a vault may use
+    ///
+    /// Guards against crafted files that would exhaust memory or CPU on unlock.
+    pub const MAX: Self = Self {
+        time_cost: 100,
+        memory_cost: 4 * 1024 * 1024, // 4 GiB
+        parallelism: 64,
+    };
+
+    /// Check that every parameter lies within [`KdfCost::MIN`]..=[`KdfCost::MAX`]
+    pub fn validate(&self) -> Result<()> {
+        let checks = [
+            (
+                "time cost",
+                self.time_cost,
+                Self::MIN.time_cost,
+                Self::MAX.time_cost,
+            ),
+            (
+                "memory cost",
+                self.memory_cost,
+                Self::MIN.memory_cost,
+                Self::MAX.memory_cost,
+            ),
+            (
+                "parallelism",
+                self.parallelism,
+                Self::MIN.parallelism,
+                Self::MAX.parallelism,
+            ),
+        ];
+
+        for (name, value, min, max) in checks {
+            if value < min {
+                return Err(anyhow!(
+                    "KDF {} {} is below the minimum of {}",
+                    name,
+                    value,
+                    min
+                ));
+            }
+            if value > max {
+                return Err(anyhow!(
+                    "KDF {} {} exceeds the maximum of {}",
+                    name,
+                    value,
+                    max
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for KdfCost {
+    /// Recommended settings for interactive use
+    fn default() -> Self {
+        Self {
+            // Time cost: 3 iterations (minimum recommended for interactive use)
+            time_cost: 3,
+            // Memory cost: 64 MiB (balances security vs. usability)
+            // Higher values = more secure but slower unlock time
+            memory_cost: 65536, // 64 MiB (in KiB units)
+            // Parallelism: 4 threads (utilizes modern multi-core CPUs)
+            parallelism: 4,
+        }
+    }
+}
+
 /// KDF parameters stored with the vault
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct KdfParams {
     pub algorithm: String,
     pub salt: Vec<u8>,
     pub time_cost: u32,
     pub memory_cost: u32,
     pub parallelism: u32,
+    /// Whether a keyfile is mixed into the derivation
+    #[serde(default)]
+    pub keyfile: bool,
 }
 
 impl KdfParams {
     /// Create new KDF parameters with recommended settings
     pub fn new() -> Result<Self> {
+        Self::with_cost(KdfCost::default())
+    }
+
+    /// Create new KDF parameters with a fresh salt and the given cost
+    pub fn with_cost(cost: KdfCost) -> Result<Self> {
+        cost.validate()?;
+
         let mut salt = vec![0u8; SALT_SIZE];
         OsRng
             .try_fill_bytes(&mut salt)
@@ -125,15 +338,188 @@ impl KdfParams {
         Ok(Self {
             algorithm: "argon2id".to_string(),
             salt,
-            // Time cost: 3 iterations (minimum recommended for interactive use)
-            time_cost: 3,
-            // Memory cost: 64 MiB (balances security vs. usability)
-            // Higher values = more secure but slower unlock time
-            memory_cost: 65536, // 64 MiB (in KiB units)
-            // Parallelism: 4 threads (utilizes modern multi-core CPUs)
-            parallelism: 4,
+            time_cost: cost.time_cost,
+            memory_cost: cost.memory_cost,
+            parallelism: cost.parallelism,
+            keyfile: false,
         })
     }
+
+    /// Cost parameters, without the salt
+    pub fn cost(&self) -> KdfCost {
+        KdfCost {
+            time_cost: self.time_cost,
+            memory_cost: self.memory_cost,
+            parallelism: self.parallelism,
+        }
+    }
+
+    /// Reject parameters read from a vault file that are unsupported or too weak
+    pub fn validate(&self) -> Result<()> {
+        if self.algorithm != "argon2id" {
+            return Err(anyhow!("Unsupported KDF algorithm: {}", self.algorithm));
+        }
+        if self.salt.len() < SALT_SIZE {
+            return Err(anyhow!(
+                "KDF salt too short: {} bytes (min {})",
+                self.salt.len(),
+                SALT_SIZE
+            ));
+        }
+        self.cost().validate()
+    }
+}
+
+/// Time a single key derivation with the given cost
+pub fn benchmark_kdf(cost: KdfCost) -> Result<Duration> {
+    let params = KdfParams::with_cost(cost)?;
+    let start = Instant::now();
+    EncryptionKey::derive("passmngr-benchmark", None, &params)?;
+    Ok(start.elapsed())
+}
+
+/// Pick KDF parameters that take roughly `target` to derive on this machine
+///
+/// Starts from the default memory cost, trades memory away if even that is
+/// too slow, then spends any remaining budget on memory first (harder on GPUs)
+/// and finally on extra passes. Never goes below [`KdfCost::MIN`].
+pub fn calibrate_kdf(target: Duration, parallelism: u32) -> Result<KdfCost> {
+    let mut cost = KdfCost {
+        time_cost: KdfCost::MIN.time_cost,
+        memory_cost: KdfCost::default().memory_cost,
+        parallelism,
+    };
+    cost.validate()?;
+
+    let mut elapsed = benchmark_kdf(cost)?;
+
+    while elapsed > target && cost.memory_cost > KdfCost::MIN.memory_cost {
+        cost.memory_cost = (cost.memory_cost / 2).max(KdfCost::MIN.memory_cost);
+        elapsed = benchmark_kdf(cost)?;
+    }
+
+    while elapsed * 2 <= target && cost.memory_cost * 2 <= CALIBRATION_MAX_MEMORY {
+        cost.memory_cost *= 2;
+        elapsed = benchmark_kdf(cost)?;
+    }
+
+    let scale = target.as_secs_f64() / elapsed.as_secs_f64().max(f64::EPSILON);
+    if scale > 1.0 {
+        cost.time_cost = ((cost.time_cost as f64 * scale) as u32)
+            .clamp(KdfCost::MIN.time_cost, KdfCost::MAX.time_cost);
+    }
+
+    Ok(cost)
+}
+
+/// Keyfile used as a second unlock factor
+///
+/// Only the SHA-256 digest of the file is kept, so any file (including one
+/// made by `Keyfile::generate`) can serve as a keyfile.
+#[derive(Zeroize, ZeroizeOnDrop)]
+pub struct Keyfile {
+    digest: [u8; 32],
+}
+
+impl Keyfile {
+    /// Read a keyfile from disk
+    pub fn read(path: &Path) -> Result<Self> {
+        let metadata = fs::metadata(path)
+            .map_err(|e| anyhow!("Failed to read keyfile {}: {}", path.display(), e))?;
+        if metadata.len() > MAX_KEYFILE_SIZE {
+            return Err(anyhow!(
+                "Keyfile too large: {} bytes (max {})",
+                metadata.len(),
+                MAX_KEYFILE_SIZE
+            ));
+        }
+
+        let mut contents = fs::read(path)?;
+        if contents.is_empty() {
+            return Err(anyhow!("Keyfile is empty: {}", path.display()));
+        }
+        let keyfile = Self::from_bytes(&contents);
+        contents.zeroize();
+
+        Ok(keyfile)
+    }
+
+    /// Build a keyfile from raw contents
+    pub fn from_bytes(contents: &[u8]) -> Self {
+        Self {
+            digest: Sha256::digest(contents).into(),
+        }
+    }
+
+    /// Write a new random keyfile (refuses to overwrite an existing file)
+    pub fn generate(path: &Path) -> Result<()> {
+        let mut contents = [0u8; KEYFILE_SIZE];
+        OsRng
+            .try_fill_bytes(&mut contents)
+            .map_err(|e| anyhow!("Failed to generate keyfile: {}", e))?;
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let mut file = options
+            .open(path)
+            .map_err(|e| anyhow!("Failed to create keyfile {}: {}", path.display(), e))?;
+        std::io::Write::write_all(&mut file, &contents)?;
+        file.sync_all()?;
+        contents.zeroize();
+
+        Ok(())
+    }
+}
+
+/// Authenticated encryption algorithm of a vault
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CipherAlgorithm {
+    /// ChaCha20-Poly1305 with a 96-bit nonce (written by older versions)
+    ChaCha20Poly1305,
+    /// XChaCha20-Poly1305 with a 192-bit nonce
+    #[default]
+    XChaCha20Poly1305,
+    /// AES-256-GCM-SIV, tolerates nonce reuse
+    Aes256GcmSiv,
+}
+
+impl CipherAlgorithm {
+    pub const ALL: [Self; 3] = [
+        Self::ChaCha20Poly1305,
+        Self::XChaCha20Poly1305,
+        Self::Aes256GcmSiv,
+    ];
+
+    /// Name stored in the vault header
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ChaCha20Poly1305 => "chacha20poly1305",
+            Self::XChaCha20Poly1305 => "xchacha20poly1305",
+            Self::Aes256GcmSiv => "aes-256-gcm-siv",
+        }
+    }
+
+    /// Look up an algorithm by its stored name
+    pub fn parse(name: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.as_str() == name)
+            .ok_or_else(|| anyhow!("Unsupported cipher algorithm: {}", name))
+    }
+
+    /// Nonce size in bytes
+    pub fn nonce_size(&self) -> usize {
+        match self {
+            Self::ChaCha20Poly1305 | Self::Aes256GcmSiv => 12,
+            Self::XChaCha20Poly1305 => 24,
+        }
+    }
 }
 
 /// Cipher parameters stored with the vault
@@ -144,18 +530,32 @@ pub struct CipherParams {
 }
 
 impl CipherParams {
-    /// Create new cipher parameters with random nonce
+    /// Create new cipher parameters for the default algorithm with random nonce
     pub fn new() -> Result<Self> {
-        let mut nonce = vec![0u8; NONCE_SIZE];
+        Self::with_algorithm(CipherAlgorithm::default())
+    }
+
+    /// Create new cipher parameters with random nonce
+    pub fn with_algorithm(algorithm: CipherAlgorithm) -> Result<Self> {
+        let mut nonce = vec![0u8; algorithm.nonce_size()];
         OsRng
             .try_fill_bytes(&mut nonce)
             .map_err(|e| anyhow!("Failed to generate nonce: {}", e))?;
 
         Ok(Self {
-            algorithm: "chacha20poly1305".to_string(),
+            algorithm: algorithm.as_str().to_string(),
             nonce,
         })
     }
+
+    /// The algorithm, checked to be supported and to match the nonce
+    pub fn algorithm(&self) -> Result<CipherAlgorithm> {
+        let algorithm = CipherAlgorithm::parse(&self.algorithm)?;
+        if self.nonce.len() != algorithm.nonce_size() {
+            return Err(anyhow!("Invalid nonce size"));
+        }
+        Ok(algorithm)
+    }
 }
 
 /// Encryption key derived from master password
@@ -165,8 +565,14 @@ pub struct EncryptionKey {
 }
 
 impl EncryptionKey {
-    /// Derive encryption key from password using Argon2id
-    pub fn derive(password: &str, params: &KdfParams) -> Result<Self> {
+    /// Derive encryption key from password (and keyfile, if the vault uses one) using Argon2id
+    pub fn derive(password: &str, keyfile: Option<&Keyfile>, params: &KdfParams) -> Result<Self> {
+        match (params.keyfile, keyfile) {
+            (true, None) => return Err(anyhow!("This vault requires a keyfile")),
+            (false, Some(_)) => return Err(anyhow!("This vault does not use a keyfile")),
+            _ => {}
+        }
+
         // Build Argon2 parameters
         let argon2_params = ParamsBuilder::new()
             .m_cost(params.memory_cost)
@@ -176,7 +582,16 @@ impl EncryptionKey {
             .build()
             .map_err(|e| anyhow!("Failed to build Argon2 parameters: {}", e))?;
 
-        let argon2 = Argon2::new(argon2::Algorithm::Argon2id, Version::V0x13, argon2_params);
+        let argon2 = match keyfile {
+            Some(keyfile) => Argon2::new_with_secret(
+                &keyfile.digest,
+                argon2::Algorithm::Argon2id,
+                Version::V0x13,
+                argon2_params,
+            )
+            .map_err(|e| anyhow!("Failed to use keyfile: {}", e))?,
+            None => Argon2::new(argon2::Algorithm::Argon2id, Version::V0x13, argon2_params),
+        };
 
         // Derive key
         let salt_string = SaltString::encode_b64(&params.salt)
@@ -200,92 +615,498 @@ impl EncryptionKey {
         Ok(Self { key })
     }
 
-    /// Encrypt data using ChaCha20-Poly1305
-    pub fn encrypt(&self, plaintext: &[u8], cipher_params: &CipherParams) -> Result<Vec<u8>> {
-        if cipher_params.nonce.len() != NONCE_SIZE {
-            return Err(anyhow!("Invalid nonce size"));
+    /// Generate a random key
+    pub fn generate() -> Result<Self> {
+        let mut key = [0u8; KEY_SIZE];
+        OsRng
+            .try_fill_bytes(&mut key)
+            .map_err(|e| anyhow!("Failed to generate key: {}", e))?;
+        Ok(Self { key })
+    }
+
+    /// Encrypt another key with this one (XChaCha20-Poly1305, random nonce)
+    ///
+    /// Returns `nonce | ciphertext | tag`.
+    pub fn wrap_key(&self, key: &EncryptionKey) -> Result<Vec<u8>> {
+        let cipher_params = CipherParams::with_algorithm(CipherAlgorithm::XChaCha20Poly1305)?;
+        let mut wrapped = cipher_params.nonce.clone();
+        wrapped.extend(self.encrypt(&key.key, &cipher_params, KEY_WRAP_AAD)?);
+        Ok(wrapped)
+    }
+
+    /// Decrypt a key made by `wrap_key`
+    pub fn unwrap_key(&self, wrapped: &[u8]) -> Result<EncryptionKey> {
+        let nonce_size = CipherAlgorithm::XChaCha20Poly1305.nonce_size();
+        if wrapped.len() <= nonce_size {
+            return Err(anyhow!("Invalid wrapped key"));
         }
+        let (nonce, ciphertext) = wrapped.split_at(nonce_size);
+        let cipher_params = CipherParams {
+            algorithm: CipherAlgorithm::XChaCha20Poly1305.as_str().to_string(),
+            nonce: nonce.to_vec(),
+        };
 
-        let cipher = ChaCha20Poly1305::new_from_slice(&self.key)
-            .map_err(|e| anyhow!("Failed to create cipher: {}", e))?;
+        let mut plaintext = self.decrypt(ciphertext, &cipher_params, KEY_WRAP_AAD)?;
+        let key = <[u8; KEY_SIZE]>::try_from(plaintext.as_slice())
+            .map_err(|_| anyhow!("Invalid wrapped key"));
+        plaintext.zeroize();
+        Ok(Self { key: key? })
+    }
 
-        let nonce = Nonce::try_from(cipher_params.nonce.as_slice())
-            .map_err(|_| anyhow!("Invalid nonce"))?;
+    /// Encrypt data with the algorithm named in `cipher_params`
+    ///
+    /// `aad` is authenticated but not encrypted; the same bytes must be passed
+    /// to `decrypt`.
+    pub fn encrypt(
+        &self,
+        plaintext: &[u8],
+        cipher_params: &CipherParams,
+        aad: &[u8],
+    ) -> Result<Vec<u8>> {
+        let nonce = cipher_params.nonce.as_slice();
 
-        cipher
-            .encrypt(&nonce, plaintext)
-            .map_err(|e| anyhow!("Encryption failed: {}", e))
+        match cipher_params.algorithm()? {
+            CipherAlgorithm::ChaCha20Poly1305 => {
+                self.seal::<ChaCha20Poly1305>(nonce, plaintext, aad)
+            }
+            CipherAlgorithm::XChaCha20Poly1305 => {
+                self.seal::<XChaCha20Poly1305>(nonce, plaintext, aad)
+            }
+            CipherAlgorithm::Aes256GcmSiv => {
+                use aes_gcm_siv::aead::{Aead as _, KeyInit as _};
+                Aes256GcmSiv::new_from_slice(&self.key)
+                    .map_err(|e| anyhow!("Failed to create cipher: {}", e))?
+                    .encrypt(
+                        &aes_gcm_siv::Nonce::from(
+                            <[u8; 12]>::try_from(nonce).map_err(|_| anyhow!("Invalid nonce"))?,
+                        ),
+                        aes_gcm_siv::aead::Payload {
+                            msg: plaintext,
+                            aad,
+                        },
+                    )
+                    .map_err(|e| anyhow!("Encryption failed: {}", e))
+            }
+        }
     }
 
-    /// Decrypt data using ChaCha20-Poly1305
-    pub fn decrypt(&self, ciphertext: &[u8], cipher_params: &CipherParams) -> Result<Vec<u8>> {
-        if cipher_params.nonce.len() != NONCE_SIZE {
-            return Err(anyhow!("Invalid nonce size"));
+    /// Decrypt data with the algorithm named in `cipher_params`
+    pub fn decrypt(
+        &self,
+        ciphertext: &[u8],
+        cipher_params: &CipherParams,
+        aad: &[u8],
+    ) -> Result<Vec<u8>> {
+        let nonce = cipher_params.nonce.as_slice();
+
+        match cipher_params.algorithm()? {
+            CipherAlgorithm::ChaCha20Poly1305 => {
+                self.open::<ChaCha20Poly1305>(nonce, ciphertext, aad)
+            }
+            CipherAlgorithm::XChaCha20Poly1305 => {
+                self.open::<XChaCha20Poly1305>(nonce, ciphertext, aad)
+            }
+            CipherAlgorithm::Aes256GcmSiv => {
+                use aes_gcm_siv::aead::{Aead as _, KeyInit as _};
+                Aes256GcmSiv::new_from_slice(&self.key)
+                    .map_err(|e| anyhow!("Failed to create cipher: {}", e))?
+                    .decrypt(
+                        &aes_gcm_siv::Nonce::from(
+                            <[u8; 12]>::try_from(nonce).map_err(|_| anyhow!("Invalid nonce"))?,
+                        ),
+                        aes_gcm_siv::aead::Payload {
+                            msg: ciphertext,
+                            aad,
+                        },
+                    )
+                    .map_err(|e| anyhow!("Decryption failed: {}", e))
+            }
         }
+    }
 
-        let cipher = ChaCha20Poly1305::new_from_slice(&self.key)
-            .map_err(|e| anyhow!("Failed to create cipher: {}", e))?;
+    /// Encrypt with one of the ChaCha20-Poly1305 variants
+    fn seal<C: KeyInit + Aead>(&self, nonce: &[u8], msg: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let cipher =
+            C::new_from_slice(&self.key).map_err(|e| anyhow!("Failed to create cipher: {}", e))?;
+        let nonce = aead::Nonce::<C>::try_from(nonce).map_err(|_| anyhow!("Invalid nonce"))?;
 
-        let nonce = Nonce::try_from(cipher_params.nonce.as_slice())
-            .map_err(|_| anyhow!("Invalid nonce"))?;
+        cipher
+            .encrypt(&nonce, Payload { msg, aad })
+            .map_err(|e| anyhow!("Encryption failed: {}", e))
+    }
+
+    /// Decrypt with one of the ChaCha20-Poly1305 variants
+    fn open<C: KeyInit + Aead>(&self, nonce: &[u8], msg: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let cipher =
+            C::new_from_slice(&self.key).map_err(|e| anyhow!("Failed to create cipher: {}", e))?;
+        let nonce = aead::Nonce::<C>::try_from(nonce).map_err(|_| anyhow!("Invalid nonce"))?;
 
         cipher
-            .decrypt(&nonce, ciphertext)
+            .decrypt(&nonce, Payload { msg, aad })
             .map_err(|e| anyhow!("Decryption failed: {}", e))
     }
 }
 
+/// Data key wrapped for recovery shares
+///
+/// The recovery key is split with [`shamir`]; any `threshold` of the `shares`
+/// printed shares rebuild it and unwrap the data key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecoverySlot {
+    pub threshold: u8,
+    pub shares: u8,
+    pub wrapped_key: Vec<u8>,
+}
+
+/// Unlocked vault session
+///
+/// Keeps the KDF parameters (including the salt), the key derived from them
+/// and the data key it wraps. Saves re-encrypt with the data key and a fresh
+/// nonce, so Argon2id only runs on unlock and on an explicit rekey, and the
+/// master password itself never needs to be kept in memory.
+pub struct Session {
+    kdf: KdfParams,
+    password_key: EncryptionKey,
+    key: EncryptionKey,
+    wrapped_key: Vec<u8>,
+    recovery: Option<RecoverySlot>,
+    cipher: CipherAlgorithm,
+}
+
+impl Session {
+    /// Start a session for a new vault (fresh salt and data key)
+    pub fn new(password: &str, keyfile: Option<&Keyfile>, cost: KdfCost) -> Result<Self> {
+        let mut kdf = KdfParams::with_cost(cost)?;
+        kdf.keyfile = keyfile.is_some();
+        Self::derive(password, keyfile, kdf)
+    }
+
+    /// Derive the password key for existing KDF parameters
+    ///
+    /// The session gets a fresh data key; call `load_key` to use the one
+    /// stored in a vault instead.
+    pub fn derive(password: &str, keyfile: Option<&Keyfile>, kdf: KdfParams) -> Result<Self> {
+        let password_key = EncryptionKey::derive(password, keyfile, &kdf)?;
+        Self::with_password_key(kdf, password_key, EncryptionKey::generate()?)
+    }
+
+    fn with_password_key(
+        kdf: KdfParams,
+        password_key: EncryptionKey,
+        key: EncryptionKey,
+    ) -> Result<Self> {
+        let wrapped_key = password_key.wrap_key(&key)?;
+        Ok(Self {
+            kdf,
+            password_key,
+            key,
+            wrapped_key,
+            recovery: None,
+            cipher: CipherAlgorithm::default(),
+        })
+    }
+
+    /// Switch to the data key stored in a vault, wrapped by the password key
+    pub fn load_key(&mut self, wrapped_key: &[u8], recovery: Option<RecoverySlot>) -> Result<()> {
+        self.key = self.password_key.unwrap_key(wrapped_key)?;
+        self.wrapped_key = wrapped_key.to_vec();
+        self.recovery = recovery;
+        Ok(())
+    }
+
+    /// Rebuild a session from recovery shares, under a new password
+    ///
+    /// The data key and the recovery slot are kept, so the same shares keep
+    /// working afterwards.
+    pub fn recover(
+        shares: &[Share],
+        recovery: RecoverySlot,
+        password: &str,
+        keyfile: Option<&Keyfile>,
+        cost: KdfCost,
+    ) -> Result<Self> {
+        let secret = shamir::combine(shares)?;
+        let recovery_key = EncryptionKey {
+            key: <[u8; KEY_SIZE]>::try_from(secret.as_slice())
+                .map_err(|_| anyhow!("Invalid recovery shares"))?,
+        };
+        let key = recovery_key
+            .unwrap_key(&recovery.wrapped_key)
+            .map_err(|_| anyhow!("Recovery shares do not match this vault"))?;
+
+        let mut kdf = KdfParams::with_cost(cost)?;
+        kdf.keyfile = keyfile.is_some();
+        let password_key = EncryptionKey::derive(password, keyfile, &kdf)?;
+
+        let mut session = Self::with_password_key(kdf, password_key, key)?;
+        session.recovery = Some(recovery);
+        Ok(session)
+    }
+
+    /// Switch to a fresh data key wrapped under new secrets and a fresh salt
+    ///
+    /// The cipher algorithm is kept. Recovery shares wrap the old data key,
+    /// so the recovery slot is dropped. Returns the session as it was, to
+    /// decrypt what the old data key encrypted.
+    pub fn rekey(
+        &mut self,
+        password: &str,
+        keyfile: Option<&Keyfile>,
+        cost: KdfCost,
+    ) -> Result<Session> {
+        let mut kdf = KdfParams::with_cost(cost)?;
+        kdf.keyfile = keyfile.is_some();
+        let password_key = EncryptionKey::derive(password, keyfile, &kdf)?;
+        self.replace_key(kdf, password_key)
+    }
+
+    /// Switch to a fresh data key wrapped under the same secrets
+    ///
+    /// Like `rekey`, the recovery slot is dropped and the old session returned.
+    pub fn rotate_key(&mut self) -> Result<Session> {
+        let password_key = EncryptionKey {
+            key: self.password_key.key,
+        };
+        self.replace_key(self.kdf.clone(), password_key)
+    }
+
+    fn replace_key(&mut self, kdf: KdfParams, password_key: EncryptionKey) -> Result<Session> {
+        let mut session = Self::with_password_key(kdf, password_key, EncryptionKey::generate()?)?;
+        session.cipher = self.cipher;
+        Ok(std::mem::replace(self, session))
+    }
+
+    /// Create recovery shares for the data key, replacing any earlier ones
+    pub fn setup_recovery(&mut self, threshold: u8, shares: u8) -> Result<Vec<Share>> {
+        let recovery_key = EncryptionKey::generate()?;
+        let split = shamir::split(&recovery_key.key, threshold, shares)?;
+        self.recovery = Some(RecoverySlot {
+            threshold,
+            shares,
+            wrapped_key: recovery_key.wrap_key(&self.key)?,
+        });
+        Ok(split)
+    }
+
+    /// Algorithm used for the next save
+    pub fn cipher(&self) -> CipherAlgorithm {
+        self.cipher
+    }
+
+    /// Encrypt future saves with `cipher`
+    pub fn set_cipher(&mut self, cipher: CipherAlgorithm) {
+        self.cipher = cipher;
+    }
+
+    /// KDF parameters the password key was derived with
+    pub fn kdf(&self) -> &KdfParams {
+        &self.kdf
+    }
+
+    /// The data key the vault is encrypted with
+    pub fn key(&self) -> &EncryptionKey {
+        &self.key
+    }
+
+    /// The key derived from the password (and keyfile)
+    ///
+    /// Vaults written before key wrapping are encrypted with it directly.
+    pub fn password_key(&self) -> &EncryptionKey {
+        &self.password_key
+    }
+
+    /// The data key wrapped by the password key
+    pub fn wrapped_key(&self) -> &[u8] {
+        &self.wrapped_key
+    }
+
+    /// Recovery slot, if recovery shares were set up
+    pub fn recovery(&self) -> Option<&RecoverySlot> {
+        self.recovery.as_ref()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// Check a known-answer vector in both directions
+    fn check_kat(
+        algorithm: CipherAlgorithm,
+        key: &str,
+        nonce: &str,
+        aad: &str,
+        plaintext: &[u8],
+        ciphertext_and_tag: &str,
+    ) {
+        let key = EncryptionKey {
+            key: hex(key).try_into().unwrap(),
+        };
+        let cipher_params = CipherParams {
+            algorithm: algorithm.as_str().to_string(),
+            nonce: hex(nonce),
+        };
+        let aad = hex(aad);
+        let expected = hex(ciphertext_and_tag);
+
+        assert_eq!(
+            key.encrypt(plaintext, &cipher_params, &aad).unwrap(),
+            expected
+        );
+        assert_eq!(
+            key.decrypt(&expected, &cipher_params, &aad).unwrap(),
+            plaintext
+        );
+    }
+
+    const SUNSCREEN: &[u8] = b"Ladies and Gentlemen of the class of '99: If I could offer you \
+only one tip for the future, sunscreen would be it.";
+
+    #[test]
+    fn test_chacha20poly1305_kat() {
+        // RFC 8439, section 2.8.2
+        check_kat(
+            CipherAlgorithm::ChaCha20Poly1305,
+            "808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f",
+            "070000004041424344454647",
+            "50515253c0c1c2c3c4c5c6c7",
+            SUNSCREEN,
+            "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d6\
+             3dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b36\
+             92ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc\
+             3ff4def08e4b7a9de576d26586cec64b6116\
+             1ae10b594f09e26a7e902ecbd0600691",
+        );
+    }
+
+    #[test]
+    fn test_xchacha20poly1305_kat() {
+        // draft-irtf-cfrg-xchacha-03, appendix A.3.1
+        check_kat(
+            CipherAlgorithm::XChaCha20Poly1305,
+            "808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f",
+            "404142434445464748494a4b4c4d4e4f5051525354555657",
+            "50515253c0c1c2c3c4c5c6c7",
+            SUNSCREEN,
+            "bd6d179d3e83d43b9576579493c0e939572a1700252bfaccbed2902c21396cbb\
+             731c7f1b0b4aa6440bf3a82f4eda7e39ae64c6708c54c216cb96b72e1213b452\
+             2f8c9ba40db5d945b11b69b982c1bb9e3f3fac2bc369488f76b2383565d3fff9\
+             21f9664c97637da9768812f615c68b13b52e\
+             c0875924c1c7987947deafd8780acf49",
+        );
+    }
+
+    #[test]
+    fn test_aes256gcmsiv_kat() {
+        // RFC 8452, appendix C.2
+        let key = "0100000000000000000000000000000000000000000000000000000000000000";
+        let nonce = "030000000000000000000000";
+
+        check_kat(
+            CipherAlgorithm::Aes256GcmSiv,
+            key,
+            nonce,
+            "",
+            b"",
+            "07f5f4169bbf55a8400cd47ea6fd400f",
+        );
+        check_kat(
+            CipherAlgorithm::Aes256GcmSiv,
+            key,
+            nonce,
+            "",
+            &hex("0100000000000000"),
+            "c2ef328e5c71c83b843122130f7364b761e0b97427e3df28",
+        );
+    }
+
+    #[test]
+    fn test_cipher_algorithms() {
+        let key = EncryptionKey::derive("test_password", None, &KdfParams::new().unwrap()).unwrap();
+
+        for algorithm in CipherAlgorithm::ALL {
+            assert_eq!(
+                CipherAlgorithm::parse(algorithm.as_str()).unwrap(),
+                algorithm
+            );
+
+            let cipher_params = CipherParams::with_algorithm(algorithm).unwrap();
+            assert_eq!(cipher_params.nonce.len(), algorithm.nonce_size());
+            let ciphertext = key.encrypt(b"secret", &cipher_params, b"aad").unwrap();
+            assert_eq!(
+                key.decrypt(&ciphertext, &cipher_params, b"aad").unwrap(),
+                b"secret"
+            );
+
+            // Wrong nonce length for the algorithm
+            let mut bad = cipher_params.clone();
+            bad.nonce.pop();
+            assert!(key.encrypt(b"secret", &bad, b"").is_err());
+        }
+
+        let unknown = CipherParams {
+            algorithm: "rot13".to_string(),
+            nonce: vec![0; 12],
+        };
+        let err = key.encrypt(b"secret", &unknown, b"").unwrap_err();
+        assert!(err.to_string().contains("Unsupported cipher algorithm"));
+        assert!(key.decrypt(b"secret", &unknown, b"").is_err());
+    }
+
     #[test]
     fn test_key_derivation() {
         let params = KdfParams::new().unwrap();
-        let key = EncryptionKey::derive("test_password", &params);
+        let key = EncryptionKey::derive("test_password", None, &params);
         assert!(key.is_ok());
     }
 
     #[test]
     fn test_encryption_decryption() {
         let params = KdfParams::new().unwrap();
-        let key = EncryptionKey::derive("test_password", &params).unwrap();
+        let key = EncryptionKey::derive("test_password", None, &params).unwrap();
 
         let plaintext = b"Hello, World!";
         let cipher_params = CipherParams::new().unwrap();
 
-        let ciphertext = key.encrypt(plaintext, &cipher_params).unwrap();
+        let ciphertext = key.encrypt(plaintext, &cipher_params, b"").unwrap();
         assert_ne!(&ciphertext[..], plaintext);
 
-        let decrypted = key.decrypt(&ciphertext, &cipher_params).unwrap();
+        let decrypted = key.decrypt(&ciphertext, &cipher_params, b"").unwrap();
         assert_eq!(&decrypted[..], plaintext);
     }
 
     #[test]
     fn test_wrong_password() {
         let params = KdfParams::new().unwrap();
-        let key1 = EncryptionKey::derive("password1", &params).unwrap();
-        let key2 = EncryptionKey::derive("password2", &params).unwrap();
+        let key1 = EncryptionKey::derive("password1", None, &params).unwrap();
+        let key2 = EncryptionKey::derive("password2", None, &params).unwrap();
 
         let plaintext = b"Secret data";
         let cipher_params = CipherParams::new().unwrap();
 
-        let ciphertext = key1.encrypt(plaintext, &cipher_params).unwrap();
+        let ciphertext = key1.encrypt(plaintext, &cipher_params, b"").unwrap();
 
         // Attempting to decrypt with wrong password should fail
-        let result = key2.decrypt(&ciphertext, &cipher_params);
+        let result = key2.decrypt(&ciphertext, &cipher_params, b"");
         assert!(result.is_err());
     }
 
     #[test]
     fn test_tampered_ciphertext() {
         let params = KdfParams::new().unwrap();
-        let key = EncryptionKey::derive("test_password", &params).unwrap();
+        let key = EncryptionKey::derive("test_password", None, &params).unwrap();
 
         let plaintext = b"Sensitive data";
         let cipher_params = CipherParams::new().unwrap();
 
-        let mut ciphertext = key.encrypt(plaintext, &cipher_params).unwrap();
+        let mut ciphertext = key.encrypt(plaintext, &cipher_params, b"").unwrap();
 
         // Tamper with ciphertext
         if let Some(byte) = ciphertext.get_mut(0) {
@@ -293,10 +1114,198 @@ mod tests {
         }
 
         // Decryption should fail due to authentication tag
-        let result = key.decrypt(&ciphertext, &cipher_params);
+        let result = key.decrypt(&ciphertext, &cipher_params, b"");
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_associated_data() {
+        let params = KdfParams::with_cost(KdfCost::MIN).unwrap();
+        let key = EncryptionKey::derive("test_password", None, &params).unwrap();
+        let cipher_params = CipherParams::new().unwrap();
+
+        let ciphertext = key.encrypt(b"payload", &cipher_params, b"header").unwrap();
+        assert_eq!(
+            key.decrypt(&ciphertext, &cipher_params, b"header").unwrap(),
+            b"payload"
+        );
+
+        // Associated data is authenticated
+        assert!(key.decrypt(&ciphertext, &cipher_params, b"HEADER").is_err());
+        assert!(key.decrypt(&ciphertext, &cipher_params, b"").is_err());
+    }
+
+    #[test]
+    fn test_session_rekey() {
+        let mut session = Session::new("old_password", None, KdfCost::MIN).unwrap();
+        session.set_cipher(CipherAlgorithm::Aes256GcmSiv);
+        session.setup_recovery(2, 3).unwrap();
+        let old_salt = session.kdf().salt.clone();
+
+        let cipher_params = CipherParams::new().unwrap();
+        let ciphertext = session.key().encrypt(b"data", &cipher_params, b"").unwrap();
+
+        let stronger = KdfCost {
+            time_cost: 3,
+            ..KdfCost::MIN
+        };
+        let old = session.rekey("new_password", None, stronger).unwrap();
+        assert_ne!(session.kdf().salt, old_salt);
+        assert_eq!(session.kdf().cost(), stronger);
+        assert_eq!(session.cipher(), CipherAlgorithm::Aes256GcmSiv);
+        assert!(session.recovery().is_none());
+
+        // A fresh data key, wrapped only for the new password
+        assert!(session
+            .key()
+            .decrypt(&ciphertext, &cipher_params, b"")
+            .is_err());
+        assert!(old.key().decrypt(&ciphertext, &cipher_params, b"").is_ok());
+        let mut reopened = Session::derive("new_password", None, session.kdf().clone()).unwrap();
+        reopened.load_key(session.wrapped_key(), None).unwrap();
+        assert_eq!(reopened.key().key, session.key().key);
+        assert!(reopened.load_key(old.wrapped_key(), None).is_err());
+
+        // Same secrets, fresh data key
+        let old = session.rotate_key().unwrap();
+        assert_eq!(session.kdf(), old.kdf());
+        assert_ne!(session.key().key, old.key().key);
+        reopened.load_key(session.wrapped_key(), None).unwrap();
+        assert_eq!(reopened.key().key, session.key().key);
+    }
+
+    #[test]
+    fn test_key_wrapping() {
+        let wrapping = EncryptionKey::generate().unwrap();
+        let key = EncryptionKey::generate().unwrap();
+
+        let wrapped = wrapping.wrap_key(&key).unwrap();
+        assert_eq!(wrapped.len(), 24 + KEY_SIZE + 16);
+        assert_ne!(wrapping.wrap_key(&key).unwrap(), wrapped);
+        assert_eq!(wrapping.unwrap_key(&wrapped).unwrap().key, key.key);
+
+        assert!(key.unwrap_key(&wrapped).is_err());
+        assert!(wrapping.unwrap_key(&wrapped[..24]).is_err());
+    }
+
+    #[test]
+    fn test_recovery() {
+        let mut session = Session::new("old_password", None, KdfCost::MIN).unwrap();
+        let cipher_params = CipherParams::new().unwrap();
+        let ciphertext = session.key().encrypt(b"data", &cipher_params, b"").unwrap();
+
+        let shares = session.setup_recovery(2, 3).unwrap();
+        let slot = session.recovery().unwrap().clone();
+        assert_eq!((slot.threshold, slot.shares), (2, 3));
+
+        let recovered = Session::recover(
+            &shares[1..],
+            slot.clone(),
+            "new_password",
+            None,
+            KdfCost::MIN,
+        )
+        .unwrap();
+        assert_eq!(recovered.recovery(), Some(&slot));
+        assert_eq!(
+            recovered
+                .key()
+                .decrypt(&ciphertext, &cipher_params, b"")
+                .unwrap(),
+            b"data"
+        );
+        let mut reopened = Session::derive("new_password", None, recovered.kdf().clone()).unwrap();
+        reopened.load_key(recovered.wrapped_key(), None).unwrap();
+        assert_eq!(reopened.key().key, session.key().key);
+
+        // Too few shares, or shares of another setup
+        assert!(Session::recover(&shares[..1], slot.clone(), "pw", None, KdfCost::MIN).is_err());
+        let other = session.setup_recovery(2, 3).unwrap();
+        assert!(Session::recover(&other[..2], slot, "pw", None, KdfCost::MIN).is_err());
+    }
+
+    #[test]
+    fn test_keyfile_required() {
+        let keyfile = Keyfile::from_bytes(b"keyfile contents");
+        let session = Session::new("test_password", Some(&keyfile), KdfCost::MIN).unwrap();
+        assert!(session.kdf().keyfile);
+
+        let cipher_params = CipherParams::new().unwrap();
+        let ciphertext = session
+            .password_key()
+            .encrypt(b"data", &cipher_params, b"")
+            .unwrap();
+
+        // Password alone is refused
+        assert!(EncryptionKey::derive("test_password", None, session.kdf()).is_err());
+
+        // Wrong keyfile derives a different key
+        let other = Keyfile::from_bytes(b"other contents");
+        let key = EncryptionKey::derive("test_password", Some(&other), session.kdf()).unwrap();
+        assert!(key.decrypt(&ciphertext, &cipher_params, b"").is_err());
+
+        let key = EncryptionKey::derive("test_password", Some(&keyfile), session.kdf()).unwrap();
+        assert_eq!(
+            key.decrypt(&ciphertext, &cipher_params, b"").unwrap(),
+            b"data"
+        );
+
+        // A keyfile for a password-only vault is refused too
+        let plain = KdfParams::with_cost(KdfCost::MIN).unwrap();
+        assert!(EncryptionKey::derive("test_password", Some(&keyfile), &plain).is_err());
+    }
+
+    #[test]
+    fn test_keyfile_generate() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("vault.key");
+
+        Keyfile::generate(&path).unwrap();
+        assert_eq!(fs::read(&path).unwrap().len(), KEYFILE_SIZE);
+        assert!(Keyfile::read(&path).is_ok());
+
+        // Never overwrite an existing keyfile
+        assert!(Keyfile::generate(&path).is_err());
+    }
+
+    #[test]
+    fn test_kdf_cost_bounds() {
+        assert!(KdfCost::default().validate().is_ok());
+        assert!(KdfCost::MIN.validate().is_ok());
+        assert!(KdfCost::MAX.validate().is_ok());
+
+        let weak = KdfCost {
+            memory_cost: 8,
+            ..KdfCost::MIN
+        };
+        assert!(weak.validate().is_err());
+        assert!(KdfParams::with_cost(weak).is_err());
+
+        let huge = KdfCost {
+            parallelism: KdfCost::MAX.parallelism + 1,
+            ..KdfCost::default()
+        };
+        assert!(huge.validate().is_err());
+    }
+
+    #[test]
+    fn test_kdf_params_validate() {
+        let params = KdfParams::with_cost(KdfCost::MIN).unwrap();
+        assert!(params.validate().is_ok());
+
+        let mut downgraded = params.clone();
+        downgraded.time_cost = 1;
+        assert!(downgraded.validate().is_err());
+
+        let mut short_salt = params.clone();
+        short_salt.salt.truncate(4);
+        assert!(short_salt.validate().is_err());
+
+        let mut other = params;
+        other.algorithm = "pbkdf2".to_string();
+        assert!(other.validate().is_err());
+    }
+
     #[test]
     fn test_generate_secure_password() {
         let password = generate_secure_password(20);
@@ -312,4 +1321,21 @@ mod tests {
         assert!(has_digit, "Password must contain digit");
         assert!(has_special, "Password must contain special char");
     }
+
+    #[test]
+    fn test_generate_password_with_policy() {
+        let policy = PasswordPolicy {
+            length: 32,
+            upper: false,
+            lower: true,
+            digits: true,
+            symbols: false,
+        };
+        let password = generate_password(&policy);
+        assert_eq!(password.len(), 32);
+        assert!(password
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit()));
+        assert!(password.chars().any(|c| c.is_ascii_digit()));
+    }
 }
@@ -1,11 +1,11 @@
 //! Export vault entries to various formats
 //!
 //! Supports:
-//! - Firefox CSV (url,username,password) - most compatible
+//! - Firefox CSV (url,username,password) - most compatible, logins only
 //! - JSON (preserves all metadata)
 //! - Extended CSV (all fields)
 
-use crate::model::Vault;
+use crate::model::{Entry, Vault};
 use anyhow::Result;
 use std::fs::File;
 use std::io::Write;
@@ -43,12 +43,13 @@ impl ExportFormat {
 /// Export vault to Firefox-compatible CSV format
 ///
 /// Format: url,username,password
-/// This is the simplest format that Firefox can import directly
+/// This is the simplest format that Firefox can import directly. Entries that
+/// aren't logins (cards, notes, keys...) have no place in it and are skipped.
 fn export_firefox_csv(vault: &Vault) -> Result<String> {
     let mut output = String::from("url,username,password\n");
 
-    for entry in &vault.entries {
-        let url = entry.url.as_deref().unwrap_or("");
+    for entry in firefox_entries(vault) {
+        let url = entry.url().unwrap_or("");
         let username = csv_escape(&entry.username);
         let password = csv_escape(&entry.password);
 
@@ -58,6 +59,11 @@ fn export_firefox_csv(vault: &Vault) -> Result<String> {
     Ok(output)
 }
 
+/// Entries the Firefox format can represent
+fn firefox_entries(vault: &Vault) -> impl Iterator<Item = &Entry> {
+    vault.entries.iter().filter(|e| e.kind.is_login())
+}
+
 /// Export vault to JSON format (preserves all metadata)
 fn export_json(vault: &Vault) -> Result<String> {
     let json = serde_json::to_string_pretty(vault)?;
@@ -66,21 +72,44 @@ fn export_json(vault: &Vault) -> Result<String> {
 
 /// Export vault to extended CSV format (all fields)
 ///
-/// Format: name,username,password,url,notes,tags
+/// Format: name,username,password,url,notes,tags,fields,kind,folder
+///
+/// Custom fields are a JSON array, and the kind of entries that aren't logins
+/// a JSON object (as in the vault). The folder is a path like `Work/DB`.
+/// Several URLs are separated by spaces, with their match rule as a prefix
+/// unless it is the default (e.g. `host:https://login.example.com`).
 fn export_csv_extended(vault: &Vault) -> Result<String> {
-    let mut output = String::from("name,username,password,url,notes,tags\n");
+    let mut output = String::from("name,username,password,url,notes,tags,fields,kind,folder\n");
 
     for entry in &vault.entries {
         let name = csv_escape(&entry.name);
         let username = csv_escape(&entry.username);
         let password = csv_escape(&entry.password);
-        let url = csv_escape(entry.url.as_deref().unwrap_or(""));
+        let urls: Vec<String> = entry.urls.iter().map(|u| u.to_string()).collect();
+        let url = csv_escape(&urls.join(" "));
         let notes = csv_escape(entry.notes.as_deref().unwrap_or(""));
         let tags = csv_escape(&entry.tags.join(","));
+        let fields = if entry.fields.is_empty() {
+            csv_escape("")
+        } else {
+            csv_escape(&serde_json::to_string(&entry.fields)?)
+        };
+        let kind = if entry.kind.is_login() {
+            csv_escape("")
+        } else {
+            csv_escape(&serde_json::to_string(&entry.kind)?)
+        };
+
+        let folder = csv_escape(
+            &entry
+                .folder
+                .map(|id| vault.folder_path(&id))
+                .unwrap_or_default(),
+        );
 
         output.push_str(&format!(
-            "{},{},{},{},{},{}\n",
-            name, username, password, url, notes, tags
+            "{},{},{},{},{},{},{},{},{}\n",
+            name, username, password, url, notes, tags, fields, kind, folder
         ));
     }
 
@@ -97,7 +126,9 @@ fn csv_escape(s: &str) -> String {
 }
 
 /// Export vault to file
-pub fn export_to_file(vault: &Vault, path: &Path, format: ExportFormat) -> Result<()> {
+///
+/// Returns the number of entries written.
+pub fn export_to_file(vault: &Vault, path: &Path, format: ExportFormat) -> Result<usize> {
     let content = match format {
         ExportFormat::Firefox => export_firefox_csv(vault)?,
         ExportFormat::Json => export_json(vault)?,
@@ -116,13 +147,16 @@ pub fn export_to_file(vault: &Vault, path: &Path, format: ExportFormat) -> Resul
         std::fs::set_permissions(path, permissions)?;
     }
 
-    Ok(())
+    Ok(match format {
+        ExportFormat::Firefox => firefox_entries(vault).count(),
+        ExportFormat::Json | ExportFormat::CsvExtended => vault.entries.len(),
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::model::Entry;
+    use crate::model::EntryKind;
 
     #[test]
     fn test_firefox_export() {
@@ -143,6 +177,24 @@ mod tests {
         assert!(result.contains("pass123"));
     }
 
+    #[test]
+    fn test_firefox_export_skips_other_kinds() {
+        let mut vault = Vault::new();
+        let mut note = Entry::new(
+            "Wifi".to_string(),
+            String::new(),
+            String::new(),
+            None,
+            Some("hunter2".to_string()),
+            vec![],
+        );
+        note.kind = EntryKind::Note;
+        vault.add_entry(note);
+
+        let result = export_firefox_csv(&vault).unwrap();
+        assert_eq!(result, "url,username,password\n");
+    }
+
     #[test]
     fn test_csv_escape() {
         assert_eq!(csv_escape("simple"), "\"simple\"");
@@ -163,7 +215,7 @@ mod tests {
         ));
 
         let result = export_csv_extended(&vault).unwrap();
-        assert!(result.contains("name,username,password,url,notes,tags"));
+        assert!(result.contains("name,username,password,url,notes,tags,fields,kind,folder"));
         assert!(result.contains("GitHub"));
         assert!(result.contains("My notes"));
         assert!(result.contains("work,dev"));
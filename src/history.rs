@@ -0,0 +1,428 @@
+//! Undo/redo history of vault edits
+//!
+//! Every edit the TUI makes is recorded as a [`Change`] that can be reverted
+//! and applied again. Changes find entries by ID and skip ones that are gone
+//! (purged on save or dropped by a merge), so the history stays usable when
+//! the vault changes underneath it.
+//!
+//! Usage statistics are not edits: an entry keeps the ones it has when a
+//! change swaps in an older or newer copy of it.
+
+use crate::model::{Entry, Folder, Template, TrashedEntry, Vault};
+use uuid::Uuid;
+
+/// Most changes kept for undo
+const MAX_UNDO: usize = 100;
+
+/// A reversible edit of a vault
+#[derive(Debug, Clone)]
+pub enum Change {
+    /// An entry was added
+    Add(Entry),
+    /// An entry was replaced
+    Edit {
+        before: Box<Entry>,
+        after: Box<Entry>,
+    },
+    /// The entry at `index` was moved to the trash
+    Trash { index: usize, trashed: TrashedEntry },
+    /// The trashed entry at `index` was put back into the vault
+    Restore { index: usize, trashed: TrashedEntry },
+    /// The trashed entry at `index` was deleted for good
+    Purge { index: usize, trashed: TrashedEntry },
+    /// The folder tree was replaced
+    Folders {
+        before: Vec<Folder>,
+        after: Vec<Folder>,
+    },
+    /// The list of templates was replaced
+    Templates {
+        before: Vec<Template>,
+        after: Vec<Template>,
+    },
+    /// Several changes made in one step
+    Batch(Vec<Change>),
+}
+
+impl Change {
+    /// Make the change
+    pub fn apply(&self, vault: &mut Vault) {
+        match self {
+            Self::Add(entry) => {
+                if vault.get_entry(&entry.id).is_none() {
+                    vault.entries.push(entry.clone());
+                }
+            }
+            Self::Edit { after, .. } => replace(vault, after),
+            Self::Trash { trashed, .. } => {
+                if let Some(pos) = entry_position(vault, &trashed.entry.id) {
+                    let current = vault.entries.remove(pos);
+                    let mut trashed = trashed.clone();
+                    keep_usage(&current, &mut trashed.entry);
+                    vault.trash.push(trashed);
+                }
+            }
+            Self::Restore { trashed, .. } => {
+                if let Some(pos) = trash_position(vault, &trashed.entry.id) {
+                    let current = vault.trash.remove(pos).entry;
+                    let mut entry = trashed.entry.clone();
+                    keep_usage(&current, &mut entry);
+                    vault.entries.push(entry);
+                }
+            }
+            Self::Purge { trashed, .. } => {
+                if let Some(pos) = trash_position(vault, &trashed.entry.id) {
+                    vault.trash.remove(pos);
+                }
+            }
+            Self::Folders { after, .. } => vault.folders = after.clone(),
+            Self::Templates { after, .. } => vault.templates = after.clone(),
+            Self::Batch(changes) => changes.iter().for_each(|c| c.apply(vault)),
+        }
+    }
+
+    /// Take the change back
+    pub fn revert(&self, vault: &mut Vault) {
+        match self {
+            Self::Add(entry) => {
+                vault.remove_entry(&entry.id);
+            }
+            Self::Edit { before, .. } => replace(vault, before),
+            Self::Trash { index, trashed } => {
+                if let Some(pos) = trash_position(vault, &trashed.entry.id) {
+                    let current = vault.trash.remove(pos).entry;
+                    let mut entry = trashed.entry.clone();
+                    keep_usage(&current, &mut entry);
+                    let index = (*index).min(vault.entries.len());
+                    vault.entries.insert(index, entry);
+                }
+            }
+            Self::Restore { index, trashed } => {
+                if let Some(pos) = entry_position(vault, &trashed.entry.id) {
+                    let current = vault.entries.remove(pos);
+                    let mut trashed = trashed.clone();
+                    keep_usage(&current, &mut trashed.entry);
+                    let index = (*index).min(vault.trash.len());
+                    vault.trash.insert(index, trashed);
+                }
+            }
+            Self::Purge { index, trashed } => {
+                if trash_position(vault, &trashed.entry.id).is_none() {
+                    let index = (*index).min(vault.trash.len());
+                    vault.trash.insert(index, trashed.clone());
+                }
+            }
+            Self::Folders { before, .. } => {
+                vault.folders = before.clone();
+                vault.drop_missing_folders();
+            }
+            Self::Templates { before, .. } => vault.templates = before.clone(),
+            Self::Batch(changes) => changes.iter().rev().for_each(|c| c.revert(vault)),
+        }
+    }
+
+    /// Entries this change can put back into the vault
+    fn entries(&self) -> Vec<&Entry> {
+        match self {
+            Self::Add(entry) => vec![entry],
+            Self::Edit { before, after } => vec![before, after],
+            Self::Trash { trashed, .. }
+            | Self::Restore { trashed, .. }
+            | Self::Purge { trashed, .. } => vec![&trashed.entry],
+            Self::Folders { .. } | Self::Templates { .. } => Vec::new(),
+            Self::Batch(changes) => changes.iter().flat_map(Change::entries).collect(),
+        }
+    }
+
+    /// Short description for status messages, e.g. "delete 'GitHub'"
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Add(entry) => format!("add '{}'", entry.name),
+            Self::Edit { after, .. } => format!("edit '{}'", after.name),
+            Self::Trash { trashed, .. } => format!("delete '{}'", trashed.entry.name),
+            Self::Restore { trashed, .. } => format!("restore '{}'", trashed.entry.name),
+            Self::Purge { trashed, .. } => format!("purge '{}'", trashed.entry.name),
+            Self::Folders { .. } => "folder change".to_string(),
+            Self::Templates { .. } => "template change".to_string(),
+            Self::Batch(changes) => match changes.as_slice() {
+                [change] => change.describe(),
+                _ => format!("{} changes", changes.len()),
+            },
+        }
+    }
+}
+
+/// Swap in `entry` for the vault entry with the same ID
+fn replace(vault: &mut Vault, entry: &Entry) {
+    if let Some(current) = vault.get_entry_mut(&entry.id) {
+        let mut entry = entry.clone();
+        keep_usage(current, &mut entry);
+        *current = entry;
+    }
+}
+
+/// Carry the usage statistics of `current` over to its copy `entry`
+fn keep_usage(current: &Entry, entry: &mut Entry) {
+    entry.last_used = current.last_used;
+    entry.use_count = current.use_count;
+}
+
+fn entry_position(vault: &Vault, id: &Uuid) -> Option<usize> {
+    vault.entries.iter().position(|e| &e.id == id)
+}
+
+fn trash_position(vault: &Vault, id: &Uuid) -> Option<usize> {
+    vault.trash.iter().position(|t| &t.entry.id == id)
+}
+
+/// Undo and redo stacks of one unlocked session
+#[derive(Debug, Default)]
+pub struct History {
+    undo: Vec<Change>,
+    redo: Vec<Change>,
+}
+
+impl History {
+    /// Apply a new change and record it; clears the redo stack
+    pub fn apply(&mut self, change: Change, vault: &mut Vault) {
+        change.apply(vault);
+        self.undo.push(change);
+        if self.undo.len() > MAX_UNDO {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    /// Revert the latest change
+    pub fn undo(&mut self, vault: &mut Vault) -> Option<&Change> {
+        let change = self.undo.pop()?;
+        change.revert(vault);
+        self.redo.push(change);
+        self.redo.last()
+    }
+
+    /// Re-apply the latest undone change
+    pub fn redo(&mut self, vault: &mut Vault) -> Option<&Change> {
+        let change = self.redo.pop()?;
+        change.apply(vault);
+        self.undo.push(change);
+        self.undo.last()
+    }
+
+    /// IDs of the attachments that undo or redo can bring back
+    pub fn attachment_ids(&self) -> impl Iterator<Item = Uuid> + '_ {
+        self.undo
+            .iter()
+            .chain(&self.redo)
+            .flat_map(Change::entries)
+            .flat_map(|entry| entry.attachments.iter().map(|a| a.id))
+    }
+
+    /// Forget all changes
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn entry(name: &str) -> Entry {
+        Entry::new(
+            name.to_string(),
+            "user".to_string(),
+            "pass".to_string(),
+            None,
+            None,
+            vec![],
+        )
+    }
+
+    fn trashed(entry: &Entry) -> TrashedEntry {
+        TrashedEntry {
+            entry: entry.clone(),
+            deleted: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_undo_redo_round_trip() {
+        let a = entry("A");
+        let b = entry("B");
+        let c = entry("C");
+        let mut vault = Vault::new();
+        vault.entries = vec![a.clone(), b.clone()];
+        vault.trash = vec![trashed(&c)];
+        let original = vault.clone();
+
+        let mut edited = a.clone();
+        edited.set_password("new".to_string());
+        let trashed_a = trashed(&edited);
+        let changes = vec![
+            Change::Add(entry("D")),
+            Change::Edit {
+                before: Box::new(a.clone()),
+                after: Box::new(edited),
+            },
+            Change::Trash {
+                index: 0,
+                trashed: trashed_a.clone(),
+            },
+            Change::Restore {
+                index: 0,
+                trashed: vault.trash[0].clone(),
+            },
+            Change::Batch(vec![Change::Purge {
+                index: 0,
+                trashed: trashed_a,
+            }]),
+            Change::Folders {
+                before: Vec::new(),
+                after: vec![Folder {
+                    id: Uuid::new_v4(),
+                    name: "Work".to_string(),
+                    parent: None,
+                }],
+            },
+        ];
+
+        let mut history = History::default();
+        let mut states = vec![vault.clone()];
+        for change in changes {
+            history.apply(change, &mut vault);
+            states.push(vault.clone());
+        }
+        assert!(vault.trash.is_empty());
+        assert_eq!(vault.entries.len(), 3);
+        assert_eq!(vault.folders.len(), 1);
+
+        // Undo walks back through every state, redo forward again
+        for state in states.iter().rev().skip(1) {
+            history.undo(&mut vault).unwrap();
+            assert_eq!(&vault, state);
+        }
+        assert!(history.undo(&mut vault).is_none());
+        assert_eq!(vault, original);
+
+        for state in states.iter().skip(1) {
+            history.redo(&mut vault).unwrap();
+            assert_eq!(&vault, state);
+        }
+        assert!(history.redo(&mut vault).is_none());
+    }
+
+    #[test]
+    fn test_usage_survives_undo() {
+        let a = entry("A");
+        let mut vault = Vault::new();
+        vault.entries = vec![a.clone()];
+        let mut history = History::default();
+
+        let mut edited = a.clone();
+        edited.set_password("new".to_string());
+        history.apply(
+            Change::Edit {
+                before: Box::new(a.clone()),
+                after: Box::new(edited),
+            },
+            &mut vault,
+        );
+        vault.entries[0].record_use(Utc::now());
+        history.undo(&mut vault);
+        assert_eq!(vault.entries[0].password, "pass");
+        assert_eq!(vault.entries[0].use_count, 1);
+
+        history.redo(&mut vault);
+        history.apply(
+            Change::Trash {
+                index: 0,
+                trashed: trashed(&vault.entries[0]),
+            },
+            &mut vault,
+        );
+        history.undo(&mut vault);
+        vault.entries[0].record_use(Utc::now());
+        history.redo(&mut vault);
+        history.undo(&mut vault);
+        assert_eq!(vault.entries[0].password, "new");
+        assert_eq!(vault.entries[0].use_count, 2);
+        assert!(vault.entries[0].last_used.is_some());
+    }
+
+    #[test]
+    fn test_attachment_ids() {
+        let mut a = entry("A");
+        a.attachments.push(crate::model::Attachment {
+            id: Uuid::new_v4(),
+            name: "key".to_string(),
+            size: 0,
+            sha256: String::new(),
+            added: Utc::now(),
+        });
+        let mut vault = Vault::new();
+        vault.trash = vec![trashed(&a)];
+        let mut history = History::default();
+
+        history.apply(
+            Change::Batch(vec![Change::Purge {
+                index: 0,
+                trashed: trashed(&a),
+            }]),
+            &mut vault,
+        );
+        assert_eq!(vault.attachment_ids().count(), 0);
+        let ids: Vec<_> = history.attachment_ids().collect();
+        assert_eq!(ids, vec![a.attachments[0].id]);
+
+        history.undo(&mut vault);
+        assert_eq!(history.attachment_ids().count(), 1);
+        history.clear();
+        assert_eq!(history.attachment_ids().count(), 0);
+    }
+
+    #[test]
+    fn test_new_change_clears_redo() {
+        let mut vault = Vault::new();
+        let mut history = History::default();
+
+        history.apply(Change::Add(entry("A")), &mut vault);
+        assert_eq!(history.undo(&mut vault).unwrap().describe(), "add 'A'");
+        history.apply(Change::Add(entry("B")), &mut vault);
+        assert!(history.redo(&mut vault).is_none());
+
+        history.clear();
+        assert!(history.undo(&mut vault).is_none());
+        assert_eq!(vault.entries[0].name, "B");
+    }
+
+    #[test]
+    fn test_missing_entries_are_skipped() {
+        let a = entry("A");
+        let mut vault = Vault::new();
+        let mut history = History::default();
+        history.apply(
+            Change::Trash {
+                index: 0,
+                trashed: trashed(&a),
+            },
+            &mut vault,
+        );
+        assert!(vault.trash.is_empty());
+
+        // The trashed copy was purged behind the history's back
+        vault.entries.push(a.clone());
+        history.apply(
+            Change::Trash {
+                index: 0,
+                trashed: trashed(&a),
+            },
+            &mut vault,
+        );
+        vault.trash.clear();
+        history.undo(&mut vault);
+        assert!(vault.entries.is_empty());
+    }
+}
@@ -4,10 +4,16 @@
 //! - Firefox CSV (full export with 8+ columns)
 //! - Firefox/Chrome simple CSV (url,username,password)
 //! - JSON (our native format)
-//! - Generic CSV with flexible header detection
-
-use crate::model::{Entry, Vault};
+//! - Generic CSV with flexible header detection (Bitwarden and KeePassXC
+//!   exports included; their folders and groups become folders)
+
+use crate::migrate;
+use crate::model::{
+    CustomField, Entry, EntryKind, EntryUrl, Expiry, FieldKind, PreviousPassword, UrlMatch, Vault,
+};
+use crate::urlmatch;
 use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
 use std::fs;
 use std::path::Path;
 use uuid::Uuid;
@@ -15,23 +21,40 @@ use uuid::Uuid;
 #[derive(Debug, Clone)]
 pub struct ImportedEntry {
     pub name: String,
+    pub kind: EntryKind,
     pub username: String,
     pub password: String,
-    pub url: Option<String>,
+    pub password_history: Vec<PreviousPassword>,
+    /// When the password was set, if the source records it
+    pub password_changed: Option<DateTime<Utc>>,
+    pub expiry: Option<Expiry>,
+    pub urls: Vec<EntryUrl>,
     pub notes: Option<String>,
     pub tags: Vec<String>,
+    pub fields: Vec<CustomField>,
+    /// Folder path, e.g. `Work/Databases`
+    pub folder: Option<String>,
 }
 
 impl ImportedEntry {
-    pub fn to_entry(self) -> Entry {
-        Entry::new(
+    /// Build the entry, creating its folder in `vault` if needed
+    pub fn to_entry(self, vault: &mut Vault) -> Entry {
+        let mut entry = Entry::new(
             self.name,
             self.username,
             self.password,
-            self.url,
+            None,
             self.notes,
             self.tags,
-        )
+        );
+        entry.kind = self.kind;
+        entry.urls = self.urls;
+        entry.password_history = self.password_history;
+        entry.password_changed = self.password_changed.unwrap_or(entry.password_changed);
+        entry.expiry = self.expiry;
+        entry.fields = self.fields;
+        entry.folder = self.folder.and_then(|path| vault.create_folder_path(&path));
+        entry
     }
 }
 
@@ -67,19 +90,25 @@ pub fn import_from_file(path: &Path, vault: &Vault) -> Result<ImportPreview> {
 
 /// Import from JSON format
 fn import_json(contents: &str, vault: &Vault) -> Result<ImportPreview> {
-    let imported_vault: Vault = serde_json::from_str(contents)?;
+    let imported_vault = migrate::vault_from_json(contents.as_bytes())?;
 
     let mut entries = Vec::new();
     let mut duplicates = Vec::new();
 
-    for entry in imported_vault.entries {
+    for entry in &imported_vault.entries {
         let imported = ImportedEntry {
             name: entry.name.clone(),
+            kind: entry.kind.clone(),
             username: entry.username.clone(),
             password: entry.password.clone(),
-            url: entry.url.clone(),
+            password_history: entry.password_history.clone(),
+            password_changed: Some(entry.password_changed),
+            expiry: entry.expiry,
+            urls: entry.urls.clone(),
             notes: entry.notes.clone(),
             tags: entry.tags.clone(),
+            fields: entry.fields.clone(),
+            folder: entry.folder.map(|id| imported_vault.folder_path(&id)),
         };
 
         // Check for duplicates
@@ -100,20 +129,20 @@ fn import_json(contents: &str, vault: &Vault) -> Result<ImportPreview> {
 
 /// Import from CSV format (auto-detect variant)
 fn import_csv(contents: &str, vault: &Vault) -> Result<ImportPreview> {
-    let mut lines = contents.lines();
-    let header = lines.next().ok_or_else(|| anyhow!("Empty CSV file"))?;
+    let mut records = split_csv_records(contents);
+    let header = records.next().ok_or_else(|| anyhow!("Empty CSV file"))?;
 
     let format = detect_csv_format(header)?;
 
     let mut entries = Vec::new();
     let mut duplicates = Vec::new();
 
-    for line in lines {
-        if line.trim().is_empty() {
+    for record in records {
+        if record.trim().is_empty() {
             continue;
         }
 
-        let imported = parse_csv_line(line, &format)?;
+        let imported = parse_csv_line(record, &format)?;
 
         // Check for duplicates
         if let Some(dup) = find_duplicate(vault, &imported) {
@@ -146,7 +175,7 @@ enum CsvFormat {
         username_idx: usize,
         password_idx: usize,
     },
-    /// Extended: name,username,password,url,notes,tags
+    /// Extended: name,username,password,url,notes,tags,fields,kind,folder
     Extended {
         name_idx: Option<usize>,
         username_idx: usize,
@@ -154,6 +183,9 @@ enum CsvFormat {
         url_idx: Option<usize>,
         notes_idx: Option<usize>,
         tags_idx: Option<usize>,
+        fields_idx: Option<usize>,
+        kind_idx: Option<usize>,
+        folder_idx: Option<usize>,
     },
 }
 
@@ -186,11 +218,18 @@ fn detect_csv_format(header: &str) -> Result<CsvFormat> {
     // Extended format (flexible)
     Ok(CsvFormat::Extended {
         name_idx: find_header_idx(&headers, &["name", "title", "site"]).ok(),
-        username_idx: find_header_idx(&headers, &["username", "login", "email", "user"])?,
-        password_idx: find_header_idx(&headers, &["password", "pass", "pwd"])?,
-        url_idx: find_header_idx(&headers, &["url", "website", "site"]).ok(),
+        username_idx: find_header_idx(
+            &headers,
+            &["username", "login", "email", "user", "login_username"],
+        )?,
+        password_idx: find_header_idx(&headers, &["password", "pass", "pwd", "login_password"])?,
+        url_idx: find_header_idx(&headers, &["url", "website", "site", "login_uri"]).ok(),
         notes_idx: find_header_idx(&headers, &["notes", "note", "comment", "comments"]).ok(),
         tags_idx: find_header_idx(&headers, &["tags", "tag", "labels", "categories"]).ok(),
+        fields_idx: find_header_idx(&headers, &["fields"]).ok(),
+        kind_idx: find_header_idx(&headers, &["kind"]).ok(),
+        // Bitwarden has folders, KeePass groups
+        folder_idx: find_header_idx(&headers, &["folder", "group"]).ok(),
     })
 }
 
@@ -229,14 +268,21 @@ fn parse_csv_line(line: &str, format: &CsvFormat) -> Result<ImportedEntry> {
         } => {
             let url = fields.get(*url_idx).map(|s| s.to_string());
             let name = generate_name_from_url(url.as_deref().unwrap_or(""));
+            let urls = url.filter(|url| !url.is_empty()).map(EntryUrl::new);
 
             Ok(ImportedEntry {
                 name,
+                kind: EntryKind::Login,
                 username: fields.get(*username_idx).cloned().unwrap_or_default(),
                 password: fields.get(*password_idx).cloned().unwrap_or_default(),
-                url,
+                password_history: Vec::new(),
+                password_changed: None,
+                expiry: None,
+                urls: urls.into_iter().collect(),
                 notes: None,
                 tags: Vec::new(),
+                fields: Vec::new(),
+                folder: None,
             })
         }
         CsvFormat::Extended {
@@ -246,15 +292,20 @@ fn parse_csv_line(line: &str, format: &CsvFormat) -> Result<ImportedEntry> {
             url_idx,
             notes_idx,
             tags_idx,
+            fields_idx,
+            kind_idx,
+            folder_idx,
         } => {
+            // Several URLs are separated by spaces, as written by `export csv`
             let url = url_idx.and_then(|idx| fields.get(idx).map(|s| s.to_string()));
+            let main_url = url.as_deref().unwrap_or("").split_whitespace().next();
             let name = if let Some(idx) = name_idx {
                 fields
                     .get(*idx)
                     .map(|s| s.to_string())
-                    .unwrap_or_else(|| generate_name_from_url(url.as_deref().unwrap_or("")))
+                    .unwrap_or_else(|| generate_name_from_url(main_url.unwrap_or("")))
             } else {
-                generate_name_from_url(url.as_deref().unwrap_or(""))
+                generate_name_from_url(main_url.unwrap_or(""))
             };
 
             let tags = if let Some(idx) = tags_idx {
@@ -266,18 +317,88 @@ fn parse_csv_line(line: &str, format: &CsvFormat) -> Result<ImportedEntry> {
                 Vec::new()
             };
 
+            // Custom fields are a JSON array, as written by `export csv`, or
+            // `label: value` lines as written by Bitwarden
+            let custom_fields = match fields_idx.and_then(|idx| fields.get(idx)) {
+                Some(json) if json.trim_start().starts_with('[') => serde_json::from_str(json)
+                    .map_err(|e| anyhow!("Invalid custom fields for '{}': {}", name, e))?,
+                Some(lines) => lines
+                    .lines()
+                    .filter_map(|line| line.split_once(':'))
+                    .map(|(label, value)| CustomField {
+                        label: label.trim().to_string(),
+                        value: value.trim().to_string(),
+                        kind: FieldKind::Text,
+                    })
+                    .collect(),
+                None => Vec::new(),
+            };
+
+            // Entries that aren't logins carry their kind as a JSON object
+            let kind = match kind_idx.and_then(|idx| fields.get(idx)) {
+                Some(json) if !json.trim().is_empty() => serde_json::from_str(json)
+                    .map_err(|e| anyhow!("Invalid entry kind for '{}': {}", name, e))?,
+                _ => EntryKind::Login,
+            };
+
             Ok(ImportedEntry {
                 name,
+                kind,
                 username: fields.get(*username_idx).cloned().unwrap_or_default(),
                 password: fields.get(*password_idx).cloned().unwrap_or_default(),
-                url,
+                password_history: Vec::new(),
+                password_changed: None,
+                expiry: None,
+                urls: EntryUrl::parse_list(url.as_deref().unwrap_or("")),
                 notes: notes_idx.and_then(|idx| fields.get(idx).map(|s| s.to_string())),
                 tags,
+                fields: custom_fields,
+                folder: folder_idx
+                    .and_then(|idx| fields.get(idx))
+                    .and_then(|group| folder_from_group(group)),
             })
         }
     }
 }
 
+/// Folder path of a folder or group column
+///
+/// KeePass group paths start with the database's root group, which has no
+/// counterpart here and is dropped.
+fn folder_from_group(group: &str) -> Option<String> {
+    let path = group.trim().trim_matches('/');
+    let path = match path.split_once('/') {
+        Some((root, rest)) if root.eq_ignore_ascii_case("root") => rest,
+        _ if path.eq_ignore_ascii_case("root") => "",
+        _ => path,
+    };
+    (!path.is_empty()).then(|| path.to_string())
+}
+
+/// Split CSV into records: lines, except that quoted cells may span lines
+fn split_csv_records(contents: &str) -> impl Iterator<Item = &str> {
+    let mut rest = contents;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let mut in_quotes = false;
+        let end = rest
+            .char_indices()
+            .find(|&(_, ch)| {
+                if ch == '"' {
+                    // An escaped quote toggles twice
+                    in_quotes = !in_quotes;
+                }
+                ch == '\n' && !in_quotes
+            })
+            .map_or(rest.len(), |(i, _)| i);
+        let record = &rest[..end];
+        rest = rest.get(end + 1..).unwrap_or("");
+        Some(record.strip_suffix('\r').unwrap_or(record))
+    })
+}
+
 /// Parse CSV fields (handles quoted fields)
 fn parse_csv_fields(line: &str) -> Vec<String> {
     let mut fields = Vec::new();
@@ -338,11 +459,21 @@ fn generate_name_from_url(url: &str) -> String {
 
 /// Find duplicate entry in vault
 fn find_duplicate(vault: &Vault, imported: &ImportedEntry) -> Option<DuplicateInfo> {
+    // Imported URLs are looked up under the existing entries' match rules
+    let addresses: Vec<_> = imported
+        .urls
+        .iter()
+        .filter(|url| url.rule != UrlMatch::Regex)
+        .filter_map(|url| urlmatch::parse_address(&url.url).ok())
+        .collect();
+
     for entry in &vault.entries {
         // Consider it a duplicate if username and URL match
-        let urls_match = match (&entry.url, &imported.url) {
-            (Some(a), Some(b)) => a.to_lowercase() == b.to_lowercase(),
-            (None, None) => true,
+        let urls_match = match (entry.urls.is_empty(), imported.urls.is_empty()) {
+            (true, true) => true,
+            (false, false) => addresses
+                .iter()
+                .any(|address| urlmatch::entry_match(entry, address).is_some()),
             _ => false,
         };
 
@@ -386,6 +517,187 @@ mod tests {
         assert_eq!(fields, vec!["has,comma", "has\"quote", "normal"]);
     }
 
+    #[test]
+    fn test_json_keeps_password_history() {
+        let mut entry = Entry::new(
+            "Site".to_string(),
+            "user".to_string(),
+            "old".to_string(),
+            None,
+            None,
+            vec![],
+        );
+        entry.set_password("new".to_string());
+        entry.expiry = Some(Expiry::Every { days: 90 });
+        let changed = entry.password_changed;
+        let mut exported = Vault::new();
+        exported.add_entry(entry);
+        let json = serde_json::to_string(&exported).unwrap();
+
+        let preview = import_json(&json, &Vault::new()).unwrap();
+        let imported = preview.entries[0].clone().to_entry(&mut Vault::new());
+        assert_eq!(imported.password, "new");
+        assert_eq!(imported.password_history[0].password, "old");
+        assert_eq!(imported.expiry, Some(Expiry::Every { days: 90 }));
+        assert_eq!(imported.password_changed, changed);
+    }
+
+    #[test]
+    fn test_custom_fields_round_trip() {
+        use crate::export::{export_to_file, ExportFormat};
+        use crate::model::FieldKind;
+
+        let mut entry = Entry::new(
+            "Bank".to_string(),
+            "user".to_string(),
+            "pass".to_string(),
+            None,
+            None,
+            vec![],
+        );
+        entry.fields = vec![
+            CustomField {
+                label: "PIN".to_string(),
+                value: "1234".to_string(),
+                kind: FieldKind::Hidden,
+            },
+            CustomField {
+                label: "Question, \"first pet\"".to_string(),
+                value: "Rex".to_string(),
+                kind: FieldKind::Text,
+            },
+        ];
+        let mut vault = Vault::new();
+        vault.add_entry(entry.clone());
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        for format in [ExportFormat::Json, ExportFormat::CsvExtended] {
+            let path = temp_dir.path().join(format.as_str());
+            export_to_file(&vault, &path, format).unwrap();
+            let preview = import_from_file(&path, &Vault::new()).unwrap();
+            assert_eq!(preview.entries[0].fields, entry.fields);
+        }
+    }
+
+    #[test]
+    fn test_entry_kinds_round_trip() {
+        use crate::export::{export_to_file, ExportFormat};
+        use crate::model::Card;
+
+        let mut entry = Entry::new(
+            "Visa".to_string(),
+            String::new(),
+            String::new(),
+            None,
+            None,
+            vec![],
+        );
+        entry.kind = EntryKind::Card(Card {
+            holder: "Jane Doe".to_string(),
+            number: "4111 1111 1111 1111".to_string(),
+            expiry: "08/29".to_string(),
+            cvv: "123".to_string(),
+        });
+        let mut vault = Vault::new();
+        vault.add_entry(entry.clone());
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        for format in [ExportFormat::Json, ExportFormat::CsvExtended] {
+            let path = temp_dir.path().join(format.as_str());
+            assert_eq!(export_to_file(&vault, &path, format).unwrap(), 1);
+            let preview = import_from_file(&path, &Vault::new()).unwrap();
+            assert_eq!(preview.entries[0].kind, entry.kind);
+        }
+
+        let path = temp_dir.path().join("firefox");
+        assert_eq!(
+            export_to_file(&vault, &path, ExportFormat::Firefox).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_folder_columns() {
+        let bitwarden = "folder,favorite,type,name,notes,fields,reprompt,login_uri,login_username,login_password,login_totp\n\
+            Work/DB,,login,Postgres,,Port: 5432,0,https://db.example.com,admin,secret,\n";
+        let keepass = "\"Group\",\"Title\",\"Username\",\"Password\",\"URL\",\"Notes\"\n\
+            \"Root/Work/DB\",\"Postgres\",\"admin\",\"secret\",\"\",\"\"\n\
+            \"Root\",\"Top\",\"me\",\"pw\",\"\",\"\"\n";
+
+        let mut vault = Vault::new();
+        for csv in [bitwarden, keepass] {
+            let preview = import_csv(csv, &vault).unwrap();
+            let first = preview.entries[0].clone();
+            assert_eq!(first.folder.as_deref(), Some("Work/DB"));
+            assert_eq!(first.password, "secret");
+            let entry = first.to_entry(&mut vault);
+            assert_eq!(vault.folder_path(&entry.folder.unwrap()), "Work/DB");
+        }
+        // Both landed in the same folder
+        assert_eq!(vault.folders.len(), 2);
+
+        let preview = import_csv(bitwarden, &vault).unwrap();
+        assert_eq!(preview.entries[0].fields[0].label, "Port");
+        let preview = import_csv(keepass, &vault).unwrap();
+        assert_eq!(preview.entries[1].folder, None);
+    }
+
+    #[test]
+    fn test_multiline_cells() {
+        let bitwarden = "folder,favorite,type,name,notes,fields,reprompt,login_uri,login_username,login_password,login_totp\r\n\
+            ,,login,Postgres,\"Primary\r\nReplica: db2\",\"Port: 5432\r\nDatabase: app\r\nSchema: \"\"public\"\"\",0,https://db.example.com,admin,secret,\r\n\
+            ,,login,Redis,,\"Port: 6379\nTLS: yes\",0,,cache,pw,\r\n";
+
+        let preview = import_csv(bitwarden, &Vault::new()).unwrap();
+        assert_eq!(preview.total_entries, 2);
+
+        let postgres = &preview.entries[0];
+        assert_eq!(postgres.notes.as_deref(), Some("Primary\r\nReplica: db2"));
+        assert_eq!(postgres.password, "secret");
+        let fields: Vec<_> = postgres
+            .fields
+            .iter()
+            .map(|f| (f.label.as_str(), f.value.as_str()))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                ("Port", "5432"),
+                ("Database", "app"),
+                ("Schema", "\"public\"")
+            ]
+        );
+
+        let redis = &preview.entries[1];
+        assert_eq!(redis.name, "Redis");
+        assert_eq!(redis.fields.len(), 2);
+        assert_eq!(redis.fields[1].value, "yes");
+    }
+
+    #[test]
+    fn test_duplicates_use_url_rules() {
+        let mut vault = Vault::new();
+        let mut existing = Entry::new(
+            "Example".to_string(),
+            "me".to_string(),
+            "pw".to_string(),
+            None,
+            None,
+            vec![],
+        );
+        existing.urls = EntryUrl::parse_list("example.co.uk host:sso.example.net");
+        vault.add_entry(existing);
+
+        let csv = "url,username,password\n\
+            \"https://login.example.co.uk/path\",\"me\",\"pw\"\n\
+            \"https://sso.example.net/\",\"me\",\"pw\"\n\
+            \"https://other.example.net/\",\"me\",\"pw\"\n\
+            \"https://login.example.co.uk/\",\"you\",\"pw\"\n";
+        let preview = import_csv(csv, &vault).unwrap();
+        assert_eq!(preview.duplicates.len(), 2);
+        assert_eq!(preview.new_entries, 2);
+    }
+
     #[test]
     fn test_detect_firefox_simple() {
         let header = "url,username,password";
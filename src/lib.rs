@@ -1,8 +1,19 @@
 //! Password manager library
 
 pub mod app;
+pub mod attachment;
+pub mod backup;
+pub mod config;
 pub mod crypto;
 pub mod export;
+pub mod history;
 pub mod import;
+pub mod lock;
+pub mod merge;
+pub mod migrate;
 pub mod model;
+pub mod otp;
+pub mod payload;
+pub mod shamir;
 pub mod storage;
+pub mod urlmatch;
@@ -0,0 +1,244 @@
+//! Cross-process locking of the vault file
+//!
+//! A TUI session holds `vault.enc.lock`, containing its PID, for as long as
+//! it has the vault open. The lock is advisory: other passmngr processes
+//! check it before writing.
+//!
+//! On Unix the lock is an `flock` on that file, so the kernel releases it
+//! when the holder exits, however it exits; a leftover file is simply locked
+//! again and its PID overwritten. Elsewhere the file is created atomically
+//! (written under a unique name, then hard-linked into place) and never
+//! taken over: a leftover file has to be removed by hand.
+
+use anyhow::{anyhow, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How long to wait for a new holder to write its PID
+const PID_WAIT: Duration = Duration::from_millis(1000);
+
+/// Held lock on a vault, released on drop
+#[derive(Debug)]
+pub struct VaultLock {
+    path: PathBuf,
+    /// Open lock file; on Unix closing it releases the `flock`
+    _file: File,
+}
+
+/// Outcome of trying to lock a vault
+#[derive(Debug)]
+pub enum LockState {
+    Acquired(VaultLock),
+    /// Another live process holds the lock
+    InUse {
+        pid: u32,
+    },
+}
+
+impl VaultLock {
+    /// Lock file path for a vault
+    pub fn lock_path(vault_path: &Path) -> PathBuf {
+        let mut name = vault_path
+            .file_name()
+            .map(|n| n.to_os_string())
+            .unwrap_or_else(|| "vault".into());
+        name.push(".lock");
+        vault_path.with_file_name(name)
+    }
+
+    /// Try to lock the vault
+    pub fn try_acquire(vault_path: &Path) -> Result<LockState> {
+        let path = Self::lock_path(vault_path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        match lock_file(&path)? {
+            Some(file) => Ok(LockState::Acquired(VaultLock { path, _file: file })),
+            None => Ok(LockState::InUse {
+                pid: holder_pid(&path)?,
+            }),
+        }
+    }
+
+    /// Lock the vault, failing if another process has it open
+    pub fn acquire(vault_path: &Path) -> Result<Self> {
+        match Self::try_acquire(vault_path)? {
+            LockState::Acquired(lock) => Ok(lock),
+            LockState::InUse { pid } => Err(anyhow!(
+                "Vault in use by PID {} (lock file {})",
+                pid,
+                Self::lock_path(vault_path).display()
+            )),
+        }
+    }
+}
+
+impl Drop for VaultLock {
+    fn drop(&mut self) {
+        // Removed while still locked; see `lock_file` for late openers
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Lock `path` and write this process's PID into it; `None` if another
+/// process holds it
+#[cfg(unix)]
+fn lock_file(path: &Path) -> Result<Option<File>> {
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::io::AsRawFd;
+
+    loop {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let e = std::io::Error::last_os_error();
+            return match e.kind() {
+                ErrorKind::WouldBlock => Ok(None),
+                _ => Err(e.into()),
+            };
+        }
+
+        // The previous holder may have removed the file between our open and
+        // our lock; then we hold a lock nobody else can see, so start over
+        let opened = file.metadata()?;
+        match fs::metadata(path) {
+            Ok(current) if (current.dev(), current.ino()) == (opened.dev(), opened.ino()) => {}
+            Ok(_) => continue,
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        }
+
+        file.set_len(0)?;
+        writeln!(file, "{}", std::process::id())?;
+        file.sync_all()?;
+        return Ok(Some(file));
+    }
+}
+
+/// Create `path` holding this process's PID; `None` if it already exists
+///
+/// The PID is written to a unique file first and hard-linked into place, so
+/// the lock file is never seen empty.
+#[cfg(not(unix))]
+fn lock_file(path: &Path) -> Result<Option<File>> {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}.tmp", uuid::Uuid::new_v4()));
+    let temp = PathBuf::from(name);
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp)?;
+    writeln!(file, "{}", std::process::id())?;
+    file.sync_all()?;
+    let linked = fs::hard_link(&temp, path);
+    fs::remove_file(&temp)?;
+
+    match linked {
+        Ok(()) => Ok(Some(File::open(path)?)),
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// PID of the process holding the lock at `path`
+///
+/// A new holder writes it right after locking, so an empty file is read
+/// again for a moment; a file that stays unreadable is an error, never a
+/// reason to take the lock.
+fn holder_pid(path: &Path) -> Result<u32> {
+    let start = std::time::Instant::now();
+    loop {
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                if let Ok(pid) = contents.trim().parse() {
+                    return Ok(pid);
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        if start.elapsed() > PID_WAIT {
+            return Err(anyhow!(
+                "Vault is locked, but lock file {} holds no PID",
+                path.display()
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_lock_excludes_and_releases() {
+        let temp_dir = TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("vault.enc");
+
+        let lock = VaultLock::acquire(&vault_path).unwrap();
+        assert!(VaultLock::lock_path(&vault_path).exists());
+
+        match VaultLock::try_acquire(&vault_path).unwrap() {
+            LockState::InUse { pid } => assert_eq!(pid, std::process::id()),
+            LockState::Acquired(_) => panic!("lock acquired twice"),
+        }
+        let err = VaultLock::acquire(&vault_path).unwrap_err();
+        assert!(err.to_string().contains("in use by PID"));
+
+        drop(lock);
+        assert!(!VaultLock::lock_path(&vault_path).exists());
+        assert!(VaultLock::acquire(&vault_path).is_ok());
+    }
+
+    #[test]
+    fn test_leftover_lock_file_is_reused() {
+        let temp_dir = TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("vault.enc");
+        let lock_path = VaultLock::lock_path(&vault_path);
+
+        // Left behind by a crashed process: unlocked, so taken regardless
+        // of what it holds
+        for contents in ["2147483647\n", "", "not a pid"] {
+            fs::write(&lock_path, contents).unwrap();
+            let _lock = VaultLock::acquire(&vault_path).unwrap();
+            assert_eq!(
+                fs::read_to_string(&lock_path).unwrap().trim(),
+                std::process::id().to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_concurrent_acquire() {
+        let temp_dir = TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("vault.enc");
+        fs::write(VaultLock::lock_path(&vault_path), "").unwrap();
+
+        // Each thread opens its own file description, like separate processes
+        let barrier = std::sync::Barrier::new(8);
+        let acquired: Vec<bool> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    scope.spawn(|| {
+                        barrier.wait();
+                        let path = VaultLock::lock_path(&vault_path);
+                        lock_file(&path).unwrap().is_some()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert_eq!(acquired.iter().filter(|a| **a).count(), 1);
+    }
+}
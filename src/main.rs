@@ -1,7 +1,7 @@
 mod ui;
 
 use anyhow::Result;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use crossterm::{
     event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
     execute,
@@ -9,19 +9,40 @@ use crossterm::{
 };
 use passmngr::{
     app::{App, Mode},
+    attachment, backup,
+    config::{Config, VaultTarget, DEFAULT_VAULT},
+    crypto::{
+        benchmark_kdf, calibrate_kdf, validate_master_password, CipherAlgorithm, KdfCost, Keyfile,
+        Session,
+    },
     export::{export_to_file, ExportFormat},
     import::import_from_file,
-    model::Vault,
+    lock::{LockState, VaultLock},
+    merge::Side,
+    model::{self, Entry, Vault},
+    otp::{self, Otp, OtpKind},
+    shamir::Share,
     storage::VaultFile,
+    urlmatch,
 };
 use ratatui::{backend::CrosstermBackend, Terminal};
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use uuid::Uuid;
 
 #[derive(Parser)]
 #[command(name = "passmngr")]
 #[command(about = "A fast, minimal TUI password manager", long_about = None)]
 struct Cli {
+    /// Vault to open: a name from `vaults list` or a path
+    #[arg(long, global = true, env = "PASSMNGR_VAULT", value_name = "NAME|PATH")]
+    vault: Option<String>,
+
+    /// Keyfile used as second unlock factor
+    #[arg(long, global = true, value_name = "PATH")]
+    keyfile: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -48,41 +69,484 @@ enum Commands {
         #[arg(short, long)]
         skip_duplicates: bool,
     },
+    /// Create a new vault
+    Init {
+        #[command(flatten)]
+        kdf: KdfArgs,
+
+        /// Cipher: xchacha20poly1305 (default) or aes-256-gcm-siv
+        #[arg(long, value_name = "ALGORITHM")]
+        cipher: Option<String>,
+    },
+    /// Change the master password (re-encrypts the vault with a fresh salt)
+    Passwd {
+        #[command(flatten)]
+        kdf: KdfArgs,
+
+        /// Cipher: xchacha20poly1305 (default) or aes-256-gcm-siv
+        #[arg(long, value_name = "ALGORITHM")]
+        cipher: Option<String>,
+
+        /// Require this keyfile from now on (replaces the current one)
+        #[arg(long, value_name = "PATH", conflicts_with = "remove_keyfile")]
+        new_keyfile: Option<PathBuf>,
+
+        /// Stop requiring a keyfile
+        #[arg(long)]
+        remove_keyfile: bool,
+    },
+    /// Manage named vaults
+    Vaults {
+        #[command(subcommand)]
+        command: VaultCommands,
+    },
+    /// List or restore backups of the vault
+    Backups {
+        #[command(subcommand)]
+        command: BackupCommands,
+    },
+    /// Manage encrypted file attachments of an entry
+    Attach {
+        #[command(subcommand)]
+        command: AttachCommands,
+    },
+    /// Print the current one-time code of an entry (advances HOTP counters)
+    Totp {
+        /// Entry name, folder/name path or ID
+        #[arg(value_name = "ENTRY")]
+        entry: String,
+    },
+    /// List the entries for a web address, best match first
+    Find {
+        /// Address of the page, e.g. https://login.example.co.uk/path
+        #[arg(long, value_name = "URL")]
+        url: String,
+    },
+    /// List passwords that have expired or expire soon (exits 1 if any expired)
+    Due {
+        /// How far ahead to look: days, or e.g. 2w, 3m
+        #[arg(long, value_name = "DURATION", default_value = "14d", value_parser = parse_within)]
+        within: u32,
+    },
+    /// Set up or use recovery shares for a forgotten master password
+    Recovery {
+        #[command(subcommand)]
+        command: RecoveryCommands,
+    },
+    /// Manage keyfiles
+    Keyfile {
+        #[command(subcommand)]
+        command: KeyfileCommands,
+    },
+    /// Measure key derivation on this machine and suggest KDF parameters
+    KdfBenchmark {
+        /// Target unlock time in milliseconds
+        #[arg(long, default_value_t = 1000)]
+        target_ms: u64,
+
+        /// Number of Argon2 lanes
+        #[arg(long, default_value_t = KdfCost::default().parallelism)]
+        parallelism: u32,
+    },
+}
+
+#[derive(Subcommand)]
+enum VaultCommands {
+    /// List known vaults
+    List,
+    /// Create a new named vault
+    Create {
+        /// Vault name
+        #[arg(value_name = "NAME")]
+        name: String,
+
+        /// Vault file location (default: next to the default vault)
+        #[arg(long, value_name = "PATH")]
+        path: Option<PathBuf>,
+
+        #[command(flatten)]
+        kdf: KdfArgs,
+
+        /// Cipher: xchacha20poly1305 (default) or aes-256-gcm-siv
+        #[arg(long, value_name = "ALGORITHM")]
+        cipher: Option<String>,
+    },
+    /// Forget a named vault (the file is kept unless --delete is given)
+    Remove {
+        /// Vault name
+        #[arg(value_name = "NAME")]
+        name: String,
+
+        /// Also delete the vault file
+        #[arg(long)]
+        delete: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum BackupCommands {
+    /// List backup generations, newest first
+    List,
+    /// Replace the vault with a backup generation
+    Restore {
+        /// Generation number as shown by `backups list`
+        #[arg(value_name = "N")]
+        number: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum AttachCommands {
+    /// Attach a file to an entry
+    Add {
+        /// Entry name, folder/name path or ID
+        #[arg(value_name = "ENTRY")]
+        entry: String,
+
+        /// File to attach
+        #[arg(value_name = "PATH")]
+        path: PathBuf,
+
+        /// Attachment name (default: the file name)
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Save an attachment to a file (readable only by you)
+    Get {
+        /// Entry name, folder/name path or ID
+        #[arg(value_name = "ENTRY")]
+        entry: String,
+
+        /// Attachment name or number
+        #[arg(value_name = "ATTACHMENT")]
+        attachment: String,
+
+        /// Output file (default: the attachment name in the current directory)
+        #[arg(value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Remove an attachment
+    Rm {
+        /// Entry name, folder/name path or ID
+        #[arg(value_name = "ENTRY")]
+        entry: String,
+
+        /// Attachment name or number
+        #[arg(value_name = "ATTACHMENT")]
+        attachment: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum RecoveryCommands {
+    /// Split a recovery key into shares (replaces earlier shares)
+    Setup {
+        /// Number of shares to print
+        #[arg(long, default_value_t = 5)]
+        shares: u8,
+
+        /// Shares needed to recover
+        #[arg(long, default_value_t = 3)]
+        threshold: u8,
+    },
+    /// Unlock with recovery shares and set a new master password
+    Unlock,
+}
+
+#[derive(Subcommand)]
+enum KeyfileCommands {
+    /// Write a new random keyfile
+    Generate {
+        /// Output file path
+        #[arg(value_name = "PATH")]
+        path: PathBuf,
+    },
+}
+
+/// Argon2id cost overrides
+#[derive(Args)]
+struct KdfArgs {
+    /// Argon2id iterations
+    #[arg(long, value_name = "N")]
+    kdf_time: Option<u32>,
+
+    /// Argon2id memory in MiB
+    #[arg(long, value_name = "MIB")]
+    kdf_memory: Option<u32>,
+
+    /// Argon2id lanes
+    #[arg(long, value_name = "N")]
+    kdf_parallelism: Option<u32>,
+}
+
+impl KdfArgs {
+    /// Apply the overrides on top of `base`; `None` if nothing was given
+    fn cost(&self, base: KdfCost) -> Option<KdfCost> {
+        if self.kdf_time.is_none() && self.kdf_memory.is_none() && self.kdf_parallelism.is_none() {
+            return None;
+        }
+
+        Some(KdfCost {
+            time_cost: self.kdf_time.unwrap_or(base.time_cost),
+            memory_cost: self
+                .kdf_memory
+                .map(|mib| mib.saturating_mul(1024))
+                .unwrap_or(base.memory_cost),
+            parallelism: self.kdf_parallelism.unwrap_or(base.parallelism),
+        })
+    }
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
+    let mut config = Config::load()?;
+
+    // Vault management works without resolving the selected vault
+    if let Some(Commands::Vaults { command }) = cli.command {
+        return handle_vaults_command(command, cli.vault.as_deref(), cli.keyfile, &mut config);
+    }
+
+    let target = config.resolve_vault(cli.vault.as_deref())?;
+
     // Handle CLI commands
     if let Some(command) = cli.command {
-        return handle_cli_command(command);
+        return handle_cli_command(command, target, cli.keyfile, config);
     }
 
     // No command - run TUI
-    run_tui()
+    run_tui(target, cli.keyfile, config)
+}
+
+/// Warn when the vault was written by a newer passmngr
+fn warn_if_newer(vault: &Vault) {
+    if vault.is_from_newer_version() {
+        eprintln!(
+            "Warning: vault was written by a newer passmngr (schema v{}).",
+            vault.version
+        );
+        eprintln!("         Fields unknown to this version are ignored and saving is disabled.");
+    }
+}
+
+/// Find an entry by ID, (case-insensitive) name or `folder/name` path
+fn find_entry(vault: &Vault, selector: &str) -> Result<Uuid> {
+    if let Some(entry) = Uuid::parse_str(selector)
+        .ok()
+        .and_then(|id| vault.get_entry(&id))
+    {
+        return Ok(entry.id);
+    }
+
+    let mut matches: Vec<_> = vault
+        .entries
+        .iter()
+        .filter(|e| e.name.eq_ignore_ascii_case(selector))
+        .collect();
+    // Names may contain '/' themselves, so paths are only tried second
+    if let Some((path, name)) = selector.rsplit_once('/').filter(|_| matches.is_empty()) {
+        let folder = vault.find_folder(path);
+        if folder.is_some() || path.trim_matches('/').is_empty() {
+            matches = vault
+                .entries
+                .iter()
+                .filter(|e| e.folder == folder && e.name.eq_ignore_ascii_case(name))
+                .collect();
+        }
+    }
+    match matches.as_slice() {
+        [entry] => Ok(entry.id),
+        [] => Err(anyhow::anyhow!("No entry named '{}'", selector)),
+        _ => {
+            let ids: Vec<_> = matches.iter().map(|e| e.id.to_string()).collect();
+            Err(anyhow::anyhow!(
+                "Several entries are named '{}'; use one of their IDs: {}",
+                selector,
+                ids.join(", ")
+            ))
+        }
+    }
 }
 
-fn handle_cli_command(command: Commands) -> Result<()> {
-    let vault_path = VaultFile::default_path()?;
+/// An entry's name with its folder path, as accepted by `find_entry`
+fn entry_path(vault: &Vault, entry: &Entry) -> String {
+    match entry.folder {
+        Some(folder) => format!("{}/{}", vault.folder_path(&folder), entry.name),
+        None => entry.name.clone(),
+    }
+}
+
+/// Read the keyfile given on the command line, if any
+fn read_keyfile(path: Option<&Path>) -> Result<Option<Keyfile>> {
+    path.map(Keyfile::read).transpose()
+}
+
+/// Parse a `--cipher` argument
+fn parse_cipher(name: Option<&str>) -> Result<Option<CipherAlgorithm>> {
+    name.map(CipherAlgorithm::parse).transpose()
+}
+
+/// Parse a `--within` argument
+fn parse_within(text: &str) -> Result<u32, String> {
+    model::parse_days(text).ok_or_else(|| {
+        format!(
+            "'{}' is not a number of days (up to {} years)",
+            text,
+            model::MAX_DAYS / 365
+        )
+    })
+}
+
+/// Create a new empty vault at `path`
+fn create_vault(
+    path: &Path,
+    kdf: &KdfArgs,
+    cipher: Option<&str>,
+    keyfile: Option<&Keyfile>,
+    config: &Config,
+) -> Result<()> {
+    let cipher = parse_cipher(cipher)?.unwrap_or_default();
+    let _lock = VaultLock::acquire(path)?;
+    if VaultFile::exists(path) {
+        return Err(anyhow::anyhow!(
+            "Vault already exists at {}",
+            path.display()
+        ));
+    }
+
+    let cost = kdf.cost(KdfCost::default()).unwrap_or_default();
+    cost.validate()?;
+
+    let password = prompt_new_password()?;
+    let mut session = Session::new(&password, keyfile, cost)?;
+    session.set_cipher(cipher);
+    VaultFile::save(path, &Vault::new(), &session, &config.backups)?;
+
+    Ok(())
+}
+
+fn handle_vaults_command(
+    command: VaultCommands,
+    selector: Option<&str>,
+    keyfile_path: Option<PathBuf>,
+    config: &mut Config,
+) -> Result<()> {
+    match command {
+        VaultCommands::List => {
+            let active = config.resolve_vault(selector).ok();
+            let default = config.resolve_vault(Some(DEFAULT_VAULT))?;
+            let targets =
+                std::iter::once(default).chain(config.vaults.iter().map(|(name, path)| {
+                    VaultTarget {
+                        name: name.clone(),
+                        path: path.clone(),
+                    }
+                }));
+
+            for target in targets {
+                println!(
+                    "{} {:<16} {}{}",
+                    if active.as_ref() == Some(&target) {
+                        "*"
+                    } else {
+                        " "
+                    },
+                    target.name,
+                    target.path.display(),
+                    if VaultFile::exists(&target.path) {
+                        ""
+                    } else {
+                        "  (missing)"
+                    }
+                );
+            }
+
+            Ok(())
+        }
+        VaultCommands::Create {
+            name,
+            path,
+            kdf,
+            cipher,
+        } => {
+            let keyfile = read_keyfile(keyfile_path.as_deref())?;
+            let target = config.add_vault(&name, path)?;
+
+            create_vault(
+                &target.path,
+                &kdf,
+                cipher.as_deref(),
+                keyfile.as_ref(),
+                config,
+            )?;
+            config.save()?;
+
+            println!("✓ Created vault '{}' at {}", name, target.path.display());
+            println!("  Open it with: passmngr --vault {}", name);
+
+            Ok(())
+        }
+        VaultCommands::Remove { name, delete } => {
+            let path = config.remove_vault(&name)?;
+
+            if delete && VaultFile::exists(&path) {
+                let _lock = VaultLock::acquire(&path)?;
+                println!("This permanently deletes {}", path.display());
+                println!("Type the vault name to confirm:");
+                let mut input = String::new();
+                io::stdin().read_line(&mut input)?;
+                if input.trim() != name {
+                    return Err(anyhow::anyhow!("Aborted"));
+                }
+                std::fs::remove_file(&path)?;
+            }
+            config.save()?;
+
+            println!("✓ Removed vault '{}'", name);
+            if !delete {
+                println!("  The file was kept at {}", path.display());
+            } else {
+                println!(
+                    "  Backups, if any, remain in {}",
+                    backup::backup_dir(&path).display()
+                );
+            }
+
+            Ok(())
+        }
+    }
+}
+
+fn handle_cli_command(
+    command: Commands,
+    target: VaultTarget,
+    keyfile_path: Option<PathBuf>,
+    config: Config,
+) -> Result<()> {
+    let vault_path = target.path;
+    let keyfile = read_keyfile(keyfile_path.as_deref())?;
 
     match command {
         Commands::Export { format, path } => {
             // Load vault
             let password = prompt_password("Enter master password: ")?;
-            let vault = VaultFile::load(&vault_path, &password)?;
+            let vault = VaultFile::load(&vault_path, &password, keyfile.as_ref())?;
+            warn_if_newer(&vault);
 
             // Parse format
             let export_format = ExportFormat::parse_format(&format)
                 .ok_or_else(|| anyhow::anyhow!("Invalid format. Use: firefox, json, or csv"))?;
 
             // Export
-            export_to_file(&vault, &path, export_format)?;
-
-            println!(
-                "✓ Exported {} entries to {}",
-                vault.entries.len(),
-                path.display()
-            );
+            let count = export_to_file(&vault, &path, export_format)?;
+
+            println!("✓ Exported {} entries to {}", count, path.display());
+            if count < vault.entries.len() {
+                println!(
+                    "   Skipped {} entries that aren't logins (use json or csv to keep them)",
+                    vault.entries.len() - count
+                );
+            }
             println!("⚠️  WARNING: File contains PLAINTEXT passwords!");
             println!("   Delete it after use: rm {}", path.display());
 
@@ -92,9 +556,12 @@ fn handle_cli_command(command: Commands) -> Result<()> {
             path,
             skip_duplicates,
         } => {
+            let _lock = VaultLock::acquire(&vault_path)?;
+
             // Load vault
             let password = prompt_password("Enter master password: ")?;
-            let mut vault = VaultFile::load(&vault_path, &password)?;
+            let (mut vault, session) = VaultFile::open(&vault_path, &password, keyfile.as_ref())?;
+            warn_if_newer(&vault);
 
             // Preview import
             let preview = import_from_file(&path, &vault)?;
@@ -133,36 +600,475 @@ fn handle_cli_command(command: Commands) -> Result<()> {
                     continue;
                 }
 
-                vault.add_entry(imported_entry.to_entry());
+                let entry = imported_entry.to_entry(&mut vault);
+                vault.add_entry(entry);
                 imported_count += 1;
             }
 
             // Save vault
-            VaultFile::save(&vault_path, &vault, &password)?;
+            VaultFile::save(&vault_path, &vault, &session, &config.backups)?;
 
             println!("✓ Imported {} entries", imported_count);
             if skip_duplicates && !preview.duplicates.is_empty() {
                 println!("  Skipped {} duplicates", preview.duplicates.len());
             }
 
+            Ok(())
+        }
+        Commands::Init { kdf, cipher } => {
+            create_vault(
+                &vault_path,
+                &kdf,
+                cipher.as_deref(),
+                keyfile.as_ref(),
+                &config,
+            )?;
+
+            println!("✓ Created vault at {}", vault_path.display());
+
+            Ok(())
+        }
+        Commands::Passwd {
+            kdf,
+            cipher,
+            new_keyfile,
+            remove_keyfile,
+        } => {
+            let cipher = parse_cipher(cipher.as_deref())?;
+            let _lock = VaultLock::acquire(&vault_path)?;
+            let old_password = prompt_password("Enter current master password: ")?;
+            // Verify before asking for the new password
+            let (_, session) = VaultFile::open(&vault_path, &old_password, keyfile.as_ref())?;
+
+            let cost = kdf.cost(session.kdf().cost());
+            if let Some(cost) = cost {
+                cost.validate()?;
+            }
+
+            // Keep the current keyfile unless told otherwise
+            let next_keyfile = match (&new_keyfile, remove_keyfile) {
+                (Some(path), _) => Some(Keyfile::read(path)?),
+                (None, true) => None,
+                (None, false) => read_keyfile(keyfile_path.as_deref())?,
+            };
+
+            let new_password = prompt_new_password()?;
+            VaultFile::change_password(
+                &vault_path,
+                &old_password,
+                keyfile.as_ref(),
+                &new_password,
+                next_keyfile.as_ref(),
+                cost,
+                cipher,
+            )?;
+
+            println!("✓ Master password changed");
+            if let Some(cipher) = cipher {
+                println!("  Cipher: {}", cipher.as_str());
+            }
+            if let Some(path) = &new_keyfile {
+                println!("  Keyfile now required: {}", path.display());
+            } else if remove_keyfile {
+                println!("  Keyfile no longer required");
+            }
+            if session.recovery().is_some() {
+                println!("  Recovery shares no longer work; run `passmngr recovery setup` again");
+            }
+
+            Ok(())
+        }
+        Commands::Vaults { .. } => unreachable!("handled in main"),
+        Commands::Backups {
+            command: BackupCommands::List,
+        } => {
+            let backups = backup::list(&vault_path)?;
+            if backups.is_empty() {
+                println!(
+                    "No backups in {}",
+                    backup::backup_dir(&vault_path).display()
+                );
+                return Ok(());
+            }
+
+            for (i, backup) in backups.iter().enumerate() {
+                println!(
+                    "{:>3}  {}  {:>8} bytes",
+                    i + 1,
+                    backup.created.format("%Y-%m-%d %H:%M:%S UTC"),
+                    backup.size
+                );
+            }
+
+            Ok(())
+        }
+        Commands::Backups {
+            command: BackupCommands::Restore { number },
+        } => {
+            let _lock = VaultLock::acquire(&vault_path)?;
+            let backups = backup::list(&vault_path)?;
+            let chosen = number
+                .checked_sub(1)
+                .and_then(|i| backups.get(i))
+                .ok_or_else(|| {
+                    anyhow::anyhow!("No backup #{} (see `passmngr backups list`)", number)
+                })?;
+
+            let password = prompt_password("Enter master password for the backup: ")?;
+            let vault = backup::restore(
+                &vault_path,
+                chosen,
+                &password,
+                keyfile.as_ref(),
+                &config.backups,
+            )?;
+            warn_if_newer(&vault);
+
+            println!(
+                "✓ Restored backup from {} ({} entries)",
+                chosen.created.format("%Y-%m-%d %H:%M:%S UTC"),
+                vault.entries.len()
+            );
+            println!("  The replaced vault was kept as a new backup");
+
+            Ok(())
+        }
+        Commands::Attach {
+            command: AttachCommands::Add { entry, path, name },
+        } => {
+            let _lock = VaultLock::acquire(&vault_path)?;
+            let password = prompt_password("Enter master password: ")?;
+            let (mut vault, session) = VaultFile::open(&vault_path, &password, keyfile.as_ref())?;
+            warn_if_newer(&vault);
+
+            let id = find_entry(&vault, &entry)?;
+            let name = match name {
+                Some(name) => name,
+                None => path
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .ok_or_else(|| anyhow::anyhow!("Use --name to name the attachment"))?,
+            };
+            let entry = vault.get_entry_mut(&id).expect("entry was just found");
+            if entry.attachments.iter().any(|a| a.name == name) {
+                return Err(anyhow::anyhow!(
+                    "'{}' already has an attachment named '{}'",
+                    entry.name,
+                    name
+                ));
+            }
+
+            let contents = attachment::read_source(&path)?;
+            let added = attachment::add(&vault_path, &session, &name, &contents)?;
+            entry.attachments.push(added.clone());
+            entry.touch();
+            let entry_name = entry.name.clone();
+
+            if let Err(e) = VaultFile::save(&vault_path, &vault, &session, &config.backups) {
+                let _ = attachment::remove(&vault_path, &added);
+                return Err(e);
+            }
+
+            println!(
+                "✓ Attached {} ({} bytes) to '{}'",
+                added.name, added.size, entry_name
+            );
+
+            Ok(())
+        }
+        Commands::Attach {
+            command:
+                AttachCommands::Get {
+                    entry,
+                    attachment: selector,
+                    output,
+                },
+        } => {
+            let password = prompt_password("Enter master password: ")?;
+            let (vault, session) = VaultFile::open(&vault_path, &password, keyfile.as_ref())?;
+
+            let entry = vault
+                .get_entry(&find_entry(&vault, &entry)?)
+                .expect("entry was just found");
+            let found = entry.attachment(&selector).ok_or_else(|| {
+                anyhow::anyhow!("'{}' has no attachment '{}'", entry.name, selector)
+            })?;
+
+            let output = output.unwrap_or_else(|| {
+                PathBuf::from(Path::new(&found.name).file_name().unwrap_or_default())
+            });
+            let contents = attachment::read(&vault_path, &session, found)?;
+            attachment::write_file(&output, &contents)?;
+
+            println!("✓ Saved {} to {}", found.name, output.display());
+
+            Ok(())
+        }
+        Commands::Attach {
+            command:
+                AttachCommands::Rm {
+                    entry,
+                    attachment: selector,
+                },
+        } => {
+            let _lock = VaultLock::acquire(&vault_path)?;
+            let password = prompt_password("Enter master password: ")?;
+            let (mut vault, session) = VaultFile::open(&vault_path, &password, keyfile.as_ref())?;
+            warn_if_newer(&vault);
+
+            let id = find_entry(&vault, &entry)?;
+            let entry = vault.get_entry_mut(&id).expect("entry was just found");
+            let position = entry
+                .attachment(&selector)
+                .and_then(|found| entry.attachments.iter().position(|a| a.id == found.id))
+                .ok_or_else(|| {
+                    anyhow::anyhow!("'{}' has no attachment '{}'", entry.name, selector)
+                })?;
+            let removed = entry.attachments.remove(position);
+            entry.touch();
+
+            // The blob stays while a backup still refers to it
+            VaultFile::save(&vault_path, &vault, &session, &config.backups)?;
+            let referenced = vault.attachment_ids().collect();
+            if let Err(e) = attachment::collect_garbage(&vault_path, &session, &referenced) {
+                eprintln!("Warning: Failed to delete unused attachments: {}", e);
+            }
+
+            println!("✓ Removed attachment {}", removed.name);
+
+            Ok(())
+        }
+        Commands::Totp { entry } => {
+            let _lock = VaultLock::acquire(&vault_path)?;
+            let password = prompt_password("Enter master password: ")?;
+            let (mut vault, session) = VaultFile::open(&vault_path, &password, keyfile.as_ref())?;
+            warn_if_newer(&vault);
+
+            let id = find_entry(&vault, &entry)?;
+            let entry = vault.get_entry_mut(&id).expect("entry was just found");
+            let value = otp::otp_field(entry)
+                .map(|field| field.value.clone())
+                .ok_or_else(|| {
+                    anyhow::anyhow!("'{}' has no one-time password field", entry.name)
+                })?;
+            let generator = Otp::parse(&value)?;
+            let code = generator.code();
+
+            if let OtpKind::Hotp { .. } = generator.kind {
+                // Burn the counter before showing the code
+                let field = otp::otp_field_mut(entry).expect("field was just found");
+                field.value = otp::advance_counter(&value)?;
+                entry.touch();
+                VaultFile::save(&vault_path, &vault, &session, &config.backups)?;
+            }
+
+            println!("{}", code);
+            if let Some(left) = generator.seconds_left(otp::now()) {
+                eprintln!("(valid for {}s)", left);
+            }
+
+            Ok(())
+        }
+        Commands::Find { url } => {
+            let password = prompt_password("Enter master password: ")?;
+            let vault = VaultFile::load(&vault_path, &password, keyfile.as_ref())?;
+            warn_if_newer(&vault);
+
+            let found = urlmatch::find_by_url(&vault, &url)?;
+            if found.is_empty() {
+                anyhow::bail!("No entry matches {}", url);
+            }
+            for (entry, rule) in found {
+                println!(
+                    "  {}  {}  ({})",
+                    entry_path(&vault, entry),
+                    entry.username,
+                    rule.as_str()
+                );
+            }
+
+            Ok(())
+        }
+        Commands::Due { within } => {
+            let password = prompt_password("Enter master password: ")?;
+            let vault = VaultFile::load(&vault_path, &password, keyfile.as_ref())?;
+            warn_if_newer(&vault);
+
+            let now = chrono::Utc::now();
+            let until = now
+                .checked_add_signed(chrono::Duration::days(i64::from(within)))
+                .ok_or_else(|| anyhow::anyhow!("--within {} days is too far ahead", within))?;
+            let due = vault.due_entries(until);
+            if due.is_empty() {
+                println!("Nothing expires within {} days", within);
+                return Ok(());
+            }
+
+            let mut overdue = 0;
+            for (entry, expires) in &due {
+                let days = (*expires - now).num_days();
+                let when = if *expires <= now {
+                    overdue += 1;
+                    format!("expired {} days ago", -days)
+                } else {
+                    format!("expires in {} days", days)
+                };
+                println!(
+                    "  {}  {}  ({})",
+                    expires.format("%Y-%m-%d"),
+                    entry_path(&vault, entry),
+                    when
+                );
+            }
+
+            if overdue > 0 {
+                anyhow::bail!(
+                    "{} expired {}",
+                    overdue,
+                    if overdue == 1 {
+                        "password"
+                    } else {
+                        "passwords"
+                    }
+                );
+            }
+
+            Ok(())
+        }
+        Commands::Recovery {
+            command: RecoveryCommands::Setup { shares, threshold },
+        } => {
+            let _lock = VaultLock::acquire(&vault_path)?;
+            let password = prompt_password("Enter master password: ")?;
+            let shares = VaultFile::setup_recovery(
+                &vault_path,
+                &password,
+                keyfile.as_ref(),
+                threshold,
+                shares,
+            )?;
+
+            println!(
+                "✓ Recovery set up: any {} of these {} shares reset the master password",
+                threshold,
+                shares.len()
+            );
+            println!("   Store them apart from each other; earlier shares no longer work.\n");
+            for share in &shares {
+                println!("  {:>3}  {}", share.x, share.encode());
+            }
+
+            Ok(())
+        }
+        Commands::Recovery {
+            command: RecoveryCommands::Unlock,
+        } => {
+            let _lock = VaultLock::acquire(&vault_path)?;
+            let slot = VaultFile::recovery(&vault_path)?.ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Recovery is not set up for this vault (see `passmngr recovery setup`)"
+                )
+            })?;
+
+            let mut shares = Vec::new();
+            while shares.len() < slot.threshold as usize {
+                let text = prompt_password(&format!(
+                    "Enter recovery share {} of {}: ",
+                    shares.len() + 1,
+                    slot.threshold
+                ))?;
+                match Share::parse(&text) {
+                    Ok(share) if share.threshold != slot.threshold => {
+                        eprintln!("That share belongs to a different recovery setup.");
+                    }
+                    Ok(share) if shares.iter().any(|s: &Share| s.x == share.x) => {
+                        eprintln!("Share {} was already entered.", share.x);
+                    }
+                    Ok(share) => shares.push(share),
+                    Err(e) => eprintln!("{}", e),
+                }
+            }
+
+            let new_password = prompt_new_password()?;
+            VaultFile::recover(&vault_path, &shares, &new_password, keyfile.as_ref())?;
+
+            println!("✓ Vault recovered; master password changed");
+            if let Some(path) = &keyfile_path {
+                println!("  Keyfile required: {}", path.display());
+            }
+
+            Ok(())
+        }
+        Commands::Keyfile {
+            command: KeyfileCommands::Generate { path },
+        } => {
+            Keyfile::generate(&path)?;
+
+            println!("✓ Keyfile written to {}", path.display());
+            println!("   Keep a backup: without it the vault cannot be unlocked.");
+            println!(
+                "   Enable it with: passmngr passwd --new-keyfile {}",
+                path.display()
+            );
+
+            Ok(())
+        }
+        Commands::KdfBenchmark {
+            target_ms,
+            parallelism,
+        } => {
+            let target = Duration::from_millis(target_ms);
+            println!("Calibrating Argon2id for ~{} ms unlock...", target_ms);
+
+            let cost = calibrate_kdf(target, parallelism)?;
+            let elapsed = benchmark_kdf(cost)?;
+
+            println!(
+                "  time={} memory={} MiB parallelism={}  ({} ms)",
+                cost.time_cost,
+                cost.memory_cost / 1024,
+                cost.parallelism,
+                elapsed.as_millis()
+            );
+            println!(
+                "\nUse with: passmngr init --kdf-time {} --kdf-memory {} --kdf-parallelism {}",
+                cost.time_cost,
+                cost.memory_cost / 1024,
+                cost.parallelism
+            );
+            println!("      or: passmngr passwd with the same flags");
+
             Ok(())
         }
     }
 }
 
-fn run_tui() -> Result<()> {
-    // Get vault path
-    let vault_path = VaultFile::default_path()?;
+fn run_tui(target: VaultTarget, keyfile_path: Option<PathBuf>, config: Config) -> Result<()> {
+    let vault_path = target.path.clone();
+    let keyfile = read_keyfile(keyfile_path.as_deref())?;
+
+    // Held until exit; without it the session is read-only
+    let vault_lock = VaultLock::try_acquire(&vault_path)?;
+    if let LockState::InUse { pid } = vault_lock {
+        if !VaultFile::exists(&vault_path) {
+            return Err(anyhow::anyhow!("Vault is being created by PID {}", pid));
+        }
+        eprintln!("Vault in use by PID {}; opening read-only.", pid);
+    }
 
     // Check if vault exists
-    let (vault, password) = if VaultFile::exists(&vault_path) {
+    let (vault, session, fingerprint) = if VaultFile::exists(&vault_path) {
         // Prompt for password and load vault
         let password = prompt_password("Enter master password: ")?;
-        match VaultFile::load(&vault_path, &password) {
-            Ok(vault) => (vault, password),
+        match VaultFile::open_with_fingerprint(&vault_path, &password, keyfile.as_ref()) {
+            Ok((vault, session, fingerprint)) => {
+                warn_if_newer(&vault);
+                (vault, session, fingerprint)
+            }
             Err(e) => {
                 eprintln!("Failed to unlock vault: {}", e);
-                eprintln!("Incorrect password or corrupted vault.");
+                eprintln!("Incorrect password, missing keyfile or corrupted vault.");
+                drop(vault_lock);
                 std::process::exit(1);
             }
         }
@@ -173,13 +1079,14 @@ fn run_tui() -> Result<()> {
             vault_path.display()
         );
         let password = prompt_new_password()?;
+        let session = Session::new(&password, keyfile.as_ref(), KdfCost::default())?;
         let vault = Vault::new();
 
         // Save the empty vault
-        VaultFile::save(&vault_path, &vault, &password)?;
+        let fingerprint = VaultFile::save(&vault_path, &vault, &session, &config.backups)?;
         println!("Vault created successfully!");
 
-        (vault, password)
+        (vault, session, fingerprint)
     };
 
     // Initialize terminal
@@ -190,7 +1097,15 @@ fn run_tui() -> Result<()> {
     let mut terminal = Terminal::new(backend)?;
 
     // Create app
-    let mut app = App::new(vault_path, password, vault);
+    let mut app = App::new(
+        target,
+        keyfile_path,
+        session,
+        vault,
+        fingerprint,
+        vault_lock,
+        config,
+    );
 
     // Run app
     let res = run_app(&mut terminal, &mut app);
@@ -216,10 +1131,6 @@ fn run_tui() -> Result<()> {
     Ok(())
 }
 
-use std::time::Duration;
-
-// ... (keep imports)
-
 /// Main application loop
 fn run_app<B: ratatui::backend::Backend>(terminal: &mut Terminal<B>, app: &mut App) -> Result<()> {
     const INACTIVITY_TIMEOUT: Duration = Duration::from_secs(60);
@@ -238,6 +1149,7 @@ fn run_app<B: ratatui::backend::Backend>(terminal: &mut Terminal<B>, app: &mut A
             if let Err(e) = app.save() {
                 app.set_status(format!("Error saving vault: {}", e));
                 app.pending_save = false; // Reset flag manually if save failed
+                app.should_quit = false; // Don't let :wq drop unsaved changes
             }
         }
 
@@ -280,6 +1192,11 @@ fn handle_key_event(app: &mut App, key: event::KeyEvent) -> Result<()> {
         Mode::Detail => handle_detail_mode(app, key)?,
         Mode::Insert => handle_insert_mode(app, key)?,
         Mode::Locked => handle_locked_mode(app, key)?,
+        Mode::Passwd => handle_passwd_mode(app, key)?,
+        Mode::Conflict => handle_conflict_mode(app, key)?,
+        Mode::Trash => handle_trash_mode(app, key)?,
+        Mode::Folders => handle_folders_mode(app, key)?,
+        Mode::Templates => handle_templates_mode(app, key)?,
     }
 
     Ok(())
@@ -288,27 +1205,109 @@ fn handle_key_event(app: &mut App, key: event::KeyEvent) -> Result<()> {
 /// Handle keys in Locked mode
 fn handle_locked_mode(app: &mut App, key: event::KeyEvent) -> Result<()> {
     match key.code {
-        KeyCode::Char('q') | KeyCode::Esc => {
+        KeyCode::Esc => {
+            app.should_quit = true;
+        }
+        // Only quit on 'q' when it can't be part of the input being typed
+        KeyCode::Char('q') if app.unlock_input_mut().is_empty() => {
             app.should_quit = true;
         }
         KeyCode::Enter => {
-            if app.unlock().is_err() {
-                // Error is already set in status
-            }
+            // On failure the error is already set in status
+            let _ = app.unlock();
+        }
+        KeyCode::Tab | KeyCode::BackTab => {
+            app.toggle_unlock_field();
         }
         KeyCode::Char(c) => {
-            app.unlock_input.push(c);
+            app.unlock_input_mut().push(c);
+        }
+        KeyCode::Backspace => {
+            app.unlock_input_mut().pop();
         }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Handle keys in Passwd mode (master password change prompt)
+fn handle_passwd_mode(app: &mut App, key: event::KeyEvent) -> Result<()> {
+    match key.code {
+        KeyCode::Esc => app.cancel_passwd(),
+        KeyCode::Enter => app.submit_passwd_stage(),
+        KeyCode::Char(c) => app.passwd_form.input_mut().push(c),
         KeyCode::Backspace => {
-            app.unlock_input.pop();
+            app.passwd_form.input_mut().pop();
         }
         _ => {}
     }
     Ok(())
 }
 
+/// Handle keys in Conflict mode (resolving a merge with the on-disk vault)
+fn handle_conflict_mode(app: &mut App, key: event::KeyEvent) -> Result<()> {
+    match key.code {
+        KeyCode::Esc => app.cancel_merge(),
+        KeyCode::Char('j') | KeyCode::Down => app.move_conflict_selection(1),
+        KeyCode::Char('k') | KeyCode::Up => app.move_conflict_selection(-1),
+        KeyCode::Char('m') => app.resolve_conflict(Side::Mine),
+        KeyCode::Char('t') => app.resolve_conflict(Side::Theirs),
+        KeyCode::Char('M') => app.resolve_all_conflicts(Side::Mine),
+        KeyCode::Char('T') => app.resolve_all_conflicts(Side::Theirs),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Handle keys in the folder pane
+fn handle_folders_mode(app: &mut App, key: event::KeyEvent) -> Result<()> {
+    match key.code {
+        KeyCode::Esc | KeyCode::Char('q') => app.mode = Mode::Normal,
+        KeyCode::Char('j') | KeyCode::Down => app.move_folder_selection(1),
+        KeyCode::Char('k') | KeyCode::Up => app.move_folder_selection(-1),
+        KeyCode::Char(' ') | KeyCode::Char('h') | KeyCode::Char('l') => {
+            app.toggle_folder_collapsed()
+        }
+        KeyCode::Enter => app.select_folder(),
+        KeyCode::Char(':') => app.enter_command_mode(),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Handle keys in the trash view
+fn handle_trash_mode(app: &mut App, key: event::KeyEvent) -> Result<()> {
+    use crossterm::event::KeyModifiers;
+
+    match key.code {
+        KeyCode::Esc | KeyCode::Char('q') => app.mode = Mode::Normal,
+        KeyCode::Char('j') | KeyCode::Down => app.move_trash_selection(1),
+        KeyCode::Char('k') | KeyCode::Up => app.move_trash_selection(-1),
+        KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => app.redo(),
+        KeyCode::Char('r') => app.restore_selected(),
+        KeyCode::Char('D') => app.purge_selected(),
+        KeyCode::Char('u') => app.undo(),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Handle keys in the template picker
+fn handle_templates_mode(app: &mut App, key: event::KeyEvent) -> Result<()> {
+    match key.code {
+        KeyCode::Esc | KeyCode::Char('q') => app.mode = Mode::Normal,
+        KeyCode::Char('j') | KeyCode::Down => app.move_template_selection(1),
+        KeyCode::Char('k') | KeyCode::Up => app.move_template_selection(-1),
+        KeyCode::Enter => app.pick_template(),
+        _ => {}
+    }
+    Ok(())
+}
+
 /// Handle keys in Normal mode
 fn handle_normal_mode(app: &mut App, key: event::KeyEvent) -> Result<()> {
+    use crossterm::event::KeyModifiers;
+
     match key.code {
         KeyCode::Char('q') => {
             app.enter_command_mode();
@@ -322,15 +1321,22 @@ fn handle_normal_mode(app: &mut App, key: event::KeyEvent) -> Result<()> {
         KeyCode::Char('g') => app.jump_to_top(),
         KeyCode::Char('G') => app.jump_to_bottom(),
         KeyCode::Char('/') => app.enter_search_mode(),
-        KeyCode::Char('n') => app.enter_insert_mode(),
+        KeyCode::Char('f') => app.enter_folder_mode(),
+        KeyCode::Char('p') => app.toggle_favorite(),
+        KeyCode::Char('n') => app.new_entry(),
         KeyCode::Char('e') => app.enter_edit_mode(),
         KeyCode::Char('d') => {
             if let Some(entry) = app.delete_selected() {
-                app.set_status(format!("Deleted entry '{}'", entry.name));
+                app.set_status(format!(
+                    "Moved '{}' to the trash (:trash to restore)",
+                    entry.name
+                ));
             }
         }
         KeyCode::Char('y') => app.copy_password_to_clipboard()?,
         KeyCode::Char('Y') => app.copy_username_to_clipboard()?,
+        KeyCode::Char('u') => app.undo(),
+        KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => app.redo(),
         KeyCode::Enter => {
             app.mode = Mode::Detail;
         }
@@ -415,6 +1421,21 @@ fn handle_detail_mode(app: &mut App, key: event::KeyEvent) -> Result<()> {
         KeyCode::Char('v') => {
             app.toggle_password_visibility();
         }
+        KeyCode::Char('o') => {
+            app.copy_otp_to_clipboard()?;
+        }
+        KeyCode::Char(c @ '0'..='9') => {
+            // 1-9, and 0 for the tenth
+            let n = match c {
+                '0' => 10,
+                _ => c as usize - '0' as usize,
+            };
+            app.copy_previous_password_to_clipboard(n)?;
+        }
+        KeyCode::Char(':') => {
+            app.show_password = false;
+            app.enter_command_mode();
+        }
         _ => {}
     }
 
@@ -435,14 +1456,26 @@ fn handle_insert_mode(app: &mut App, key: event::KeyEvent) -> Result<()> {
         KeyCode::Char('g') if key.modifiers.contains(KeyModifiers::CONTROL) => {
             app.generate_password();
         }
+        KeyCode::Char('a') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            app.add_custom_field();
+        }
+        KeyCode::Char('d') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            app.remove_custom_field();
+        }
+        KeyCode::Char('t') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            app.cycle_custom_field_kind();
+        }
+        KeyCode::Char('k') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            app.cycle_entry_kind();
+        }
         KeyCode::Esc => {
             app.cancel_form();
         }
         KeyCode::Tab | KeyCode::Enter => {
-            app.focused_field = app.focused_field.next();
+            app.focus_next_field();
         }
         KeyCode::BackTab => {
-            app.focused_field = app.focused_field.prev();
+            app.focus_prev_field();
         }
         KeyCode::Char(c) => {
             let field_value = app.get_field_value_mut(app.focused_field);
@@ -470,8 +1503,8 @@ fn prompt_password(prompt: &str) -> Result<String> {
 fn prompt_new_password() -> Result<String> {
     loop {
         let password1 = prompt_password("Enter new master password: ")?;
-        if password1.len() < 8 {
-            eprintln!("Password must be at least 8 characters long.");
+        if let Err(e) = validate_master_password(&password1) {
+            eprintln!("{}", e);
             continue;
         }
 
@@ -0,0 +1,367 @@
+//! Three-way merge of vaults
+//!
+//! Used when the vault file changed on disk while it was open. Entries are
+//! matched by `id`; an entry counts as changed on one side when it was added,
+//! removed or has a different `modified` time than in the common base.
+//! Changes on only one side are taken as-is, changes on both sides are
+//! conflicts left for the user to resolve. The trash is merged as a set:
+//! entries trashed on either side are kept unless the other side purged them
+//! or the entry is back in the vault. Folders and templates are merged one by
+//! one the same way as entries, except that ones changed on both sides keep
+//! this session's version instead of becoming conflicts.
+
+use crate::model::{Entry, TrashedEntry, Vault};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// Side of a merge
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    /// This session's version
+    Mine,
+    /// The version found on disk
+    Theirs,
+}
+
+/// An entry changed on both sides; `None` means deleted on that side
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    pub id: Uuid,
+    pub mine: Option<Entry>,
+    pub theirs: Option<Entry>,
+}
+
+impl Conflict {
+    /// Version of the entry on one side
+    pub fn side(&self, side: Side) -> Option<&Entry> {
+        match side {
+            Side::Mine => self.mine.as_ref(),
+            Side::Theirs => self.theirs.as_ref(),
+        }
+    }
+
+    /// Display name of the entry, from whichever side still has it
+    pub fn name(&self) -> &str {
+        self.mine
+            .as_ref()
+            .or(self.theirs.as_ref())
+            .map(|e| e.name.as_str())
+            .unwrap_or_default()
+    }
+}
+
+/// Result of a merge: the merged vault without conflicting entries, and the
+/// conflicts still to be resolved
+#[derive(Debug, Clone)]
+pub struct Merge {
+    pub vault: Vault,
+    pub conflicts: Vec<Conflict>,
+}
+
+impl Merge {
+    /// Build the final vault, taking the chosen side of every conflict
+    pub fn resolve(mut self, choices: &[Side]) -> Vault {
+        for (conflict, side) in self.conflicts.into_iter().zip(choices) {
+            let entry = match side {
+                Side::Mine => conflict.mine,
+                Side::Theirs => conflict.theirs,
+            };
+            if let Some(entry) = entry {
+                self.vault.add_entry(entry);
+            }
+        }
+        drop_restored(&mut self.vault);
+        self.vault
+    }
+}
+
+/// Merge `mine` and `theirs`, both derived from `base`
+pub fn merge(base: &Vault, mine: &Vault, theirs: &Vault) -> Merge {
+    let mut vault = Vault {
+        entries: Vec::new(),
+        trash: merge_trash(base, mine, theirs),
+        folders: merge_by_id(&base.folders, &mine.folders, &theirs.folders, |f| f.id),
+        templates: merge_by_id(&base.templates, &mine.templates, &theirs.templates, |t| {
+            t.id
+        }),
+        ..mine.clone()
+    };
+    let mut conflicts = Vec::new();
+
+    // Keep this session's order, then append entries only found on disk
+    let mut seen = HashSet::new();
+    let ids = mine
+        .entries
+        .iter()
+        .chain(&theirs.entries)
+        .chain(&base.entries)
+        .map(|e| e.id)
+        .filter(|id| seen.insert(*id));
+
+    for id in ids {
+        let b = base.get_entry(&id);
+        let m = mine.get_entry(&id);
+        let t = theirs.get_entry(&id);
+
+        let merged = if same(m, t) || !changed(t, b) {
+            m
+        } else if !changed(m, b) {
+            t
+        } else {
+            conflicts.push(Conflict {
+                id,
+                mine: m.cloned(),
+                theirs: t.cloned(),
+            });
+            continue;
+        };
+
+        if let Some(entry) = merged {
+            vault.add_entry(entry.clone());
+        }
+    }
+
+    drop_restored(&mut vault);
+    Merge { vault, conflicts }
+}
+
+/// This session's trash, minus what was purged on disk, plus what was
+/// trashed on disk
+fn merge_trash(base: &Vault, mine: &Vault, theirs: &Vault) -> Vec<TrashedEntry> {
+    let in_trash = |vault: &Vault, id: &Uuid| vault.trash.iter().any(|t| &t.entry.id == id);
+
+    let kept = mine
+        .trash
+        .iter()
+        .filter(|t| in_trash(theirs, &t.entry.id) || !in_trash(base, &t.entry.id));
+    let added = theirs
+        .trash
+        .iter()
+        .filter(|t| !in_trash(mine, &t.entry.id) && !in_trash(base, &t.entry.id));
+    kept.chain(added).cloned().collect()
+}
+
+/// Items (folders, templates) changed on one side, taking this session's
+/// version when both sides changed one
+fn merge_by_id<T: Clone + PartialEq>(
+    base: &[T],
+    mine: &[T],
+    theirs: &[T],
+    id: impl Fn(&T) -> Uuid,
+) -> Vec<T> {
+    let find = |items: &[T], wanted: Uuid| items.iter().find(|item| id(item) == wanted).cloned();
+    let mut seen = HashSet::new();
+    let ids: Vec<Uuid> = mine
+        .iter()
+        .chain(theirs)
+        .chain(base)
+        .map(&id)
+        .filter(|id| seen.insert(*id))
+        .collect();
+
+    ids.into_iter()
+        .filter_map(|id| {
+            let (b, m, t) = (find(base, id), find(mine, id), find(theirs, id));
+            if m == b {
+                t
+            } else {
+                m
+            }
+        })
+        .collect()
+}
+
+/// Drop trashed copies of entries that are in the vault again, and references
+/// to folders deleted on the other side
+fn drop_restored(vault: &mut Vault) {
+    let ids: HashSet<Uuid> = vault.entries.iter().map(|e| e.id).collect();
+    vault.trash.retain(|t| !ids.contains(&t.entry.id));
+    vault.drop_missing_folders();
+}
+
+/// Whether two versions of an entry are the same revision
+fn same(a: Option<&Entry>, b: Option<&Entry>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.modified == b.modified,
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Whether a side changed the entry relative to the base
+fn changed(side: Option<&Entry>, base: Option<&Entry>) -> bool {
+    !same(side, base)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Template;
+    use chrono::Duration;
+
+    fn entry(name: &str) -> Entry {
+        Entry::new(
+            name.to_string(),
+            "user".to_string(),
+            "pass".to_string(),
+            None,
+            None,
+            vec![],
+        )
+    }
+
+    fn edited(entry: &Entry, password: &str) -> Entry {
+        let mut entry = entry.clone();
+        entry.password = password.to_string();
+        entry.modified += Duration::seconds(1);
+        entry
+    }
+
+    fn vault(entries: &[&Entry]) -> Vault {
+        let mut vault = Vault::new();
+        for entry in entries {
+            vault.add_entry((*entry).clone());
+        }
+        vault
+    }
+
+    fn names(vault: &Vault) -> Vec<&str> {
+        vault.entries.iter().map(|e| e.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_one_sided_changes_merge() {
+        let a = entry("A");
+        let b = entry("B");
+        let c = entry("C");
+        let base = vault(&[&a, &b, &c]);
+
+        // Mine: edit A, delete B, add D. Theirs: edit C, add E.
+        let a2 = edited(&a, "mine");
+        let d = entry("D");
+        let mine = vault(&[&a2, &c, &d]);
+        let c2 = edited(&c, "theirs");
+        let e = entry("E");
+        let theirs = vault(&[&a, &b, &c2, &e]);
+
+        let merge = merge(&base, &mine, &theirs);
+        assert!(merge.conflicts.is_empty());
+        assert_eq!(names(&merge.vault), vec!["A", "C", "D", "E"]);
+        assert_eq!(merge.vault.get_entry(&a.id).unwrap().password, "mine");
+        assert_eq!(merge.vault.get_entry(&c.id).unwrap().password, "theirs");
+    }
+
+    #[test]
+    fn test_identical_changes_are_not_conflicts() {
+        let a = entry("A");
+        let base = vault(&[&a]);
+        let a2 = edited(&a, "same");
+
+        let merge = merge(&base, &vault(&[&a2]), &vault(&[&a2]));
+        assert!(merge.conflicts.is_empty());
+        assert_eq!(merge.vault.entries.len(), 1);
+
+        // Deleted on both sides
+        let merge = super::merge(&base, &Vault::new(), &Vault::new());
+        assert!(merge.conflicts.is_empty());
+        assert!(merge.vault.entries.is_empty());
+    }
+
+    #[test]
+    fn test_trash_merges_as_a_set() {
+        let a = entry("A");
+        let b = entry("B");
+        let c = entry("C");
+        let mut base = vault(&[&a, &b, &c]);
+        base.trash_entry(&c.id);
+
+        // Mine: trash A, purge C. Theirs: trash B.
+        let mut mine = base.clone();
+        mine.trash_entry(&a.id);
+        mine.purge_entry(&c.id);
+        let mut theirs = base.clone();
+        theirs.trash_entry(&b.id);
+
+        let merge = merge(&base, &mine, &theirs);
+        assert!(merge.conflicts.is_empty());
+        assert!(merge.vault.entries.is_empty());
+        let trashed: Vec<_> = merge.vault.trash.iter().map(|t| t.entry.id).collect();
+        assert_eq!(trashed, vec![a.id, b.id]);
+
+        // Restored on disk: back in the vault and out of the trash
+        let mut restored = base.clone();
+        restored.restore_entry(&c.id);
+        let merge = super::merge(&base, &base, &restored);
+        assert_eq!(names(&merge.vault), vec!["A", "B", "C"]);
+        assert!(merge.vault.trash.is_empty());
+    }
+
+    #[test]
+    fn test_folders_merge() {
+        let mut base = Vault::new();
+        let work = base.create_folder_path("Work").unwrap();
+        let home = base.create_folder_path("Home").unwrap();
+
+        // Mine: rename Work, add Bills. Theirs: delete Home, add Games.
+        let mut mine = base.clone();
+        mine.folders[0].name = "Job".to_string();
+        let bills = mine.create_folder_path("Bills").unwrap();
+        let mut a = entry("A");
+        a.folder = Some(home);
+        mine.add_entry(a.clone());
+        let mut theirs = base.clone();
+        theirs.delete_folder(&home);
+        let games = theirs.create_folder_path("Games").unwrap();
+
+        let merge = merge(&base, &mine, &theirs);
+        let ids: Vec<_> = merge.vault.folders.iter().map(|f| f.id).collect();
+        assert_eq!(ids, vec![work, bills, games]);
+        assert_eq!(merge.vault.folder_path(&work), "Job");
+        // The entry's folder is gone, so it lands at the top level
+        assert_eq!(merge.vault.get_entry(&a.id).unwrap().folder, None);
+    }
+
+    #[test]
+    fn test_templates_merge() {
+        let mut base = Vault::new();
+        base.templates
+            .push(Template::from_entry("DB".to_string(), &entry("A")));
+
+        let mut mine = base.clone();
+        mine.templates[0].tags = vec!["db".to_string()];
+        let mut theirs = base.clone();
+        theirs
+            .templates
+            .push(Template::from_entry("Cloud".to_string(), &entry("B")));
+
+        let merge = merge(&base, &mine, &theirs);
+        let names: Vec<_> = merge.vault.templates.iter().map(|t| &t.name).collect();
+        assert_eq!(names, vec!["DB", "Cloud"]);
+        assert_eq!(merge.vault.templates[0].tags, vec!["db".to_string()]);
+    }
+
+    #[test]
+    fn test_conflicts_and_resolution() {
+        let a = entry("A");
+        let b = entry("B");
+        let base = vault(&[&a, &b]);
+
+        // A edited on both sides; B edited here but deleted on disk
+        let mine = vault(&[&edited(&a, "mine"), &edited(&b, "mine")]);
+        let theirs = vault(&[&edited(&edited(&a, "theirs"), "theirs")]);
+
+        let merge = merge(&base, &mine, &theirs);
+        assert_eq!(merge.conflicts.len(), 2);
+        assert!(merge.vault.entries.is_empty());
+        assert_eq!(merge.conflicts[1].name(), "B");
+        assert!(merge.conflicts[1].side(Side::Theirs).is_none());
+
+        let resolved = merge.clone().resolve(&[Side::Theirs, Side::Theirs]);
+        assert_eq!(names(&resolved), vec!["A"]);
+        assert_eq!(resolved.entries[0].password, "theirs");
+
+        let resolved = merge.resolve(&[Side::Mine, Side::Mine]);
+        assert_eq!(names(&resolved), vec!["A", "B"]);
+        assert_eq!(resolved.entries[0].password, "mine");
+    }
+}
@@ -0,0 +1,365 @@
+//! Schema migrations for the decrypted vault
+//!
+//! The decrypted payload is JSON. Vaults written with an older `Vault.version`
+//! are upgraded one step at a time on the raw JSON value before it is
+//! deserialized, so a step can rename, move or reshape fields the current
+//! structs no longer know about.
+//!
+//! Vaults written by a newer passmngr are still readable, but their version is
+//! kept as-is so `VaultFile::save` can refuse to overwrite them: fields this
+//! build does not know about would otherwise be dropped silently.
+//!
+//! To change the schema: bump `VAULT_VERSION` in `model` and append a step to
+//! `MIGRATIONS`, and freeze a vault written with the new version as
+//! `tests/fixtures/vault_vN.json`.
+
+use crate::model::{Vault, VAULT_VERSION};
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+
+/// A single upgrade step from schema version N to N + 1
+type Migration = fn(&mut Value) -> Result<()>;
+
+/// Upgrade steps; `MIGRATIONS[i]` upgrades version `i + 1` to `i + 2`
+const MIGRATIONS: &[Migration] = &[
+    add_attachments,
+    add_password_history,
+    add_trash,
+    add_custom_fields,
+    add_entry_kinds,
+    add_folders,
+    add_usage,
+    add_password_changed,
+    add_url_list,
+    add_templates,
+];
+
+// Every version below the current one needs a step
+const _: () = assert!(MIGRATIONS.len() as u32 + 1 == VAULT_VERSION);
+
+/// v1 -> v2: entries gain an (empty) attachment list
+fn add_attachments(value: &mut Value) -> Result<()> {
+    for entry in entries_mut(value)? {
+        entry["attachments"] = Value::Array(Vec::new());
+    }
+    Ok(())
+}
+
+/// v2 -> v3: entries gain an (empty) password history
+fn add_password_history(value: &mut Value) -> Result<()> {
+    for entry in entries_mut(value)? {
+        entry["password_history"] = Value::Array(Vec::new());
+    }
+    Ok(())
+}
+
+/// v3 -> v4: vaults gain an (empty) trash
+fn add_trash(value: &mut Value) -> Result<()> {
+    value["trash"] = Value::Array(Vec::new());
+    Ok(())
+}
+
+/// v4 -> v5: entries gain an (empty) list of custom fields
+fn add_custom_fields(value: &mut Value) -> Result<()> {
+    for entry in entries_mut(value)? {
+        entry["fields"] = Value::Array(Vec::new());
+    }
+    Ok(())
+}
+
+/// v5 -> v6: every existing entry is a login
+fn add_entry_kinds(value: &mut Value) -> Result<()> {
+    for entry in entries_mut(value)? {
+        entry["kind"] = serde_json::json!({ "type": "login" });
+    }
+    Ok(())
+}
+
+/// v6 -> v7: vaults gain an (empty) folder tree; entries start at the top
+fn add_folders(value: &mut Value) -> Result<()> {
+    for entry in entries_mut(value)? {
+        entry["folder"] = Value::Null;
+    }
+    value["folders"] = Value::Array(Vec::new());
+    Ok(())
+}
+
+/// v7 -> v8: entries gain usage statistics and a favorite flag
+fn add_usage(value: &mut Value) -> Result<()> {
+    for entry in entries_mut(value)? {
+        entry["favorite"] = Value::Bool(false);
+        entry["last_used"] = Value::Null;
+        entry["use_count"] = Value::from(0);
+    }
+    Ok(())
+}
+
+/// v8 -> v9: entries gain an (unset) expiry and the time the password was set
+///
+/// That is when the newest history entry was replaced, or failing that the
+/// last modification.
+fn add_password_changed(value: &mut Value) -> Result<()> {
+    for entry in entries_mut(value)? {
+        let changed = entry["password_history"]
+            .get(0)
+            .map(|previous| previous["replaced"].clone())
+            .unwrap_or_else(|| entry["modified"].clone());
+        entry["password_changed"] = changed;
+        entry["expiry"] = Value::Null;
+    }
+    Ok(())
+}
+
+/// v9 -> v10: the single `url` becomes a list of URLs with match rules
+fn add_url_list(value: &mut Value) -> Result<()> {
+    for entry in entries_mut(value)? {
+        let urls = match entry.as_object_mut().and_then(|e| e.remove("url")) {
+            Some(Value::String(url)) => serde_json::json!([{ "url": url, "rule": "domain" }]),
+            _ => Value::Array(Vec::new()),
+        };
+        entry["urls"] = urls;
+    }
+    Ok(())
+}
+
+/// v10 -> v11: vaults gain an (empty) list of entry templates
+fn add_templates(value: &mut Value) -> Result<()> {
+    value["templates"] = Value::Array(Vec::new());
+    Ok(())
+}
+
+/// The entry objects of a vault, trashed ones included
+fn entries_mut(value: &mut Value) -> Result<impl Iterator<Item = &mut Value>> {
+    let vault = value
+        .as_object_mut()
+        .filter(|vault| vault.get("entries").is_some_and(Value::is_array))
+        .ok_or_else(|| anyhow!("Vault data has no entries"))?;
+
+    Ok(vault
+        .iter_mut()
+        .flat_map(|(key, field)| match (key.as_str(), field) {
+            ("entries", Value::Array(entries)) => entries.iter_mut().collect(),
+            ("trash", Value::Array(trash)) => trash
+                .iter_mut()
+                .filter_map(|t| t.get_mut("entry"))
+                .collect(),
+            _ => Vec::new(),
+        }))
+}
+
+/// Read the schema version of a decrypted vault
+fn schema_version(value: &Value) -> Result<u32> {
+    let version = value
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| anyhow!("Vault data has no schema version"))?;
+
+    match u32::try_from(version) {
+        Ok(version) if version >= 1 => Ok(version),
+        _ => Err(anyhow!("Invalid vault schema version: {}", version)),
+    }
+}
+
+/// Run `steps` from `from` up to `steps.len() + 1`, updating the version field
+fn apply_migrations(value: &mut Value, from: u32, steps: &[Migration]) -> Result<()> {
+    for (i, step) in steps.iter().enumerate().skip(from as usize - 1) {
+        step(value)?;
+        value["version"] = Value::from(i as u32 + 2);
+    }
+    Ok(())
+}
+
+/// Upgrade a decrypted vault to the current schema
+///
+/// Returns the schema version the data was written with.
+pub fn migrate(value: &mut Value) -> Result<u32> {
+    let version = schema_version(value)?;
+    if version < VAULT_VERSION {
+        apply_migrations(value, version, MIGRATIONS)?;
+    }
+    Ok(version)
+}
+
+/// Deserialize a vault from decrypted (or exported) JSON of any schema version
+pub fn vault_from_json(bytes: &[u8]) -> Result<Vault> {
+    let mut value: Value = serde_json::from_slice(bytes)?;
+    migrate(&mut value)?;
+    Ok(serde_json::from_value(value)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{EntryKind, Expiry, UrlMatch};
+
+    /// Vaults as written by each schema version, oldest first
+    ///
+    /// These are frozen: never edit one, add a fixture for a new version.
+    const FIXTURES: &[&str] = &[
+        include_str!("../tests/fixtures/vault_v1.json"),
+        include_str!("../tests/fixtures/vault_v2.json"),
+        include_str!("../tests/fixtures/vault_v3.json"),
+        include_str!("../tests/fixtures/vault_v4.json"),
+        include_str!("../tests/fixtures/vault_v5.json"),
+        include_str!("../tests/fixtures/vault_v6.json"),
+        include_str!("../tests/fixtures/vault_v7.json"),
+        include_str!("../tests/fixtures/vault_v8.json"),
+        include_str!("../tests/fixtures/vault_v9.json"),
+        include_str!("../tests/fixtures/vault_v10.json"),
+        include_str!("../tests/fixtures/vault_v11.json"),
+    ];
+
+    const V1_FIXTURE: &str = FIXTURES[0];
+
+    // Every version needs a fixture
+    const _: () = assert!(FIXTURES.len() as u32 == VAULT_VERSION);
+
+    #[test]
+    fn test_fixtures_migrate() {
+        for (i, fixture) in FIXTURES.iter().enumerate() {
+            let written = i as u32 + 1;
+            let since = |version: u32| written >= version;
+            let vault = vault_from_json(fixture.as_bytes()).unwrap();
+            assert_eq!(vault.version, VAULT_VERSION, "v{}", written);
+
+            let github = &vault.entries[0];
+            let bank = &vault.entries[1];
+            assert_eq!(github.name, "GitHub");
+            assert_eq!(github.password, "hunter2");
+            assert_eq!(github.tags, vec!["dev".to_string()]);
+            assert_eq!(github.url(), Some("https://github.com"));
+            assert_eq!(github.urls[0].rule, UrlMatch::Domain);
+            assert_eq!(github.kind, EntryKind::Login);
+            assert_eq!(bank.notes.as_deref(), Some("Branch: High Street"));
+
+            // v2: attachments
+            assert_eq!(bank.attachments.len(), since(2) as usize);
+            assert!(github.attachments.is_empty());
+
+            // v3: password history, and with it when the password was set
+            assert_eq!(github.password_history.len(), since(3) as usize);
+            let changed = if since(3) {
+                "2024-01-15T00:00:00+00:00".to_string()
+            } else {
+                github.modified.to_rfc3339()
+            };
+            assert_eq!(github.password_changed.to_rfc3339(), changed);
+
+            // v4: trash, migrated like the live entries
+            assert_eq!(vault.trash.len(), since(4) as usize);
+            if let Some(trashed) = vault.trash.first() {
+                assert_eq!(trashed.entry.name, "Old forum");
+                assert_eq!(trashed.entry.kind, EntryKind::Login);
+                assert_eq!(trashed.entry.url(), Some("https://forum.example.com"));
+            }
+
+            // v5: custom fields
+            assert_eq!(bank.fields.len(), since(5) as usize);
+            if let Some(pin) = bank.fields.first() {
+                assert_eq!((pin.label.as_str(), pin.value.as_str()), ("PIN", "1234"));
+            }
+
+            // v6: entry kinds
+            assert_eq!(vault.entries.len(), 2 + since(6) as usize);
+            if let Some(server) = vault.entries.get(2) {
+                assert!(matches!(server.kind, EntryKind::SshKey(_)));
+            }
+
+            // v7: folders
+            assert_eq!(vault.folders.len(), since(7) as usize);
+            assert_eq!(github.folder, vault.folders.first().map(|f| f.id));
+            assert_eq!(bank.folder, None);
+
+            // v8: usage
+            assert_eq!(github.favorite, since(8));
+            assert_eq!(github.use_count, if since(8) { 5 } else { 0 });
+            assert_eq!(github.last_used.is_some(), since(8));
+
+            // v9: expiry
+            let expiry = since(9).then_some(Expiry::Every { days: 90 });
+            assert_eq!(bank.expiry, expiry);
+
+            // v10: several URLs with match rules
+            assert_eq!(github.urls.len(), 1 + since(10) as usize);
+            if let Some(login) = github.urls.get(1) {
+                assert_eq!(login.rule, UrlMatch::Exact);
+            }
+
+            // v11: templates
+            assert_eq!(vault.templates.len(), since(11) as usize);
+
+            let json = serde_json::to_vec(&vault).unwrap();
+            assert_eq!(vault_from_json(&json).unwrap(), vault);
+        }
+    }
+
+    #[test]
+    fn test_steps_run_in_order() {
+        fn add_a(value: &mut Value) -> Result<()> {
+            value["trail"] = Value::from("a");
+            Ok(())
+        }
+        fn add_b(value: &mut Value) -> Result<()> {
+            let trail = value["trail"].as_str().unwrap_or_default().to_string();
+            value["trail"] = Value::from(trail + "b");
+            Ok(())
+        }
+        let steps: &[Migration] = &[add_a, add_b];
+
+        let mut from_v1 = serde_json::json!({ "version": 1 });
+        apply_migrations(&mut from_v1, 1, steps).unwrap();
+        assert_eq!(from_v1["trail"], "ab");
+        assert_eq!(from_v1["version"], 3);
+
+        // Starting part way only runs the remaining steps
+        let mut from_v2 = serde_json::json!({ "version": 2, "trail": "x" });
+        apply_migrations(&mut from_v2, 2, steps).unwrap();
+        assert_eq!(from_v2["trail"], "xb");
+        assert_eq!(from_v2["version"], 3);
+    }
+
+    #[test]
+    fn test_trashed_entries_are_migrated() {
+        let mut value: Value = serde_json::from_str(V1_FIXTURE).unwrap();
+        apply_migrations(&mut value, 1, &MIGRATIONS[..3]).unwrap();
+        let entry = value["entries"].as_array_mut().unwrap().remove(0);
+        value["trash"] = serde_json::json!([{ "entry": entry, "deleted": "2024-03-01T00:00:00Z" }]);
+
+        let vault = vault_from_json(&serde_json::to_vec(&value).unwrap()).unwrap();
+        assert_eq!(vault.entries.len(), 1);
+        assert_eq!(vault.trash[0].entry.name, "GitHub");
+        assert!(vault.trash[0].entry.fields.is_empty());
+    }
+
+    #[test]
+    fn test_password_changed_from_history() {
+        let mut value: Value = serde_json::from_str(V1_FIXTURE).unwrap();
+        apply_migrations(&mut value, 1, &MIGRATIONS[..2]).unwrap();
+        value["entries"][0]["password_history"] = serde_json::json!([
+            { "password": "older", "replaced": "2024-01-15T00:00:00Z" }
+        ]);
+
+        let vault = vault_from_json(&serde_json::to_vec(&value).unwrap()).unwrap();
+        assert_eq!(
+            vault.entries[0].password_changed.to_rfc3339(),
+            "2024-01-15T00:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn test_newer_version_is_kept() {
+        let json = format!(
+            r#"{{ "version": {}, "entries": [], "trash": [], "folders": [], "templates": [], "added_later": true }}"#,
+            VAULT_VERSION + 1
+        );
+        let vault = vault_from_json(json.as_bytes()).unwrap();
+        assert_eq!(vault.version, VAULT_VERSION + 1);
+        assert!(vault.is_from_newer_version());
+    }
+
+    #[test]
+    fn test_invalid_version() {
+        assert!(vault_from_json(br#"{ "entries": [] }"#).is_err());
+        assert!(vault_from_json(br#"{ "version": 0, "entries": [] }"#).is_err());
+    }
+}
@@ -1,21 +1,527 @@
 //! Data model for password entries and vault structure
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+pub use crate::crypto::PasswordPolicy;
+
+/// Schema version written by this build (see `migrate` for older versions)
+pub const VAULT_VERSION: u32 = 11;
+
+/// Number of previous passwords kept per entry
+pub const MAX_PASSWORD_HISTORY: usize = 10;
+
+/// Days before its expiry that an entry is flagged as expiring soon
+pub const EXPIRY_WARNING_DAYS: i64 = 14;
+
+/// Longest interval `parse_days` accepts (100 years)
+pub const MAX_DAYS: u32 = 100 * 365;
+
 /// A single password entry
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Entry {
     pub id: Uuid,
     pub created: DateTime<Utc>,
     pub modified: DateTime<Utc>,
     pub name: String,
+    /// What the entry holds, with the fields specific to that kind
+    pub kind: EntryKind,
     pub username: String,
+    /// The password, or the SSH key passphrase or API token
     pub password: String,
-    pub url: Option<String>,
+    /// Earlier passwords, newest first (at most `MAX_PASSWORD_HISTORY`)
+    pub password_history: Vec<PreviousPassword>,
+    /// When the password was last set (other edits leave this alone)
+    pub password_changed: DateTime<Utc>,
+    /// When the password has to be changed, if ever
+    pub expiry: Option<Expiry>,
+    /// Addresses the entry is for, the main one first
+    pub urls: Vec<EntryUrl>,
     pub notes: Option<String>,
+    /// User-defined fields, in display order
+    pub fields: Vec<CustomField>,
     pub tags: Vec<String>,
+    /// Folder the entry is in (`None` for the top level)
+    pub folder: Option<Uuid>,
+    pub attachments: Vec<Attachment>,
+    /// Pinned to the top of the list
+    pub favorite: bool,
+    /// When a value was last copied (not an edit: `modified` is unchanged)
+    pub last_used: Option<DateTime<Utc>>,
+    /// How often a value was copied
+    pub use_count: u32,
+}
+
+/// Order of the entry list; favorites always come first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortOrder {
+    /// Most used and most recently used first
+    #[default]
+    Frecency,
+    Name,
+    /// Most recently modified first
+    Modified,
+    /// Newest first
+    Created,
+}
+
+impl SortOrder {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "frecency" => Some(Self::Frecency),
+            "name" => Some(Self::Name),
+            "modified" => Some(Self::Modified),
+            "created" => Some(Self::Created),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Frecency => "frecency",
+            Self::Name => "name",
+            Self::Modified => "modified",
+            Self::Created => "created",
+        }
+    }
+
+    /// Sort entries in place; ties keep their current (vault) order
+    pub fn sort(&self, entries: &mut [&Entry], now: DateTime<Utc>) {
+        entries.sort_by(|a, b| {
+            b.favorite.cmp(&a.favorite).then_with(|| match self {
+                Self::Frecency => b.frecency(now).cmp(&a.frecency(now)),
+                Self::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+                Self::Modified => b.modified.cmp(&a.modified),
+                Self::Created => b.created.cmp(&a.created),
+            })
+        });
+    }
+}
+
+/// When an entry's password has to be changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Expiry {
+    /// At the start of a fixed day (UTC)
+    On { date: NaiveDate },
+    /// A number of days after the password was last changed
+    Every { days: u32 },
+}
+
+impl Expiry {
+    /// Parse a date (`2025-12-31`) or a rotation interval (`90d`, see `parse_days`)
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        match NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+            Ok(date) => Some(Self::On { date }),
+            Err(_) => parse_days(text).map(|days| Self::Every { days }),
+        }
+    }
+
+    /// When a password set at `changed` expires, `None` if that is beyond
+    /// the representable date range
+    pub fn due(&self, changed: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Self::On { date } => Some(date.and_time(Default::default()).and_utc()),
+            Self::Every { days } => changed.checked_add_signed(Duration::days(i64::from(*days))),
+        }
+    }
+}
+
+impl std::fmt::Display for Expiry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::On { date } => write!(f, "{}", date.format("%Y-%m-%d")),
+            Self::Every { days } => write!(f, "every {} days", days),
+        }
+    }
+}
+
+/// Parse a number of days: `14`, `14d`, `2w`, `6m` (30 days) or `1y` (365 days)
+///
+/// Intervals longer than `MAX_DAYS` are rejected.
+pub fn parse_days(text: &str) -> Option<u32> {
+    let text = text.trim().to_lowercase();
+    let (number, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(at) => text.split_at(at),
+        None => (text.as_str(), "d"),
+    };
+    let factor = match unit {
+        "d" => 1,
+        "w" => 7,
+        "m" => 30,
+        "y" => 365,
+        _ => return None,
+    };
+    number
+        .parse::<u32>()
+        .ok()?
+        .checked_mul(factor)
+        .filter(|days| *days <= MAX_DAYS)
+}
+
+/// How close an entry is to its expiry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpiryState {
+    Expired,
+    /// Expires within `EXPIRY_WARNING_DAYS`
+    Soon,
+}
+
+/// How an entry URL is compared with the address of a page (see `urlmatch`)
+///
+/// Variants are ordered from the strictest to the loosest match. A pattern
+/// can be as loose as `.*`, so `Regex` ranks last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UrlMatch {
+    /// The whole address
+    Exact,
+    /// The address starts with the URL
+    Prefix,
+    /// Same host and port
+    Host,
+    /// Same registrable domain, e.g. `example.co.uk` for `login.example.co.uk`
+    #[default]
+    Domain,
+    /// The URL is a regular expression found in the address
+    Regex,
+}
+
+impl UrlMatch {
+    pub fn all() -> &'static [UrlMatch] {
+        &[
+            Self::Exact,
+            Self::Prefix,
+            Self::Host,
+            Self::Domain,
+            Self::Regex,
+        ]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Exact => "exact",
+            Self::Prefix => "prefix",
+            Self::Host => "host",
+            Self::Domain => "domain",
+            Self::Regex => "regex",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::all()
+            .iter()
+            .find(|rule| rule.as_str() == name.to_lowercase())
+            .copied()
+    }
+}
+
+/// One of an entry's URLs with its match rule
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntryUrl {
+    /// The URL, or the pattern for `UrlMatch::Regex`
+    pub url: String,
+    pub rule: UrlMatch,
+}
+
+impl EntryUrl {
+    /// A URL matched by its base domain
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            rule: UrlMatch::default(),
+        }
+    }
+
+    /// Parse `URL` or `RULE:URL`, e.g. `host:https://login.example.com`
+    pub fn parse(text: &str) -> Self {
+        match text.split_once(':') {
+            Some((rule, url)) if !url.is_empty() => match UrlMatch::parse(rule) {
+                Some(rule) => Self {
+                    url: url.to_string(),
+                    rule,
+                },
+                None => Self::new(text.to_string()),
+            },
+            _ => Self::new(text.to_string()),
+        }
+    }
+
+    /// Parse whitespace-separated URLs (as written by `Display`)
+    ///
+    /// Double quotes keep whitespace in a URL or pattern, e.g.
+    /// `regex:"^https://example\.com/a b"`; inside them, `""` is a quote.
+    pub fn parse_list(text: &str) -> Vec<Self> {
+        let mut urls = Vec::new();
+        let mut current = String::new();
+        let mut quoted = false;
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if quoted && chars.peek() == Some(&'"') => {
+                    chars.next();
+                    current.push('"');
+                }
+                '"' => quoted = !quoted,
+                c if c.is_whitespace() && !quoted => {
+                    if !current.is_empty() {
+                        urls.push(Self::parse(&std::mem::take(&mut current)));
+                    }
+                }
+                c => current.push(c),
+            }
+        }
+        if !current.is_empty() {
+            urls.push(Self::parse(&current));
+        }
+        urls
+    }
+}
+
+impl std::fmt::Display for EntryUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.rule != UrlMatch::Domain {
+            write!(f, "{}:", self.rule.as_str())?;
+        }
+        // Quoted so that `parse_list` reads it back as one URL
+        if self.url.contains(|c: char| c.is_whitespace() || c == '"') {
+            write!(f, "\"{}\"", self.url.replace('"', "\"\""))
+        } else {
+            write!(f, "{}", self.url)
+        }
+    }
+}
+
+/// Separator of folder names in paths like `Work/Databases`
+pub const FOLDER_SEPARATOR: char = '/';
+
+/// A folder of the vault's folder tree
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Folder {
+    pub id: Uuid,
+    pub name: String,
+    /// Enclosing folder (`None` for the top level)
+    pub parent: Option<Uuid>,
+}
+
+/// What an entry holds
+///
+/// Name, notes, tags and custom fields are common to all kinds. Logins use the
+/// username, password and URL; SSH keys the username and password (as the
+/// passphrase); API tokens the password (as the token) and URL (as the
+/// endpoint). Everything else lives in the kind itself.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EntryKind {
+    #[default]
+    Login,
+    Note,
+    Card(Card),
+    Identity(Identity),
+    SshKey(SshKey),
+    ApiToken(ApiToken),
+}
+
+/// Payment card details
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Card {
+    pub holder: String,
+    pub number: String,
+    /// As printed on the card, e.g. `08/29`
+    pub expiry: String,
+    pub cvv: String,
+}
+
+/// Personal details for filling in forms
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Identity {
+    pub full_name: String,
+    pub email: String,
+    pub phone: String,
+    pub street: String,
+    pub city: String,
+    pub postcode: String,
+    pub country: String,
+}
+
+/// An SSH key pair (OpenSSH format)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SshKey {
+    pub private_key: String,
+    pub public_key: String,
+}
+
+/// Scopes of an API token
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub scopes: Vec<String>,
+}
+
+impl EntryKind {
+    /// Empty entries of every kind, in cycling order
+    pub fn all() -> [Self; 6] {
+        [
+            Self::Login,
+            Self::Note,
+            Self::Card(Card::default()),
+            Self::Identity(Identity::default()),
+            Self::SshKey(SshKey::default()),
+            Self::ApiToken(ApiToken::default()),
+        ]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Login => "login",
+            Self::Note => "note",
+            Self::Card(_) => "card",
+            Self::Identity(_) => "identity",
+            Self::SshKey(_) => "ssh key",
+            Self::ApiToken(_) => "api token",
+        }
+    }
+
+    /// The same kind with all its fields empty
+    pub fn empty(&self) -> Self {
+        Self::all()
+            .into_iter()
+            .find(|k| k.as_str() == self.as_str())
+            .unwrap_or_default()
+    }
+
+    /// The next kind, empty and wrapping around (for cycling in the form)
+    pub fn next(&self) -> Self {
+        let all = Self::all();
+        let i = all
+            .iter()
+            .position(|k| k.as_str() == self.as_str())
+            .unwrap_or(0);
+        all[(i + 1) % all.len()].clone()
+    }
+
+    /// Whether the entry uses its username, password and URL as a login
+    pub fn is_login(&self) -> bool {
+        matches!(self, Self::Login)
+    }
+
+    /// Label of the password for this kind (`None` if it has none)
+    pub fn password_label(&self) -> Option<&'static str> {
+        match self {
+            Self::Login => Some("Password"),
+            Self::SshKey(_) => Some("Passphrase"),
+            Self::ApiToken(_) => Some("Token"),
+            Self::Note | Self::Card(_) | Self::Identity(_) => None,
+        }
+    }
+
+    /// Label of the URL for this kind (`None` if it has none)
+    pub fn url_label(&self) -> Option<&'static str> {
+        match self {
+            Self::Login => Some("URLs"),
+            Self::ApiToken(_) => Some("Endpoint"),
+            _ => None,
+        }
+    }
+
+    /// Whether this kind has a username
+    pub fn has_username(&self) -> bool {
+        matches!(self, Self::Login | Self::SshKey(_))
+    }
+
+    /// Kind-specific values, as (label, value, secret) in display order
+    pub fn details(&self) -> Vec<(&'static str, String, bool)> {
+        match self {
+            Self::Login | Self::Note => Vec::new(),
+            Self::Card(card) => vec![
+                ("Cardholder", card.holder.clone(), false),
+                ("Number", card.number.clone(), true),
+                ("Expiry", card.expiry.clone(), false),
+                ("CVV", card.cvv.clone(), true),
+            ],
+            Self::Identity(identity) => vec![
+                ("Full name", identity.full_name.clone(), false),
+                ("Email", identity.email.clone(), false),
+                ("Phone", identity.phone.clone(), false),
+                ("Street", identity.street.clone(), false),
+                ("City", identity.city.clone(), false),
+                ("Postcode", identity.postcode.clone(), false),
+                ("Country", identity.country.clone(), false),
+            ],
+            Self::SshKey(key) => vec![
+                ("Public key", key.public_key.clone(), false),
+                ("Private key", key.private_key.clone(), true),
+            ],
+            Self::ApiToken(token) => vec![("Scopes", token.scopes.join(", "), false)],
+        }
+    }
+}
+
+/// How a custom field is shown and used
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldKind {
+    #[default]
+    Text,
+    /// Masked like the password
+    Hidden,
+    Url,
+    Email,
+    /// Base32 secret of a one-time password generator
+    Totp,
+}
+
+impl FieldKind {
+    pub const ALL: [Self; 5] = [Self::Text, Self::Hidden, Self::Url, Self::Email, Self::Totp];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Hidden => "hidden",
+            Self::Url => "url",
+            Self::Email => "email",
+            Self::Totp => "totp",
+        }
+    }
+
+    /// Whether the value is masked until revealed
+    pub fn is_secret(&self) -> bool {
+        matches!(self, Self::Hidden | Self::Totp)
+    }
+
+    /// The next kind, wrapping around (for cycling in the form)
+    pub fn next(&self) -> Self {
+        let i = Self::ALL.iter().position(|k| k == self).unwrap_or(0);
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+}
+
+/// A user-defined field of an entry
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CustomField {
+    pub label: String,
+    pub value: String,
+    pub kind: FieldKind,
+}
+
+/// A password an entry used before
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PreviousPassword {
+    pub password: String,
+    /// When it was replaced
+    pub replaced: DateTime<Utc>,
+}
+
+/// Reference to an encrypted file stored next to the vault (see `attachment`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attachment {
+    pub id: Uuid,
+    pub name: String,
+    pub size: u64,
+    /// SHA-256 of the contents, hex encoded
+    pub sha256: String,
+    pub added: DateTime<Utc>,
 }
 
 impl Entry {
@@ -34,11 +540,21 @@ impl Entry {
             created: now,
             modified: now,
             name,
+            kind: EntryKind::Login,
             username,
             password,
-            url,
+            password_history: Vec::new(),
+            password_changed: now,
+            expiry: None,
+            urls: url.into_iter().map(EntryUrl::new).collect(),
             notes,
+            fields: Vec::new(),
             tags,
+            folder: None,
+            attachments: Vec::new(),
+            favorite: false,
+            last_used: None,
+            use_count: 0,
         }
     }
 
@@ -47,6 +563,81 @@ impl Entry {
         self.modified = Utc::now();
     }
 
+    /// Record that a value of the entry was copied
+    pub fn record_use(&mut self, now: DateTime<Utc>) {
+        self.last_used = Some(now);
+        self.use_count = self.use_count.saturating_add(1);
+    }
+
+    /// Ranking by use count, weighted by how recently the entry was used
+    pub fn frecency(&self, now: DateTime<Utc>) -> u64 {
+        let Some(last_used) = self.last_used else {
+            return 0;
+        };
+        let weight = match (now - last_used).num_days() {
+            ..=3 => 100,
+            4..=14 => 70,
+            15..=31 => 50,
+            32..=90 => 30,
+            _ => 10,
+        };
+        u64::from(self.use_count) * weight
+    }
+
+    /// When the password expires, if it ever does
+    pub fn expires(&self) -> Option<DateTime<Utc>> {
+        self.expiry?.due(self.password_changed)
+    }
+
+    /// Whether the password has expired or expires soon
+    pub fn expiry_state(&self, now: DateTime<Utc>) -> Option<ExpiryState> {
+        let expires = self.expires()?;
+        if expires <= now {
+            Some(ExpiryState::Expired)
+        } else if expires <= now + Duration::days(EXPIRY_WARNING_DAYS) {
+            Some(ExpiryState::Soon)
+        } else {
+            None
+        }
+    }
+
+    /// Replace the password, keeping the old one in the history
+    pub fn set_password(&mut self, password: String) {
+        if password == self.password {
+            return;
+        }
+
+        let now = Utc::now();
+        self.password_changed = now;
+        let old = std::mem::replace(&mut self.password, password);
+        if !old.is_empty() {
+            self.password_history.insert(
+                0,
+                PreviousPassword {
+                    password: old,
+                    replaced: now,
+                },
+            );
+            self.password_history.truncate(MAX_PASSWORD_HISTORY);
+        }
+    }
+
+    /// The main URL
+    pub fn url(&self) -> Option<&str> {
+        self.urls.first().map(|u| u.url.as_str())
+    }
+
+    /// Find an attachment by name or by its 1-based position
+    pub fn attachment(&self, selector: &str) -> Option<&Attachment> {
+        self.attachments
+            .iter()
+            .find(|a| a.name == selector)
+            .or_else(|| {
+                let index = selector.parse::<usize>().ok()?.checked_sub(1)?;
+                self.attachments.get(index)
+            })
+    }
+
     /// Check if entry matches search query (case-insensitive)
     pub fn matches(&self, query: &str) -> bool {
         let query = query.to_lowercase();
@@ -54,37 +645,133 @@ impl Entry {
         let name_match = self.name.to_lowercase().contains(&query);
         let username_match = self.username.to_lowercase().contains(&query);
         let url_match = self
-            .url
-            .as_ref()
-            .map(|u| u.to_lowercase().contains(&query))
-            .unwrap_or(false);
+            .urls
+            .iter()
+            .any(|u| u.url.to_lowercase().contains(&query));
         let notes_match = self
             .notes
             .as_ref()
             .map(|n| n.to_lowercase().contains(&query))
             .unwrap_or(false);
         let tags_match = self.tags.iter().any(|t| t.to_lowercase().contains(&query));
+        // Card numbers, CVVs and private keys are not searched
+        let details_match = self
+            .kind
+            .details()
+            .iter()
+            .any(|(_, value, secret)| !secret && value.to_lowercase().contains(&query));
 
-        name_match | username_match | url_match | notes_match | tags_match
+        name_match | username_match | url_match | notes_match | tags_match | details_match
+    }
+
+    /// The value `y` copies: the card number for cards, otherwise the password
+    pub fn secret(&self) -> &str {
+        match &self.kind {
+            EntryKind::Card(card) => &card.number,
+            _ => &self.password,
+        }
     }
 }
 
-/// The vault containing all password entries
+/// An entry deleted from the vault, kept until restored or purged
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrashedEntry {
+    pub entry: Entry,
+    pub deleted: DateTime<Utc>,
+}
+
+/// How long deleted entries stay in the trash
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrashPolicy {
+    /// Purge trashed entries older than this many days (`None` keeps them)
+    pub purge_after_days: Option<u32>,
+}
+
+impl Default for TrashPolicy {
+    fn default() -> Self {
+        Self {
+            purge_after_days: Some(30),
+        }
+    }
+}
+
+impl TrashPolicy {
+    /// When an entry trashed at `deleted` is purged, `None` if it is kept
+    /// forever or the limit reaches beyond the representable date range
+    pub fn purge_at(&self, deleted: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let days = self.purge_after_days?;
+        deleted.checked_add_signed(Duration::days(days.into()))
+    }
+}
+
+/// A user-defined starting point for new entries
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Template {
+    pub id: Uuid,
+    pub name: String,
+    /// Kind of the new entries (its fields are left empty)
+    pub kind: EntryKind,
+    pub tags: Vec<String>,
+    /// Custom fields the new entries start with
+    pub fields: Vec<CustomField>,
+    /// How passwords of the new entries are generated
+    pub password: PasswordPolicy,
+}
+
+impl Template {
+    /// Capture the shape of an entry: its kind, tags, custom field labels and
+    /// kinds, and the length and character categories of its password
+    pub fn from_entry(name: String, entry: &Entry) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            name,
+            kind: entry.kind.empty(),
+            tags: entry.tags.clone(),
+            fields: entry
+                .fields
+                .iter()
+                .map(|field| CustomField {
+                    value: String::new(),
+                    ..field.clone()
+                })
+                .collect(),
+            password: PasswordPolicy::of(&entry.password),
+        }
+    }
+}
+
+/// The vault containing all password entries
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Vault {
     pub version: u32,
     pub entries: Vec<Entry>,
+    /// Deleted entries, oldest first
+    pub trash: Vec<TrashedEntry>,
+    /// The folder tree, as a flat list linked by `parent`
+    pub folders: Vec<Folder>,
+    /// Templates for new entries, in the order they are offered
+    pub templates: Vec<Template>,
 }
 
 impl Vault {
     /// Create a new empty vault
     pub fn new() -> Self {
         Self {
-            version: 1,
+            version: VAULT_VERSION,
             entries: Vec::new(),
+            trash: Vec::new(),
+            folders: Vec::new(),
+            templates: Vec::new(),
         }
     }
 
+    /// Whether the vault was written by a newer passmngr with a schema this
+    /// build cannot fully represent (saving it would drop data)
+    pub fn is_from_newer_version(&self) -> bool {
+        self.version > VAULT_VERSION
+    }
+
     /// Add a new entry to the vault
     pub fn add_entry(&mut self, entry: Entry) {
         self.entries.push(entry);
@@ -99,6 +786,59 @@ impl Vault {
         }
     }
 
+    /// Move an entry to the trash
+    pub fn trash_entry(&mut self, id: &Uuid) -> Option<&Entry> {
+        let entry = self.remove_entry(id)?;
+        self.trash.push(TrashedEntry {
+            entry,
+            deleted: Utc::now(),
+        });
+        self.trash.last().map(|t| &t.entry)
+    }
+
+    /// Move an entry from the trash back into the vault
+    ///
+    /// Entries whose folder was deleted meanwhile go to the top level.
+    pub fn restore_entry(&mut self, id: &Uuid) -> Option<&Entry> {
+        let pos = self.trash.iter().position(|t| &t.entry.id == id)?;
+        let mut entry = self.trash.remove(pos).entry;
+        if entry.folder.is_some_and(|f| self.folder(&f).is_none()) {
+            entry.folder = None;
+        }
+        self.add_entry(entry);
+        self.entries.last()
+    }
+
+    /// Delete an entry from the trash for good
+    pub fn purge_entry(&mut self, id: &Uuid) -> Option<Entry> {
+        let pos = self.trash.iter().position(|t| &t.entry.id == id)?;
+        Some(self.trash.remove(pos).entry)
+    }
+
+    /// Delete everything in the trash; returns how many entries were purged
+    pub fn empty_trash(&mut self) -> usize {
+        std::mem::take(&mut self.trash).len()
+    }
+
+    /// Purge trashed entries older than the policy allows
+    pub fn purge_expired(&mut self, policy: &TrashPolicy, now: DateTime<Utc>) -> usize {
+        let before = self.trash.len();
+        self.trash.retain(|t| {
+            policy
+                .purge_at(t.deleted)
+                .is_none_or(|purge_at| purge_at > now)
+        });
+        before - self.trash.len()
+    }
+
+    /// IDs of the attachments of all entries, trashed ones included
+    pub fn attachment_ids(&self) -> impl Iterator<Item = Uuid> + '_ {
+        self.entries
+            .iter()
+            .chain(self.trash.iter().map(|t| &t.entry))
+            .flat_map(|entry| entry.attachments.iter().map(|a| a.id))
+    }
+
     /// Get a mutable reference to an entry by ID
     pub fn get_entry_mut(&mut self, id: &Uuid) -> Option<&mut Entry> {
         self.entries.iter_mut().find(|e| &e.id == id)
@@ -109,6 +849,134 @@ impl Vault {
         self.entries.iter().find(|e| &e.id == id)
     }
 
+    /// Get a folder by ID
+    pub fn folder(&self, id: &Uuid) -> Option<&Folder> {
+        self.folders.iter().find(|f| &f.id == id)
+    }
+
+    /// Find a template by ID
+    pub fn template(&self, id: &Uuid) -> Option<&Template> {
+        self.templates.iter().find(|t| &t.id == id)
+    }
+
+    /// Find a template by (case-insensitive) name
+    pub fn find_template(&self, name: &str) -> Option<&Template> {
+        self.templates
+            .iter()
+            .find(|t| t.name.to_lowercase() == name.trim().to_lowercase())
+    }
+
+    /// Direct subfolders of `parent` (`None` for the top level), by name
+    pub fn subfolders(&self, parent: Option<Uuid>) -> Vec<&Folder> {
+        let mut folders: Vec<_> = self.folders.iter().filter(|f| f.parent == parent).collect();
+        folders.sort_by_key(|f| f.name.to_lowercase());
+        folders
+    }
+
+    /// Full path of a folder, e.g. `Work/Databases`
+    pub fn folder_path(&self, id: &Uuid) -> String {
+        let mut names = Vec::new();
+        let mut current = self.folder(id);
+        // Bounded by the folder count in case of a cycle in a damaged vault
+        while let Some(folder) = current.filter(|_| names.len() < self.folders.len()) {
+            names.push(folder.name.as_str());
+            current = folder.parent.and_then(|p| self.folder(&p));
+        }
+        names.reverse();
+        names.join(&FOLDER_SEPARATOR.to_string())
+    }
+
+    /// Whether `folder` is `ancestor` or inside it
+    pub fn folder_within(&self, folder: Option<Uuid>, ancestor: &Uuid) -> bool {
+        let mut current = folder;
+        for _ in 0..=self.folders.len() {
+            match current {
+                Some(id) if &id == ancestor => return true,
+                Some(id) => current = self.folder(&id).and_then(|f| f.parent),
+                None => return false,
+            }
+        }
+        false
+    }
+
+    /// Find a folder by path (names compared case-insensitively)
+    pub fn find_folder(&self, path: &str) -> Option<Uuid> {
+        let mut parent = None;
+        for name in split_folder_path(path) {
+            parent = Some(
+                self.folders
+                    .iter()
+                    .find(|f| f.parent == parent && f.name.eq_ignore_ascii_case(name))?
+                    .id,
+            );
+        }
+        parent
+    }
+
+    /// Find a folder by path, creating any missing folders along it
+    ///
+    /// Returns `None` for an empty path (the top level).
+    pub fn create_folder_path(&mut self, path: &str) -> Option<Uuid> {
+        let mut parent = None;
+        for name in split_folder_path(path) {
+            let existing = self
+                .folders
+                .iter()
+                .find(|f| f.parent == parent && f.name.eq_ignore_ascii_case(name));
+            parent = Some(match existing {
+                Some(folder) => folder.id,
+                None => {
+                    let folder = Folder {
+                        id: Uuid::new_v4(),
+                        name: name.to_string(),
+                        parent,
+                    };
+                    let id = folder.id;
+                    self.folders.push(folder);
+                    id
+                }
+            });
+        }
+        parent
+    }
+
+    /// Delete a folder; its entries and subfolders move up to its parent
+    pub fn delete_folder(&mut self, id: &Uuid) -> Option<Folder> {
+        let pos = self.folders.iter().position(|f| &f.id == id)?;
+        let folder = self.folders.remove(pos);
+        for child in self.folders.iter_mut().filter(|f| f.parent == Some(*id)) {
+            child.parent = folder.parent;
+        }
+        for entry in self.entries.iter_mut().filter(|e| e.folder == Some(*id)) {
+            entry.folder = folder.parent;
+        }
+        Some(folder)
+    }
+
+    /// Clear references to folders that no longer exist (e.g. after a merge)
+    pub fn drop_missing_folders(&mut self) {
+        let ids: Vec<Uuid> = self.folders.iter().map(|f| f.id).collect();
+        let missing = |folder: &Option<Uuid>| folder.is_some_and(|f| !ids.contains(&f));
+        for entry in self.entries.iter_mut().filter(|e| missing(&e.folder)) {
+            entry.folder = None;
+        }
+        for folder in self.folders.iter_mut().filter(|f| missing(&f.parent)) {
+            folder.parent = None;
+        }
+    }
+
+    /// Entries whose password expires before `until`, soonest first
+    pub fn due_entries(&self, until: DateTime<Utc>) -> Vec<(&Entry, DateTime<Utc>)> {
+        let mut due: Vec<_> = self
+            .entries
+            .iter()
+            .filter_map(|e| Some((e, e.expires()?)))
+            .filter(|(_, expires)| *expires <= until)
+            .collect();
+        due.sort_by_key(|(_, expires)| *expires);
+        due
+    }
+
     /// Search entries by query
     pub fn search(&self, query: &str) -> Vec<&Entry> {
         if query.is_empty() {
@@ -119,6 +987,13 @@ impl Vault {
     }
 }
 
+/// Folder names along a path, ignoring empty segments
+pub fn split_folder_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split(FOLDER_SEPARATOR)
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+}
+
 impl Default for Vault {
     fn default() -> Self {
         Self::new()
@@ -148,6 +1023,129 @@ mod tests {
         assert!(!entry.matches("gitlab"));
     }
 
+    #[test]
+    fn test_kind_details_match() {
+        let mut entry = Entry::new(
+            "Visa".to_string(),
+            String::new(),
+            String::new(),
+            None,
+            None,
+            vec![],
+        );
+        entry.kind = EntryKind::Card(Card {
+            holder: "Jane Doe".to_string(),
+            number: "4111111111111111".to_string(),
+            expiry: "08/29".to_string(),
+            cvv: "123".to_string(),
+        });
+
+        assert!(entry.matches("jane"));
+        assert!(entry.matches("08/29"));
+        assert!(!entry.matches("4111"));
+        assert!(!entry.matches("123"));
+        assert_eq!(entry.secret(), "4111111111111111");
+
+        entry.kind = EntryKind::ApiToken(ApiToken {
+            scopes: vec!["repo:read".to_string()],
+        });
+        assert!(entry.matches("repo:"));
+    }
+
+    #[test]
+    fn test_entry_kind() {
+        let entry: EntryKind = serde_json::from_str(
+            r#"{ "type": "ssh_key", "private_key": "-----BEGIN", "public_key": "ssh-ed25519 AAAA" }"#,
+        )
+        .unwrap();
+        assert_eq!(entry.as_str(), "ssh key");
+        assert_eq!(entry.password_label(), Some("Passphrase"));
+        assert_eq!(
+            serde_json::to_value(EntryKind::Note).unwrap(),
+            serde_json::json!({ "type": "note" })
+        );
+
+        let mut kind = EntryKind::Login;
+        for _ in 0..EntryKind::all().len() {
+            kind = kind.next();
+        }
+        assert_eq!(kind, EntryKind::Login);
+    }
+
+    #[test]
+    fn test_password_history() {
+        let mut entry = Entry::new(
+            "Site".to_string(),
+            "user".to_string(),
+            "pass0".to_string(),
+            None,
+            None,
+            vec![],
+        );
+
+        // Unchanged passwords aren't recorded
+        entry.set_password("pass0".to_string());
+        assert!(entry.password_history.is_empty());
+
+        for i in 1..=MAX_PASSWORD_HISTORY + 2 {
+            entry.set_password(format!("pass{}", i));
+        }
+        assert_eq!(entry.password, format!("pass{}", MAX_PASSWORD_HISTORY + 2));
+        assert_eq!(entry.password_history.len(), MAX_PASSWORD_HISTORY);
+        assert_eq!(
+            entry.password_history[0].password,
+            format!("pass{}", MAX_PASSWORD_HISTORY + 1)
+        );
+        assert_eq!(entry.password_history.last().unwrap().password, "pass2");
+    }
+
+    #[test]
+    fn test_field_kind() {
+        let field: CustomField =
+            serde_json::from_str(r#"{ "label": "PIN", "value": "1234", "kind": "hidden" }"#)
+                .unwrap();
+        assert_eq!(field.kind, FieldKind::Hidden);
+        assert!(field.kind.is_secret());
+        assert_eq!(
+            serde_json::to_value(FieldKind::Totp).unwrap(),
+            serde_json::json!("totp")
+        );
+
+        let mut kind = FieldKind::Text;
+        for _ in 0..FieldKind::ALL.len() {
+            kind = kind.next();
+        }
+        assert_eq!(kind, FieldKind::Text);
+    }
+
+    #[test]
+    fn test_attachment_lookup() {
+        let mut entry = Entry::new(
+            "Server".to_string(),
+            "root".to_string(),
+            "pass".to_string(),
+            None,
+            None,
+            vec![],
+        );
+        for name in ["2", "id_ed25519"] {
+            entry.attachments.push(Attachment {
+                id: Uuid::new_v4(),
+                name: name.to_string(),
+                size: 0,
+                sha256: String::new(),
+                added: Utc::now(),
+            });
+        }
+
+        assert_eq!(entry.attachment("id_ed25519").unwrap().name, "id_ed25519");
+        assert_eq!(entry.attachment("1").unwrap().name, "2");
+        // Names win over positions
+        assert_eq!(entry.attachment("2").unwrap().name, "2");
+        assert!(entry.attachment("3").is_none());
+        assert!(entry.attachment("0").is_none());
+    }
+
     #[test]
     fn test_vault_operations() {
         let mut vault = Vault::new();
@@ -172,6 +1170,258 @@ mod tests {
         assert_eq!(vault.entries.len(), 0);
     }
 
+    #[test]
+    fn test_trash() {
+        let mut vault = Vault::new();
+        let entry = Entry::new(
+            "Test".to_string(),
+            "user".to_string(),
+            "pass".to_string(),
+            None,
+            None,
+            vec![],
+        );
+        let id = entry.id;
+        vault.add_entry(entry);
+
+        assert_eq!(vault.trash_entry(&id).unwrap().name, "Test");
+        assert!(vault.entries.is_empty());
+        assert!(vault.search("test").is_empty());
+
+        assert!(vault.restore_entry(&id).is_some());
+        assert_eq!(vault.entries.len(), 1);
+        assert!(vault.trash.is_empty());
+
+        vault.trash_entry(&id);
+        assert!(vault.purge_entry(&id).is_some());
+        assert!(vault.restore_entry(&id).is_none());
+        assert!(vault.entries.is_empty() && vault.trash.is_empty());
+    }
+
+    #[test]
+    fn test_purge_expired() {
+        let mut vault = Vault::new();
+        for name in ["Old", "New"] {
+            let entry = Entry::new(
+                name.to_string(),
+                "user".to_string(),
+                "pass".to_string(),
+                None,
+                None,
+                vec![],
+            );
+            let id = entry.id;
+            vault.add_entry(entry);
+            vault.trash_entry(&id);
+        }
+        vault.trash[0].deleted -= Duration::days(31);
+
+        let now = Utc::now();
+        let keep = TrashPolicy {
+            purge_after_days: None,
+        };
+        assert_eq!(vault.purge_expired(&keep, now), 0);
+        let forever = TrashPolicy {
+            purge_after_days: Some(u32::MAX),
+        };
+        assert_eq!(vault.purge_expired(&forever, now), 0);
+        assert_eq!(vault.purge_expired(&TrashPolicy::default(), now), 1);
+        assert_eq!(vault.trash[0].entry.name, "New");
+
+        assert_eq!(vault.empty_trash(), 1);
+        assert!(vault.trash.is_empty());
+    }
+
+    #[test]
+    fn test_folders() {
+        let mut vault = Vault::new();
+        let db = vault.create_folder_path("Work/Databases").unwrap();
+        let work = vault.find_folder("work").unwrap();
+        assert_eq!(vault.create_folder_path("/Work/ databases/"), Some(db));
+        assert_eq!(vault.folders.len(), 2);
+        assert_eq!(vault.folder_path(&db), "Work/Databases");
+        assert!(vault.folder_within(Some(db), &work));
+        assert!(!vault.folder_within(Some(work), &db));
+        assert!(vault.find_folder("Work/Nope").is_none());
+        assert!(vault.create_folder_path("").is_none());
+
+        let mut entry = Entry::new(
+            "Postgres".to_string(),
+            "admin".to_string(),
+            "pass".to_string(),
+            None,
+            None,
+            vec![],
+        );
+        entry.folder = Some(db);
+        let id = entry.id;
+        vault.add_entry(entry);
+        vault.create_folder_path("Work/Databases/Replicas");
+
+        // Contents move up to the parent
+        vault.delete_folder(&db).unwrap();
+        assert_eq!(vault.get_entry(&id).unwrap().folder, Some(work));
+        assert_eq!(
+            vault.folder_path(&vault.find_folder("Work/Replicas").unwrap()),
+            "Work/Replicas"
+        );
+
+        vault.trash_entry(&id);
+        vault.delete_folder(&work);
+        assert_eq!(vault.restore_entry(&id).unwrap().folder, None);
+    }
+
+    #[test]
+    fn test_sort_order() {
+        let now = Utc::now();
+        let new = |name: &str| {
+            Entry::new(
+                name.to_string(),
+                "user".to_string(),
+                "pass".to_string(),
+                None,
+                None,
+                vec![],
+            )
+        };
+        let mut daily = new("daily");
+        for _ in 0..5 {
+            daily.record_use(now);
+        }
+        let mut stale = new("stale");
+        for _ in 0..10 {
+            stale.record_use(now - Duration::days(200));
+        }
+        let unused = new("Unused");
+        let mut pinned = new("pinned");
+        pinned.favorite = true;
+
+        let mut entries = vec![&unused, &stale, &pinned, &daily];
+        SortOrder::Frecency.sort(&mut entries, now);
+        let names: Vec<_> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["pinned", "daily", "stale", "Unused"]);
+
+        SortOrder::Name.sort(&mut entries, now);
+        let names: Vec<_> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["pinned", "daily", "stale", "Unused"]);
+
+        assert_eq!(SortOrder::parse("Modified"), Some(SortOrder::Modified));
+        assert!(SortOrder::parse("size").is_none());
+    }
+
+    #[test]
+    fn test_expiry() {
+        let now = Utc::now();
+        let mut entry = Entry::new(
+            "Bank".to_string(),
+            "user".to_string(),
+            "pass".to_string(),
+            None,
+            None,
+            vec![],
+        );
+        assert_eq!(entry.expiry_state(now), None);
+
+        entry.expiry = Expiry::parse("30d");
+        entry.password_changed = now - Duration::days(20);
+        assert_eq!(entry.expiry_state(now), Some(ExpiryState::Soon));
+        entry.password_changed = now - Duration::days(31);
+        assert_eq!(entry.expiry_state(now), Some(ExpiryState::Expired));
+
+        // Only a new password restarts the interval
+        entry.tags.push("finance".to_string());
+        entry.touch();
+        entry.set_password("pass".to_string());
+        assert_eq!(entry.expiry_state(now), Some(ExpiryState::Expired));
+        entry.set_password("new".to_string());
+        assert_eq!(entry.expiry_state(Utc::now()), None);
+
+        entry.expiry = Expiry::parse("2020-01-31");
+        assert_eq!(entry.expiry_state(now), Some(ExpiryState::Expired));
+        assert_eq!(
+            entry.expires().unwrap().to_rfc3339(),
+            "2020-01-31T00:00:00+00:00"
+        );
+
+        let mut vault = Vault::new();
+        vault.add_entry(entry);
+        assert_eq!(vault.due_entries(now).len(), 1);
+        vault.entries[0].expiry = None;
+        assert!(vault.due_entries(now).is_empty());
+
+        // Intervals from a vault file can be larger than `parse_days` allows
+        vault.entries[0].expiry = Some(Expiry::Every { days: u32::MAX });
+        assert_eq!(vault.entries[0].expires(), None);
+        assert!(vault.due_entries(now).is_empty());
+    }
+
+    #[test]
+    fn test_parse_days() {
+        assert_eq!(parse_days("14"), Some(14));
+        assert_eq!(parse_days("14d"), Some(14));
+        assert_eq!(parse_days("2W"), Some(14));
+        assert_eq!(parse_days("6m"), Some(180));
+        assert_eq!(parse_days("1y"), Some(365));
+        assert_eq!(parse_days("d"), None);
+        assert_eq!(parse_days("3h"), None);
+        assert_eq!(parse_days("100y"), Some(MAX_DAYS));
+        assert_eq!(parse_days("101y"), None);
+        assert_eq!(parse_days("1000000y"), None);
+        assert_eq!(Expiry::parse("90d"), Some(Expiry::Every { days: 90 }));
+        assert_eq!(Expiry::parse("never"), None);
+    }
+
+    #[test]
+    fn test_url_list() {
+        let urls = EntryUrl::parse_list(r#" example.co.uk  regex:"^https://a b/""x""" "#);
+        assert_eq!(urls.len(), 2);
+        assert_eq!(urls[0], EntryUrl::new("example.co.uk".to_string()));
+        assert_eq!(urls[1].rule, UrlMatch::Regex);
+        assert_eq!(urls[1].url, r#"^https://a b/"x""#);
+
+        let text = urls.iter().map(|u| u.to_string()).collect::<Vec<_>>();
+        assert_eq!(text[1], r#"regex:"^https://a b/""x""""#);
+        assert_eq!(EntryUrl::parse_list(&text.join(" ")), urls);
+    }
+
+    #[test]
+    fn test_password_policy_of() {
+        let policy = PasswordPolicy::of("Abc123");
+        assert_eq!(policy.length, 6);
+        assert!(policy.upper && policy.lower && policy.digits && !policy.symbols);
+        assert!(!PasswordPolicy::of("abc").symbols);
+        assert_eq!(PasswordPolicy::of(""), PasswordPolicy::default());
+    }
+
+    #[test]
+    fn test_template_from_entry() {
+        let mut entry = Entry::new(
+            "Prod DB".to_string(),
+            "admin".to_string(),
+            "s3cretpassword".to_string(),
+            None,
+            None,
+            vec!["db".to_string()],
+        );
+        entry.fields.push(CustomField {
+            label: "Host".to_string(),
+            value: "db1.internal".to_string(),
+            kind: FieldKind::Text,
+        });
+
+        let template = Template::from_entry("Database".to_string(), &entry);
+        assert_eq!(template.kind, EntryKind::Login);
+        assert_eq!(template.tags, vec!["db".to_string()]);
+        assert_eq!(template.fields[0].label, "Host");
+        assert!(template.fields[0].value.is_empty());
+        assert_eq!(template.password.length, 14);
+        assert!(template.password.digits && !template.password.upper);
+
+        let mut vault = Vault::new();
+        vault.templates.push(template);
+        assert!(vault.find_template("database").is_some());
+    }
+
     #[test]
     fn test_search() {
         let mut vault = Vault::new();
@@ -0,0 +1,239 @@
+//! Framing of the vault plaintext before encryption
+//!
+//! The serialized vault is compressed and then padded, so the ciphertext
+//! length says little about how many entries or how much notes text the vault
+//! holds. Both schemes are recorded in the vault header; files without them
+//! (written by older versions) hold the raw JSON.
+
+use anyhow::{anyhow, Result};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder};
+use std::io::{Read, Write};
+
+/// Smallest padded payload, so tiny vaults all look alike
+const MIN_PADDED_SIZE: usize = 1024;
+
+/// Largest plaintext accepted (guards against decompression bombs)
+const MAX_PLAINTEXT_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Size of the length prefix of a padded payload
+const LENGTH_SIZE: usize = 4;
+
+/// Compression applied to the serialized vault
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    /// Raw DEFLATE (RFC 1951)
+    Deflate,
+}
+
+impl Compression {
+    /// Name stored in the vault header
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Deflate => "deflate",
+        }
+    }
+
+    /// Look up a scheme by its stored name
+    pub fn parse(name: &str) -> Result<Self> {
+        [Self::None, Self::Deflate]
+            .into_iter()
+            .find(|compression| compression.as_str() == name)
+            .ok_or_else(|| anyhow!("Unsupported payload compression: {}", name))
+    }
+}
+
+/// Padding applied after compression
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Padding {
+    #[default]
+    None,
+    /// Length-prefixed, zero-padded to a Padmé bucket
+    ///
+    /// Padmé (Nikitin et al., PETS 2019) rounds a length so that only
+    /// O(log log n) bits of it remain, costing at most 12% overhead.
+    Padme,
+}
+
+impl Padding {
+    /// Name stored in the vault header
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Padme => "padme",
+        }
+    }
+
+    /// Look up a scheme by its stored name
+    pub fn parse(name: &str) -> Result<Self> {
+        [Self::None, Self::Padme]
+            .into_iter()
+            .find(|padding| padding.as_str() == name)
+            .ok_or_else(|| anyhow!("Unsupported payload padding: {}", name))
+    }
+}
+
+/// How the plaintext of a vault file is framed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PayloadFormat {
+    pub compression: Compression,
+    pub padding: Padding,
+}
+
+impl PayloadFormat {
+    /// Raw JSON, as written by older versions
+    pub const RAW: Self = Self {
+        compression: Compression::None,
+        padding: Padding::None,
+    };
+
+    /// Format written by `save`
+    pub const CURRENT: Self = Self {
+        compression: Compression::Deflate,
+        padding: Padding::Padme,
+    };
+
+    /// Frame a serialized vault for encryption
+    ///
+    /// Fails for plaintexts larger than `decode` accepts.
+    pub fn encode(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        if plaintext.len() as u64 > MAX_PLAINTEXT_SIZE {
+            return Err(anyhow!("Vault too large to save"));
+        }
+
+        let compressed = match self.compression {
+            Compression::None => plaintext.to_vec(),
+            Compression::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(plaintext)?;
+                encoder.finish()?
+            }
+        };
+
+        match self.padding {
+            Padding::None => Ok(compressed),
+            Padding::Padme => {
+                let len = u32::try_from(compressed.len())
+                    .map_err(|_| anyhow!("Vault too large to pad"))?;
+                let padded_len = padme((LENGTH_SIZE + compressed.len()).max(MIN_PADDED_SIZE));
+
+                let mut out = Vec::with_capacity(padded_len);
+                out.extend_from_slice(&len.to_le_bytes());
+                out.extend_from_slice(&compressed);
+                out.resize(padded_len, 0);
+                Ok(out)
+            }
+        }
+    }
+
+    /// Recover the serialized vault from a decrypted payload
+    pub fn decode(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        let compressed = match self.padding {
+            Padding::None => payload,
+            Padding::Padme => {
+                let (len, rest) = payload
+                    .split_first_chunk::<LENGTH_SIZE>()
+                    .ok_or_else(|| anyhow!("Truncated vault payload"))?;
+                rest.get(..u32::from_le_bytes(*len) as usize)
+                    .ok_or_else(|| anyhow!("Truncated vault payload"))?
+            }
+        };
+
+        match self.compression {
+            Compression::None => Ok(compressed.to_vec()),
+            Compression::Deflate => {
+                let mut plaintext = Vec::new();
+                DeflateDecoder::new(compressed)
+                    .take(MAX_PLAINTEXT_SIZE + 1)
+                    .read_to_end(&mut plaintext)
+                    .map_err(|e| anyhow!("Failed to decompress vault: {}", e))?;
+                if plaintext.len() as u64 > MAX_PLAINTEXT_SIZE {
+                    return Err(anyhow!("Decompressed vault too large"));
+                }
+                Ok(plaintext)
+            }
+        }
+    }
+}
+
+/// Round `len` up to its Padmé bucket
+fn padme(len: usize) -> usize {
+    if len < 2 {
+        return len;
+    }
+    let exponent = usize::BITS - 1 - len.leading_zeros();
+    let exponent_bits = u32::BITS - exponent.leading_zeros();
+    let mask = (1usize << (exponent - exponent_bits)) - 1;
+    (len + mask) & !mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_padme() {
+        assert_eq!(padme(1024), 1024);
+        assert_eq!(padme(1025), 1088);
+        assert_eq!(padme(1088), 1088);
+        assert_eq!(padme(100_000), 100_352);
+        for len in [1usize, 7, 1000, 4097, 123_456, 9_999_999] {
+            let padded = padme(len);
+            assert!(padded >= len && padded - len <= len / 8 + 1);
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let plaintext = br#"{"entries":[{"name":"a","notes":"aaaaaaaaaaaaaaaaaaaa"}]}"#;
+        for format in [
+            PayloadFormat::RAW,
+            PayloadFormat::CURRENT,
+            PayloadFormat {
+                compression: Compression::Deflate,
+                padding: Padding::None,
+            },
+        ] {
+            let encoded = format.encode(plaintext).unwrap();
+            assert_eq!(format.decode(&encoded).unwrap(), plaintext);
+        }
+
+        assert_eq!(PayloadFormat::RAW.encode(plaintext).unwrap(), plaintext);
+        assert_eq!(
+            PayloadFormat::CURRENT.encode(plaintext).unwrap().len(),
+            MIN_PADDED_SIZE
+        );
+    }
+
+    #[test]
+    fn test_similar_sizes_share_a_bucket() {
+        let a = PayloadFormat::CURRENT.encode(&[b'x'; 5000]).unwrap();
+        let b = PayloadFormat::CURRENT.encode(&[b'y'; 6000]).unwrap();
+        assert_eq!(a.len(), b.len());
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let format = PayloadFormat::CURRENT;
+        assert!(format.decode(&[1, 2]).is_err());
+
+        // Length prefix beyond the payload
+        let mut encoded = format.encode(b"data").unwrap();
+        encoded[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(format.decode(&encoded).is_err());
+
+        assert!(Compression::parse("zip").is_err());
+        assert_eq!(Padding::parse("padme").unwrap(), Padding::Padme);
+    }
+
+    #[test]
+    fn test_encode_rejects_oversized() {
+        let plaintext = vec![0; MAX_PLAINTEXT_SIZE as usize + 1];
+        assert!(PayloadFormat::CURRENT.encode(&plaintext).is_err());
+        assert!(PayloadFormat::CURRENT
+            .encode(&plaintext[..MAX_PLAINTEXT_SIZE as usize])
+            .is_ok());
+    }
+}
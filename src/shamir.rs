@@ -0,0 +1,265 @@
+//! Shamir secret sharing over GF(256)
+//!
+//! Splits a secret byte-wise into `n` shares so that any `k` of them rebuild
+//! it and fewer reveal nothing. Arithmetic is in GF(2^8) with the AES
+//! polynomial x^8 + x^4 + x^3 + x + 1. Used for recovery codes, see
+//! `crypto::Session::setup_recovery`.
+
+use anyhow::{anyhow, Result};
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use zeroize::{Zeroize, Zeroizing};
+
+/// Version byte of the printed share format
+const SHARE_FORMAT: u8 = 1;
+
+/// Checksum bytes appended to a printed share to catch typos
+const CHECKSUM_SIZE: usize = 2;
+
+/// One share of a secret
+#[derive(Clone, PartialEq, Eq, Zeroize)]
+#[zeroize(drop)]
+pub struct Share {
+    /// Shares needed to rebuild the secret
+    pub threshold: u8,
+    /// Evaluation point (1..=255)
+    pub x: u8,
+    /// Polynomial values at `x`, one per secret byte
+    pub y: Vec<u8>,
+}
+
+impl std::fmt::Debug for Share {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Share")
+            .field("threshold", &self.threshold)
+            .field("x", &self.x)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Share {
+    /// Printable form: hex in dash-separated groups, with a checksum
+    pub fn encode(&self) -> String {
+        let mut bytes = Zeroizing::new(vec![SHARE_FORMAT, self.threshold, self.x]);
+        bytes.extend_from_slice(&self.y);
+        let checksum = Sha256::digest(&bytes[..]);
+        bytes.extend_from_slice(&checksum[..CHECKSUM_SIZE]);
+
+        let hex = Zeroizing::new(
+            bytes
+                .iter()
+                .map(|b| format!("{:02X}", b))
+                .collect::<String>(),
+        );
+        hex.as_bytes()
+            .chunks(4)
+            .map(|chunk| String::from_utf8_lossy(chunk).into_owned())
+            .collect::<Vec<_>>()
+            .join("-")
+    }
+
+    /// Parse the printable form; dashes, spaces and case are ignored
+    pub fn parse(text: &str) -> Result<Self> {
+        let hex: Zeroizing<Vec<u8>> = Zeroizing::new(
+            text.bytes()
+                .filter(|b| !b.is_ascii_whitespace() && *b != b'-')
+                .collect(),
+        );
+        if !hex.len().is_multiple_of(2) {
+            return Err(anyhow!("Invalid recovery share"));
+        }
+
+        let mut bytes = Zeroizing::new(Vec::with_capacity(hex.len() / 2));
+        for pair in hex.chunks(2) {
+            let pair = std::str::from_utf8(pair).map_err(|_| anyhow!("Invalid recovery share"))?;
+            bytes
+                .push(u8::from_str_radix(pair, 16).map_err(|_| anyhow!("Invalid recovery share"))?);
+        }
+
+        if bytes.len() < 3 + 1 + CHECKSUM_SIZE {
+            return Err(anyhow!("Invalid recovery share"));
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - CHECKSUM_SIZE);
+        if Sha256::digest(body)[..CHECKSUM_SIZE] != *checksum {
+            return Err(anyhow!("Recovery share has a typo (checksum mismatch)"));
+        }
+        if body[0] != SHARE_FORMAT {
+            return Err(anyhow!("Unsupported recovery share format"));
+        }
+
+        Ok(Self {
+            threshold: body[1],
+            x: body[2],
+            y: body[3..].to_vec(),
+        })
+    }
+}
+
+/// Split `secret` into `shares` shares, any `threshold` of which rebuild it
+pub fn split(secret: &[u8], threshold: u8, shares: u8) -> Result<Vec<Share>> {
+    if threshold < 2 || threshold > shares {
+        return Err(anyhow!(
+            "Threshold must be between 2 and the number of shares ({})",
+            shares
+        ));
+    }
+    if secret.is_empty() {
+        return Err(anyhow!("Cannot split an empty secret"));
+    }
+
+    let mut result: Vec<Share> = (1..=shares)
+        .map(|x| Share {
+            threshold,
+            x,
+            y: Vec::with_capacity(secret.len()),
+        })
+        .collect();
+
+    // One random polynomial per byte, with the secret byte as constant term
+    let mut coefficients = Zeroizing::new(vec![0u8; threshold as usize]);
+    for &byte in secret {
+        coefficients[0] = byte;
+        OsRng
+            .try_fill_bytes(&mut coefficients[1..])
+            .map_err(|e| anyhow!("Failed to generate randomness: {}", e))?;
+
+        for share in result.iter_mut() {
+            share.y.push(evaluate(&coefficients, share.x));
+        }
+    }
+
+    Ok(result)
+}
+
+/// Rebuild the secret from at least `threshold` shares
+pub fn combine(shares: &[Share]) -> Result<Zeroizing<Vec<u8>>> {
+    let first = shares
+        .first()
+        .ok_or_else(|| anyhow!("No recovery shares"))?;
+    let threshold = first.threshold as usize;
+
+    if shares
+        .iter()
+        .any(|s| s.threshold != first.threshold || s.y.len() != first.y.len())
+    {
+        return Err(anyhow!("Recovery shares belong to different secrets"));
+    }
+    for (i, share) in shares.iter().enumerate() {
+        if share.x == 0 || shares[..i].iter().any(|s| s.x == share.x) {
+            return Err(anyhow!("Duplicate or invalid recovery share"));
+        }
+    }
+    if shares.len() < threshold {
+        return Err(anyhow!(
+            "Need {} recovery shares, got {}",
+            threshold,
+            shares.len()
+        ));
+    }
+
+    // Lagrange interpolation at x = 0; subtraction is XOR in GF(2^8)
+    let shares = &shares[..threshold];
+    let mut secret = Zeroizing::new(vec![0u8; first.y.len()]);
+    for (i, share) in shares.iter().enumerate() {
+        let mut basis = 1u8;
+        for (j, other) in shares.iter().enumerate() {
+            if i != j {
+                basis = mul(basis, div(other.x, other.x ^ share.x));
+            }
+        }
+        for (byte, &y) in secret.iter_mut().zip(&share.y) {
+            *byte ^= mul(basis, y);
+        }
+    }
+
+    Ok(secret)
+}
+
+/// Evaluate a polynomial (constant term first) at `x`
+fn evaluate(coefficients: &[u8], x: u8) -> u8 {
+    coefficients
+        .iter()
+        .rev()
+        .fold(0, |acc, &coefficient| mul(acc, x) ^ coefficient)
+}
+
+/// Multiply in GF(2^8), without data-dependent branches
+fn mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0u8;
+    for _ in 0..8 {
+        product ^= a & (b & 1).wrapping_neg();
+        let carry = (a >> 7).wrapping_neg();
+        a = (a << 1) ^ (carry & 0x1b);
+        b >>= 1;
+    }
+    product
+}
+
+/// Divide in GF(2^8); `b` must be non-zero
+fn div(a: u8, b: u8) -> u8 {
+    // b^254 is the inverse of b
+    let mut inverse = 1u8;
+    for _ in 0..254 {
+        inverse = mul(inverse, b);
+    }
+    mul(a, inverse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field_arithmetic() {
+        // FIPS-197 section 4.2 example
+        assert_eq!(mul(0x57, 0x83), 0xc1);
+        for a in 1..=255u8 {
+            assert_eq!(mul(div(1, a), a), 1);
+        }
+    }
+
+    #[test]
+    fn test_any_threshold_subset_combines() {
+        let secret: Vec<u8> = (0..32).collect();
+        let shares = split(&secret, 3, 5).unwrap();
+        assert_eq!(shares.len(), 5);
+
+        for a in 0..5 {
+            for b in a + 1..5 {
+                for c in b + 1..5 {
+                    let subset = [shares[c].clone(), shares[a].clone(), shares[b].clone()];
+                    assert_eq!(&combine(&subset).unwrap()[..], &secret[..]);
+                }
+            }
+        }
+
+        // Too few shares, or the same share twice
+        assert!(combine(&shares[..2]).is_err());
+        let duplicate = [shares[0].clone(), shares[0].clone(), shares[1].clone()];
+        assert!(combine(&duplicate).is_err());
+    }
+
+    #[test]
+    fn test_split_rejects_bad_parameters() {
+        assert!(split(b"secret", 1, 5).is_err());
+        assert!(split(b"secret", 4, 3).is_err());
+        assert!(split(b"", 2, 3).is_err());
+    }
+
+    #[test]
+    fn test_share_encoding() {
+        let shares = split(&[0xab; 32], 2, 3).unwrap();
+        let text = shares[1].encode();
+        assert_eq!(Share::parse(&text).unwrap(), shares[1]);
+        assert_eq!(
+            Share::parse(&text.to_lowercase().replace('-', " ")).unwrap(),
+            shares[1]
+        );
+
+        // A single mistyped character is caught
+        let mut typo = text.into_bytes();
+        typo[8] = if typo[8] == b'0' { b'1' } else { b'0' };
+        let typo = String::from_utf8(typo).unwrap();
+        assert!(Share::parse(&typo).is_err());
+    }
+}
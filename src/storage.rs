@@ -1,25 +1,327 @@
 //! Storage layer for encrypted vault persistence
 
-use crate::crypto::{CipherParams, EncryptionKey, KdfParams};
+use crate::attachment;
+use crate::backup::{self, BackupPolicy};
+use crate::crypto::{
+    CipherAlgorithm, CipherParams, KdfCost, KdfParams, Keyfile, RecoverySlot, Session,
+};
+use crate::migrate;
 use crate::model::Vault;
+use crate::payload::{Compression, Padding, PayloadFormat};
+use crate::shamir::Share;
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
-use std::fs;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use uuid::Uuid;
 
 /// Maximum allowed vault file size (10 MiB) to prevent DoS
 const MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
 
+/// Magic bytes at the start of a binary (v2) vault file
+const MAGIC: &[u8; 8] = b"PASSMNGR";
+
+/// Format version written by `save`
+const FORMAT_VERSION: u32 = 2;
+
+/// Size of the fixed prefix: magic, version (u16) and header length (u32)
+const PREFIX_SIZE: usize = MAGIC.len() + 2 + 4;
+
+/// SHA-256 of the vault file's bytes, used to notice changes made on disk
+pub type Fingerprint = [u8; 32];
+
+/// Tags of the v2 header records
+mod tag {
+    pub const KDF_ALGORITHM: u8 = 0x01;
+    pub const KDF_SALT: u8 = 0x02;
+    pub const KDF_TIME_COST: u8 = 0x03;
+    pub const KDF_MEMORY_COST: u8 = 0x04;
+    pub const KDF_PARALLELISM: u8 = 0x05;
+    pub const KDF_KEYFILE: u8 = 0x06;
+    pub const CIPHER_ALGORITHM: u8 = 0x10;
+    pub const CIPHER_NONCE: u8 = 0x11;
+    pub const WRAPPED_KEY: u8 = 0x20;
+    pub const RECOVERY_KEY: u8 = 0x21;
+    pub const RECOVERY_THRESHOLD: u8 = 0x22;
+    pub const RECOVERY_SHARES: u8 = 0x23;
+    pub const PAYLOAD_COMPRESSION: u8 = 0x30;
+    pub const PAYLOAD_PADDING: u8 = 0x31;
+}
+
 /// Encrypted vault file format
+///
+/// Version 1 is a pretty-printed JSON document (read-only, upgraded on the
+/// next save). Version 2 is a binary container:
+///
+/// ```text
+/// "PASSMNGR" | version: u16 LE | header length: u32 LE | header | ciphertext
+/// ```
+///
+/// The header is a sequence of `tag: u8 | length: u16 LE | value` records with
+/// the KDF and cipher parameters, the data key wrapped by the password key and,
+/// if set up, the recovery slot, and how the plaintext was compressed and
+/// padded (see [`PayloadFormat`]). Files without a wrapped key (written by
+/// older versions, and all v1 files) are encrypted with the password key
+/// directly; files without payload records hold raw JSON. Everything before the
+/// ciphertext is passed to the AEAD as associated data, so the parameters
+/// cannot be changed without decryption failing.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct VaultFile {
     pub version: u32,
     pub kdf: KdfParams,
     pub cipher: CipherParams,
+    /// Data key wrapped by the password key
+    #[serde(skip)]
+    pub wrapped_key: Option<Vec<u8>>,
+    #[serde(skip)]
+    pub recovery: Option<RecoverySlot>,
+    #[serde(skip)]
+    pub payload: PayloadFormat,
     pub ciphertext: Vec<u8>,
 }
 
+/// Append a header record
+fn put_field(out: &mut Vec<u8>, tag: u8, value: &[u8]) -> Result<()> {
+    let len = u16::try_from(value.len())
+        .map_err(|_| anyhow!("Vault header field 0x{:02x} too large", tag))?;
+    out.push(tag);
+    out.extend_from_slice(&len.to_le_bytes());
+    out.extend_from_slice(value);
+    Ok(())
+}
+
+/// Header records by tag
+struct HeaderFields<'a>(BTreeMap<u8, &'a [u8]>);
+
+impl<'a> HeaderFields<'a> {
+    /// Split the header into records, rejecting duplicates and truncation
+    fn parse(mut header: &'a [u8]) -> Result<Self> {
+        let mut fields = BTreeMap::new();
+
+        while !header.is_empty() {
+            if header.len() < 3 {
+                return Err(anyhow!("Truncated vault header"));
+            }
+            let tag = header[0];
+            let len = u16::from_le_bytes([header[1], header[2]]) as usize;
+            let value = header
+                .get(3..3 + len)
+                .ok_or_else(|| anyhow!("Truncated vault header"))?;
+
+            if fields.insert(tag, value).is_some() {
+                return Err(anyhow!("Duplicate vault header field 0x{:02x}", tag));
+            }
+            header = &header[3 + len..];
+        }
+
+        Ok(Self(fields))
+    }
+
+    fn bytes(&mut self, tag: u8) -> Result<&'a [u8]> {
+        self.0
+            .remove(&tag)
+            .ok_or_else(|| anyhow!("Missing vault header field 0x{:02x}", tag))
+    }
+
+    fn string(&mut self, tag: u8) -> Result<String> {
+        String::from_utf8(self.bytes(tag)?.to_vec())
+            .map_err(|_| anyhow!("Invalid vault header field 0x{:02x}", tag))
+    }
+
+    fn u32(&mut self, tag: u8) -> Result<u32> {
+        let bytes = self.bytes(tag)?;
+        let bytes: [u8; 4] = bytes
+            .try_into()
+            .map_err(|_| anyhow!("Invalid vault header field 0x{:02x}", tag))?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn optional_bytes(&mut self, tag: u8) -> Option<&'a [u8]> {
+        self.0.remove(&tag)
+    }
+
+    fn optional_string(&mut self, tag: u8) -> Result<Option<String>> {
+        match self.optional_bytes(tag) {
+            Some(bytes) => String::from_utf8(bytes.to_vec())
+                .map(Some)
+                .map_err(|_| anyhow!("Invalid vault header field 0x{:02x}", tag)),
+            None => Ok(None),
+        }
+    }
+
+    fn u8(&mut self, tag: u8) -> Result<u8> {
+        match self.bytes(tag)? {
+            [value] => Ok(*value),
+            _ => Err(anyhow!("Invalid vault header field 0x{:02x}", tag)),
+        }
+    }
+
+    fn flag(&mut self, tag: u8) -> Result<bool> {
+        match self.bytes(tag)? {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            _ => Err(anyhow!("Invalid vault header field 0x{:02x}", tag)),
+        }
+    }
+
+    /// Fail on records this version does not understand
+    fn finish(self) -> Result<()> {
+        match self.0.keys().next() {
+            Some(tag) => Err(anyhow!("Unknown vault header field 0x{:02x}", tag)),
+            None => Ok(()),
+        }
+    }
+}
+
 impl VaultFile {
+    /// Encode the v2 prefix and header (the associated data of the ciphertext)
+    fn encode_header(&self) -> Result<Vec<u8>> {
+        let mut header = Vec::new();
+        put_field(
+            &mut header,
+            tag::KDF_ALGORITHM,
+            self.kdf.algorithm.as_bytes(),
+        )?;
+        put_field(&mut header, tag::KDF_SALT, &self.kdf.salt)?;
+        put_field(
+            &mut header,
+            tag::KDF_TIME_COST,
+            &self.kdf.time_cost.to_le_bytes(),
+        )?;
+        put_field(
+            &mut header,
+            tag::KDF_MEMORY_COST,
+            &self.kdf.memory_cost.to_le_bytes(),
+        )?;
+        put_field(
+            &mut header,
+            tag::KDF_PARALLELISM,
+            &self.kdf.parallelism.to_le_bytes(),
+        )?;
+        put_field(&mut header, tag::KDF_KEYFILE, &[self.kdf.keyfile as u8])?;
+        put_field(
+            &mut header,
+            tag::CIPHER_ALGORITHM,
+            self.cipher.algorithm.as_bytes(),
+        )?;
+        put_field(&mut header, tag::CIPHER_NONCE, &self.cipher.nonce)?;
+        if let Some(wrapped_key) = &self.wrapped_key {
+            put_field(&mut header, tag::WRAPPED_KEY, wrapped_key)?;
+        }
+        if let Some(recovery) = &self.recovery {
+            put_field(&mut header, tag::RECOVERY_KEY, &recovery.wrapped_key)?;
+            put_field(&mut header, tag::RECOVERY_THRESHOLD, &[recovery.threshold])?;
+            put_field(&mut header, tag::RECOVERY_SHARES, &[recovery.shares])?;
+        }
+        if self.payload.compression != Compression::None {
+            put_field(
+                &mut header,
+                tag::PAYLOAD_COMPRESSION,
+                self.payload.compression.as_str().as_bytes(),
+            )?;
+        }
+        if self.payload.padding != Padding::None {
+            put_field(
+                &mut header,
+                tag::PAYLOAD_PADDING,
+                self.payload.padding.as_str().as_bytes(),
+            )?;
+        }
+
+        let mut out = Vec::with_capacity(PREFIX_SIZE + header.len());
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&(FORMAT_VERSION as u16).to_le_bytes());
+        out.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        out.extend_from_slice(&header);
+        Ok(out)
+    }
+
+    /// Serialize as a v2 container
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut out = self.encode_header()?;
+        out.extend_from_slice(&self.ciphertext);
+        Ok(out)
+    }
+
+    /// Parse a v1 (JSON) or v2 (binary) vault file
+    ///
+    /// Returns the file together with the associated data its ciphertext was
+    /// sealed with (empty for v1).
+    pub fn from_bytes(contents: &[u8]) -> Result<(Self, Vec<u8>)> {
+        if !contents.starts_with(MAGIC) {
+            let vault_file: VaultFile = serde_json::from_slice(contents)?;
+            if vault_file.version != 1 {
+                return Err(anyhow!("Unsupported vault version: {}", vault_file.version));
+            }
+            return Ok((vault_file, Vec::new()));
+        }
+
+        if contents.len() < PREFIX_SIZE {
+            return Err(anyhow!("Truncated vault header"));
+        }
+
+        let version = u16::from_le_bytes([contents[8], contents[9]]) as u32;
+        if version != FORMAT_VERSION {
+            return Err(anyhow!("Unsupported vault version: {}", version));
+        }
+
+        let header_len =
+            u32::from_le_bytes([contents[10], contents[11], contents[12], contents[13]]) as usize;
+        let header_end = PREFIX_SIZE
+            .checked_add(header_len)
+            .filter(|end| *end <= contents.len())
+            .ok_or_else(|| anyhow!("Truncated vault header"))?;
+
+        let mut fields = HeaderFields::parse(&contents[PREFIX_SIZE..header_end])?;
+        let kdf = KdfParams {
+            algorithm: fields.string(tag::KDF_ALGORITHM)?,
+            salt: fields.bytes(tag::KDF_SALT)?.to_vec(),
+            time_cost: fields.u32(tag::KDF_TIME_COST)?,
+            memory_cost: fields.u32(tag::KDF_MEMORY_COST)?,
+            parallelism: fields.u32(tag::KDF_PARALLELISM)?,
+            keyfile: fields.flag(tag::KDF_KEYFILE)?,
+        };
+        let cipher = CipherParams {
+            algorithm: fields.string(tag::CIPHER_ALGORITHM)?,
+            nonce: fields.bytes(tag::CIPHER_NONCE)?.to_vec(),
+        };
+        let wrapped_key = fields.optional_bytes(tag::WRAPPED_KEY).map(<[u8]>::to_vec);
+        let recovery = match fields.optional_bytes(tag::RECOVERY_KEY) {
+            Some(wrapped_key) => Some(RecoverySlot {
+                threshold: fields.u8(tag::RECOVERY_THRESHOLD)?,
+                shares: fields.u8(tag::RECOVERY_SHARES)?,
+                wrapped_key: wrapped_key.to_vec(),
+            }),
+            None => None,
+        };
+        let payload = PayloadFormat {
+            compression: match fields.optional_string(tag::PAYLOAD_COMPRESSION)? {
+                Some(name) => Compression::parse(&name)?,
+                None => Compression::None,
+            },
+            padding: match fields.optional_string(tag::PAYLOAD_PADDING)? {
+                Some(name) => Padding::parse(&name)?,
+                None => Padding::None,
+            },
+        };
+        fields.finish()?;
+
+        let vault_file = VaultFile {
+            version,
+            kdf,
+            cipher,
+            wrapped_key,
+            recovery,
+            payload,
+            ciphertext: contents[header_end..].to_vec(),
+        };
+
+        Ok((vault_file, contents[..header_end].to_vec()))
+    }
+
     /// Get default vault file path
     pub fn default_path() -> Result<PathBuf> {
         let home = dirs::home_dir().ok_or_else(|| anyhow!("Could not determine home directory"))?;
@@ -36,7 +338,112 @@ impl VaultFile {
     }
 
     /// Load and decrypt vault from file
-    pub fn load(path: &Path, password: &str) -> Result<Vault> {
+    pub fn load(path: &Path, password: &str, keyfile: Option<&Keyfile>) -> Result<Vault> {
+        let (vault, _session) = Self::open(path, password, keyfile)?;
+        Ok(vault)
+    }
+
+    /// Load and decrypt vault from file, keeping the derived key for later saves
+    pub fn open(
+        path: &Path,
+        password: &str,
+        keyfile: Option<&Keyfile>,
+    ) -> Result<(Vault, Session)> {
+        let (vault, session, _) = Self::open_with_fingerprint(path, password, keyfile)?;
+        Ok((vault, session))
+    }
+
+    /// Like `open`, also returning the fingerprint of the file that was read
+    pub fn open_with_fingerprint(
+        path: &Path,
+        password: &str,
+        keyfile: Option<&Keyfile>,
+    ) -> Result<(Vault, Session, Fingerprint)> {
+        let contents = Self::read(path)?;
+        let (vault_file, associated_data) = Self::from_bytes(&contents)?;
+
+        // Refuse weak or unsupported parameters before deriving anything
+        vault_file.kdf.validate()?;
+        let cipher = vault_file.cipher.algorithm()?;
+
+        // Derive key from password and unwrap the data key
+        let mut session = Session::derive(password, keyfile, vault_file.kdf.clone())?;
+        if let Some(wrapped_key) = &vault_file.wrapped_key {
+            session.load_key(wrapped_key, vault_file.recovery.clone())?;
+        }
+
+        // Keep the vault's cipher, except that the 96-bit-nonce ChaCha20 of
+        // older versions is upgraded to the default on the next save
+        if cipher != CipherAlgorithm::ChaCha20Poly1305 {
+            session.set_cipher(cipher);
+        }
+
+        // Decrypt vault data
+        let plaintext = vault_file.decrypt(&session, &associated_data)?;
+
+        // Deserialize vault, upgrading older schema versions
+        let vault = migrate::vault_from_json(&plaintext)?;
+
+        Ok((vault, session, fingerprint(&contents)))
+    }
+
+    /// Decrypt and unframe the payload with the session's data key, or with
+    /// its password key for files written before key wrapping
+    fn decrypt(&self, session: &Session, associated_data: &[u8]) -> Result<Vec<u8>> {
+        let key = match self.wrapped_key {
+            Some(_) => session.key(),
+            None => session.password_key(),
+        };
+        let payload = key.decrypt(&self.ciphertext, &self.cipher, associated_data)?;
+        self.payload.decode(&payload)
+    }
+
+    /// Decrypt the current file with an existing session
+    ///
+    /// Fails if the file is no longer encrypted under the session's password,
+    /// e.g. after its master password was changed elsewhere. If the data key
+    /// or recovery slot changed, the session switches to the file's.
+    pub fn reload(path: &Path, session: &mut Session) -> Result<(Vault, Fingerprint)> {
+        let contents = Self::read(path)?;
+        let (vault_file, associated_data) = Self::from_bytes(&contents)?;
+
+        if &vault_file.kdf != session.kdf() {
+            return Err(anyhow!(
+                "Vault on disk was re-keyed; unlock it again to pick up the change"
+            ));
+        }
+        if let Some(wrapped_key) = &vault_file.wrapped_key {
+            session.load_key(wrapped_key, vault_file.recovery.clone())?;
+        }
+
+        let plaintext = vault_file.decrypt(session, &associated_data)?;
+        let vault = migrate::vault_from_json(&plaintext)?;
+
+        Ok((vault, fingerprint(&contents)))
+    }
+
+    /// Decrypt another file of this vault, e.g. a backup, with the data key
+    /// of an existing session
+    ///
+    /// Fails for files encrypted under a different data key.
+    pub fn load_with_session(path: &Path, session: &Session) -> Result<Vault> {
+        let contents = Self::read(path)?;
+        let (vault_file, associated_data) = Self::from_bytes(&contents)?;
+        let plaintext = vault_file.decrypt(session, &associated_data)?;
+        migrate::vault_from_json(&plaintext)
+    }
+
+    /// Fingerprint of the vault file, `None` if it doesn't exist
+    pub fn fingerprint(path: &Path) -> Result<Option<Fingerprint>> {
+        match fs::read(path) {
+            Ok(contents) => Ok(Some(fingerprint(&contents))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Read a vault file, refusing oversized ones
+    fn read(path: &Path) -> Result<Vec<u8>> {
         // Check file size
         let metadata = fs::metadata(path)?;
         if metadata.len() > MAX_FILE_SIZE {
@@ -47,58 +454,202 @@ impl VaultFile {
             ));
         }
 
-        // Read encrypted file
-        let contents = fs::read(path)?;
-        let vault_file: VaultFile = serde_json::from_slice(&contents)?;
+        Ok(fs::read(path)?)
+    }
+
+    /// Encrypt and save vault to file
+    ///
+    /// Reuses the session key; only the nonce is regenerated. Always writes
+    /// the current (v2) format, which upgrades v1 files in place. The file
+    /// being replaced is first copied into a backup generation. Returns the
+    /// fingerprint of the written file.
+    pub fn save(
+        path: &Path,
+        vault: &Vault,
+        session: &Session,
+        backups: &BackupPolicy,
+    ) -> Result<Fingerprint> {
+        // Refuse before a backup rotates out an older generation
+        Self::check_writable(vault)?;
+        backup::create(path, backups)?;
+        Self::write(path, vault, session)
+    }
 
-        // Verify version
-        if vault_file.version != 1 {
-            return Err(anyhow!("Unsupported vault version: {}", vault_file.version));
+    /// Fail for vaults that can't be written without losing data
+    fn check_writable(vault: &Vault) -> Result<()> {
+        // Fields from a newer schema were dropped on load; don't persist that
+        if vault.is_from_newer_version() {
+            return Err(anyhow!(
+                "Vault was written by a newer passmngr (schema v{}); refusing to overwrite it",
+                vault.version
+            ));
         }
+        Ok(())
+    }
 
-        // Derive key from password
-        let key = EncryptionKey::derive(password, &vault_file.kdf)?;
+    /// Encrypt and write vault to file without taking a backup
+    fn write(path: &Path, vault: &Vault, session: &Session) -> Result<Fingerprint> {
+        Self::check_writable(vault)?;
 
-        // Decrypt vault data
-        let plaintext = key.decrypt(&vault_file.ciphertext, &vault_file.cipher)?;
+        // Serialize vault to JSON
+        let plaintext = serde_json::to_vec(vault)?;
+        let contents = Self::encrypt(&plaintext, session)?;
 
-        // Deserialize vault
-        let vault: Vault = serde_json::from_slice(&plaintext)?;
+        // Ensure directory exists
+        Self::ensure_dir(path)?;
 
-        Ok(vault)
+        write_atomic(path, &contents)?;
+        Ok(fingerprint(&contents))
     }
 
-    /// Encrypt and save vault to file
-    pub fn save(path: &Path, vault: &Vault, password: &str) -> Result<()> {
-        // Serialize vault to JSON
-        let plaintext = serde_json::to_vec(vault)?;
+    /// Encrypt serialized vault data into the bytes of a vault file
+    fn encrypt(plaintext: &[u8], session: &Session) -> Result<Vec<u8>> {
+        // Create vault file structure with a fresh nonce for every encryption
+        let mut vault_file = VaultFile {
+            version: FORMAT_VERSION,
+            kdf: session.kdf().clone(),
+            cipher: CipherParams::with_algorithm(session.cipher())?,
+            wrapped_key: Some(session.wrapped_key().to_vec()),
+            recovery: session.recovery().cloned(),
+            payload: PayloadFormat::CURRENT,
+            ciphertext: Vec::new(),
+        };
 
-        // Generate new crypto parameters
-        let kdf_params = KdfParams::new()?;
-        let cipher_params = CipherParams::new()?;
+        // Compress and pad, then encrypt, authenticating the header
+        let payload = vault_file.payload.encode(plaintext)?;
+        let header = vault_file.encode_header()?;
+        vault_file.ciphertext = session
+            .key()
+            .encrypt(&payload, &vault_file.cipher, &header)?;
 
-        // Derive key and encrypt
-        let key = EncryptionKey::derive(password, &kdf_params)?;
-        let ciphertext = key.encrypt(&plaintext, &cipher_params)?;
+        vault_file.to_bytes()
+    }
 
-        // Create vault file structure
-        let vault_file = VaultFile {
-            version: 1,
-            kdf: kdf_params,
-            cipher: cipher_params,
-            ciphertext,
-        };
+    /// Re-encrypt the contents of another file of this vault, e.g. a backup,
+    /// from `old`'s data key to `session`'s
+    ///
+    /// The data itself is left alone, whatever its schema version.
+    pub(crate) fn reencrypt(contents: &[u8], old: &Session, session: &Session) -> Result<Vec<u8>> {
+        let (vault_file, associated_data) = Self::from_bytes(contents)?;
+        let plaintext = vault_file.decrypt(old, &associated_data)?;
+        Self::encrypt(&plaintext, session)
+    }
 
-        // Ensure directory exists
-        Self::ensure_dir(path)?;
+    /// Write the vault under a new data key, re-encrypting its attachments
+    /// and backups along with it
+    ///
+    /// `old` holds the data key they are encrypted with now. Attachments and
+    /// backups are re-encrypted into temporary files first and only swapped
+    /// in once the vault is written; ones `old` can't decrypt are only
+    /// readable with earlier secrets and are deleted.
+    fn write_rekeyed(path: &Path, vault: &Vault, old: &Session, session: &Session) -> Result<()> {
+        let mut rekeyed = Rekeyed::default();
+        let result = attachment::rekey(path, old, session, &mut rekeyed)
+            .and_then(|()| backup::rekey(path, old, session, &mut rekeyed))
+            .and_then(|()| Self::write(path, vault, session));
+        match result {
+            Ok(_) => rekeyed.commit(),
+            Err(e) => {
+                rekeyed.discard();
+                Err(e)
+            }
+        }
+    }
 
-        // Write to temp file first, then rename (atomic operation)
-        let temp_path = path.with_extension("tmp");
-        let contents = serde_json::to_vec_pretty(&vault_file)?;
-        fs::write(&temp_path, contents)?;
-        fs::rename(&temp_path, path)?;
+    /// Change the master password of the vault at `path`
+    ///
+    /// Verifies the old secrets by decrypting the vault, then rewrites it, its
+    /// attachments and its backups under a fresh data key, wrapped under a key
+    /// derived from the new secrets and a fresh salt. Nothing written from now
+    /// on can be decrypted with the old secrets, not even with the help of an
+    /// old copy of the vault.
+    /// Passing a different `new_keyfile` adds, swaps or (with `None`) removes
+    /// the keyfile factor. `cost` and `cipher` replace the KDF cost and the
+    /// cipher algorithm; `None` keeps the vault's current ones. Recovery
+    /// shares stop working and have to be set up again. Returns the new
+    /// session; any session derived from the old secrets no longer matches the
+    /// file and must be discarded.
+    pub fn change_password(
+        path: &Path,
+        old_password: &str,
+        old_keyfile: Option<&Keyfile>,
+        new_password: &str,
+        new_keyfile: Option<&Keyfile>,
+        cost: Option<KdfCost>,
+        cipher: Option<CipherAlgorithm>,
+    ) -> Result<Session> {
+        let (vault, mut session) = Self::open(path, old_password, old_keyfile)?;
+        let cost = cost.unwrap_or_else(|| session.kdf().cost());
+        let old = session.rekey(new_password, new_keyfile, cost)?;
+        if let Some(cipher) = cipher {
+            session.set_cipher(cipher);
+        }
+        // Backups are re-encrypted, so they don't open with the old password
+        Self::write_rekeyed(path, &vault, &old, &session)?;
+        Ok(session)
+    }
 
-        Ok(())
+    /// Create recovery shares for the vault at `path`
+    ///
+    /// Any `threshold` of the returned `shares` can later reset the master
+    /// password with `recover`. Like `change_password`, this switches to a
+    /// fresh data key, so earlier shares stop working, even on old copies
+    /// of the vault.
+    pub fn setup_recovery(
+        path: &Path,
+        password: &str,
+        keyfile: Option<&Keyfile>,
+        threshold: u8,
+        shares: u8,
+    ) -> Result<Vec<Share>> {
+        let (vault, mut session) = Self::open(path, password, keyfile)?;
+        let old = session.rotate_key()?;
+        let shares = session.setup_recovery(threshold, shares)?;
+        Self::write_rekeyed(path, &vault, &old, &session)?;
+        Ok(shares)
+    }
+
+    /// Recovery slot of the vault at `path`, if recovery shares were set up
+    pub fn recovery(path: &Path) -> Result<Option<RecoverySlot>> {
+        let (vault_file, _) = Self::from_bytes(&Self::read(path)?)?;
+        Ok(vault_file.recovery)
+    }
+
+    /// Unlock the vault at `path` with recovery shares and set a new password
+    ///
+    /// No backup is taken and any other session no longer matches the file.
+    /// The data key is kept, so the shares keep working. Returns the new
+    /// session.
+    pub fn recover(
+        path: &Path,
+        shares: &[Share],
+        new_password: &str,
+        new_keyfile: Option<&Keyfile>,
+    ) -> Result<Session> {
+        let contents = Self::read(path)?;
+        let (vault_file, associated_data) = Self::from_bytes(&contents)?;
+        vault_file.kdf.validate()?;
+        let cipher = vault_file.cipher.algorithm()?;
+        let recovery = vault_file
+            .recovery
+            .clone()
+            .ok_or_else(|| anyhow!("Recovery is not set up for this vault"))?;
+
+        let mut session = Session::recover(
+            shares,
+            recovery,
+            new_password,
+            new_keyfile,
+            vault_file.kdf.cost(),
+        )?;
+        if cipher != CipherAlgorithm::ChaCha20Poly1305 {
+            session.set_cipher(cipher);
+        }
+
+        let plaintext = vault_file.decrypt(&session, &associated_data)?;
+        let vault = migrate::vault_from_json(&plaintext)?;
+        Self::write(path, &vault, &session)?;
+        Ok(session)
     }
 
     /// Check if vault file exists
@@ -107,6 +658,127 @@ impl VaultFile {
     }
 }
 
+/// Fingerprint of vault file contents
+fn fingerprint(contents: &[u8]) -> Fingerprint {
+    Sha256::digest(contents).into()
+}
+
+/// Files re-encrypted under a new data key, waiting to be swapped in
+#[derive(Default)]
+pub(crate) struct Rekeyed {
+    /// Temp files and the files they replace
+    staged: Vec<(PathBuf, PathBuf)>,
+    /// Files to delete
+    unreadable: Vec<PathBuf>,
+}
+
+impl Rekeyed {
+    /// Write the new `contents` of `path` to a temp file next to it
+    pub(crate) fn stage(&mut self, path: &Path, contents: &[u8]) -> Result<()> {
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| anyhow!("Invalid path: {}", path.display()))?;
+        let temp_path = path.with_file_name(format!(
+            ".{}.{}.tmp",
+            file_name.to_string_lossy(),
+            Uuid::new_v4().simple()
+        ));
+        self.staged.push((temp_path.clone(), path.to_path_buf()));
+        write_synced(&temp_path, contents)
+    }
+
+    /// Delete `path` along with the swap
+    pub(crate) fn remove(&mut self, path: &Path) {
+        self.unreadable.push(path.to_path_buf());
+    }
+
+    /// Swap in the staged files
+    fn commit(self) -> Result<()> {
+        for (temp_path, path) in &self.staged {
+            fs::rename(temp_path, path)?;
+        }
+        for path in &self.unreadable {
+            fs::remove_file(path)?;
+        }
+        let dirs: std::collections::BTreeSet<_> = self
+            .staged
+            .iter()
+            .map(|(_, path)| path)
+            .chain(&self.unreadable)
+            .filter_map(|path| path.parent())
+            .collect();
+        for dir in dirs {
+            sync_dir(dir)?;
+        }
+        Ok(())
+    }
+
+    /// Drop the staged files, leaving everything as it was
+    fn discard(self) {
+        for (temp_path, _) in &self.staged {
+            let _ = fs::remove_file(temp_path);
+        }
+    }
+}
+
+/// Replace `path` with `contents` via a temp file and rename
+///
+/// The temp file has a unique name in the same directory, so concurrent
+/// writers never share it. Both the file and the directory are synced, so
+/// the rename survives a crash.
+pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("Invalid vault path: {}", path.display()))?;
+    let temp_path = dir.join(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        Uuid::new_v4().simple()
+    ));
+
+    let result = write_synced(&temp_path, contents).and_then(|()| {
+        fs::rename(&temp_path, path)?;
+        sync_dir(dir)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Write a new file and flush it to disk
+fn write_synced(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Flush a directory entry change (the rename) to disk
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> Result<()> {
+    fs::File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+/// Directories can't be opened for syncing here
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> Result<()> {
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -129,13 +801,14 @@ mod tests {
         ));
 
         let password = "test_master_password";
+        let session = Session::new(password, None, KdfCost::MIN).unwrap();
 
         // Save vault
-        VaultFile::save(&vault_path, &vault, password).unwrap();
+        VaultFile::save(&vault_path, &vault, &session, &BackupPolicy::default()).unwrap();
         assert!(VaultFile::exists(&vault_path));
 
         // Load vault
-        let loaded_vault = VaultFile::load(&vault_path, password).unwrap();
+        let loaded_vault = VaultFile::load(&vault_path, password, None).unwrap();
         assert_eq!(loaded_vault.entries.len(), 1);
         assert_eq!(loaded_vault.entries[0].name, "Test Entry");
     }
@@ -146,9 +819,526 @@ mod tests {
         let vault_path = temp_dir.path().join("test_vault.enc");
 
         let vault = Vault::new();
-        VaultFile::save(&vault_path, &vault, "correct_password").unwrap();
+        let session = Session::new("correct_password", None, KdfCost::MIN).unwrap();
+        VaultFile::save(&vault_path, &vault, &session, &BackupPolicy::default()).unwrap();
 
-        let result = VaultFile::load(&vault_path, "wrong_password");
+        let result = VaultFile::load(&vault_path, "wrong_password", None);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_change_password() {
+        let temp_dir = TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("test_vault.enc");
+
+        let mut vault = Vault::new();
+        vault.add_entry(Entry::new(
+            "Kept".to_string(),
+            "user".to_string(),
+            "pass".to_string(),
+            None,
+            None,
+            vec![],
+        ));
+        let session = Session::new("old_password", None, KdfCost::MIN).unwrap();
+        VaultFile::save(&vault_path, &vault, &session, &BackupPolicy::default()).unwrap();
+
+        assert!(VaultFile::change_password(
+            &vault_path,
+            "wrong_password",
+            None,
+            "new_password",
+            None,
+            None,
+            None,
+        )
+        .is_err());
+
+        let new_session = VaultFile::change_password(
+            &vault_path,
+            "old_password",
+            None,
+            "new_password",
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        assert_ne!(new_session.kdf().salt, session.kdf().salt);
+        assert_eq!(new_session.kdf().cost(), KdfCost::MIN);
+
+        assert!(VaultFile::load(&vault_path, "old_password", None).is_err());
+        let loaded = VaultFile::load(&vault_path, "new_password", None).unwrap();
+        assert_eq!(loaded.entries.len(), 1);
+        assert_eq!(loaded.entries[0].name, "Kept");
+    }
+
+    #[test]
+    fn test_old_copies_do_not_open_rekeyed_vault() {
+        let temp_dir = TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("test_vault.enc");
+        let policy = BackupPolicy::default();
+
+        let session = Session::new("old_password", None, KdfCost::MIN).unwrap();
+        let mut vault = Vault::new();
+        let mut entry = Entry::new(
+            "Server".to_string(),
+            "root".to_string(),
+            "pass".to_string(),
+            None,
+            None,
+            vec![],
+        );
+        let file = attachment::add(&vault_path, &session, "id_ed25519", b"private key").unwrap();
+        entry.attachments.push(file.clone());
+        vault.add_entry(entry);
+        VaultFile::save(&vault_path, &vault, &session, &policy).unwrap();
+        VaultFile::save(&vault_path, &vault, &session, &policy).unwrap();
+        let old_copy = temp_dir.path().join("old_copy.enc");
+        fs::copy(&vault_path, &old_copy).unwrap();
+
+        let new_session = VaultFile::change_password(
+            &vault_path,
+            "old_password",
+            None,
+            "new_password",
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+
+        // The old password still opens the old copy, but its data key opens
+        // nothing written since
+        let (_, old_session) = VaultFile::open(&old_copy, "old_password", None).unwrap();
+        assert!(VaultFile::load_with_session(&vault_path, &old_session).is_err());
+        assert!(attachment::read(&vault_path, &old_session, &file).is_err());
+        let backups = backup::list(&vault_path).unwrap();
+        assert_eq!(backups.len(), 1);
+        assert!(VaultFile::load(&backups[0].path, "old_password", None).is_err());
+        assert!(VaultFile::load_with_session(&backups[0].path, &old_session).is_err());
+
+        // Everything opens with the new password
+        assert!(VaultFile::load(&backups[0].path, "new_password", None).is_ok());
+        assert_eq!(
+            attachment::read(&vault_path, &new_session, &file).unwrap(),
+            b"private key"
+        );
+
+        // Setting up recovery switches the data key too
+        VaultFile::setup_recovery(&vault_path, "new_password", None, 2, 3).unwrap();
+        assert!(VaultFile::load_with_session(&vault_path, &new_session).is_err());
+        let (_, session) = VaultFile::open(&vault_path, "new_password", None).unwrap();
+        assert!(attachment::read(&vault_path, &session, &file).is_ok());
+        assert!(VaultFile::load(&backups[0].path, "new_password", None).is_ok());
+
+        // No temp files are left behind
+        for dir in [
+            temp_dir.path().to_path_buf(),
+            attachment::attachment_dir(&vault_path),
+            backup::backup_dir(&vault_path),
+        ] {
+            for dir_entry in fs::read_dir(dir).unwrap() {
+                let name = dir_entry.unwrap().file_name();
+                assert!(!name.to_string_lossy().ends_with(".tmp"), "{:?}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_keyfile_vault() {
+        let temp_dir = TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("test_vault.enc");
+        let keyfile = Keyfile::from_bytes(b"second factor");
+
+        let session = Session::new("master_password", Some(&keyfile), KdfCost::MIN).unwrap();
+        VaultFile::save(
+            &vault_path,
+            &Vault::new(),
+            &session,
+            &BackupPolicy::default(),
+        )
+        .unwrap();
+
+        assert!(VaultFile::load(&vault_path, "master_password", None).is_err());
+        assert!(VaultFile::load(&vault_path, "master_password", Some(&keyfile)).is_ok());
+
+        // Dropping the keyfile on rekey makes the password sufficient again
+        VaultFile::change_password(
+            &vault_path,
+            "master_password",
+            Some(&keyfile),
+            "master_password",
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        assert!(VaultFile::load(&vault_path, "master_password", None).is_ok());
+    }
+
+    #[test]
+    fn test_save_reuses_session_key() {
+        let temp_dir = TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("test_vault.enc");
+
+        let mut vault = Vault::new();
+        let session = Session::new("master_password", None, KdfCost::MIN).unwrap();
+        VaultFile::save(&vault_path, &vault, &session, &BackupPolicy::default()).unwrap();
+        let (_, session) = VaultFile::open(&vault_path, "master_password", None).unwrap();
+
+        vault.add_entry(Entry::new(
+            "Second Save".to_string(),
+            "user".to_string(),
+            "pass".to_string(),
+            None,
+            None,
+            vec![],
+        ));
+        VaultFile::save(&vault_path, &vault, &session, &BackupPolicy::default()).unwrap();
+
+        // Same salt on disk, still readable with the original password
+        let loaded = VaultFile::load(&vault_path, "master_password", None).unwrap();
+        assert_eq!(loaded.entries.len(), 1);
+        let contents = fs::read(&vault_path).unwrap();
+        let (vault_file, _) = VaultFile::from_bytes(&contents).unwrap();
+        assert_eq!(vault_file.kdf.salt, session.kdf().salt);
+    }
+
+    #[test]
+    fn test_fingerprint_and_reload() {
+        let temp_dir = TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("test_vault.enc");
+        let policy = BackupPolicy::disabled();
+
+        assert!(VaultFile::fingerprint(&vault_path).unwrap().is_none());
+
+        let session = Session::new("master_password", None, KdfCost::MIN).unwrap();
+        let saved = VaultFile::save(&vault_path, &Vault::new(), &session, &policy).unwrap();
+        let (_, mut session, opened) =
+            VaultFile::open_with_fingerprint(&vault_path, "master_password", None).unwrap();
+        assert_eq!(saved, opened);
+        assert_eq!(VaultFile::fingerprint(&vault_path).unwrap(), Some(saved));
+
+        // Another writer with the same key
+        let mut vault = Vault::new();
+        vault.add_entry(Entry::new(
+            "Elsewhere".to_string(),
+            "user".to_string(),
+            "pass".to_string(),
+            None,
+            None,
+            vec![],
+        ));
+        let changed = VaultFile::save(&vault_path, &vault, &session, &policy).unwrap();
+        assert_ne!(changed, saved);
+
+        let (reloaded, fingerprint) = VaultFile::reload(&vault_path, &mut session).unwrap();
+        assert_eq!(reloaded.entries.len(), 1);
+        assert_eq!(fingerprint, changed);
+
+        // Re-keyed elsewhere: the old session can't read it
+        VaultFile::change_password(
+            &vault_path,
+            "master_password",
+            None,
+            "another_password",
+            None,
+            Some(KdfCost::MIN),
+            None,
+        )
+        .unwrap();
+        assert!(VaultFile::reload(&vault_path, &mut session).is_err());
+    }
+
+    #[test]
+    fn test_write_atomic_leaves_no_temp_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("test_vault.enc");
+
+        write_atomic(&vault_path, b"first").unwrap();
+        write_atomic(&vault_path, b"second").unwrap();
+        assert_eq!(fs::read(&vault_path).unwrap(), b"second");
+
+        let names: Vec<_> = fs::read_dir(temp_dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names, vec![std::ffi::OsString::from("test_vault.enc")]);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&vault_path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn test_load_rejects_weak_kdf() {
+        let temp_dir = TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("test_vault.enc");
+
+        let session = Session::new("master_password", None, KdfCost::MIN).unwrap();
+        VaultFile::save(
+            &vault_path,
+            &Vault::new(),
+            &session,
+            &BackupPolicy::default(),
+        )
+        .unwrap();
+
+        // Rewrite the header with a cheaper memory cost
+        let contents = fs::read(&vault_path).unwrap();
+        let (mut vault_file, _) = VaultFile::from_bytes(&contents).unwrap();
+        vault_file.kdf.memory_cost = 8;
+        fs::write(&vault_path, vault_file.to_bytes().unwrap()).unwrap();
+
+        let err = VaultFile::load(&vault_path, "master_password", None).unwrap_err();
+        assert!(err.to_string().contains("below the minimum"));
+    }
+
+    #[test]
+    fn test_v1_upgraded_on_save() {
+        let temp_dir = TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("test_vault.enc");
+
+        let mut vault = Vault::new();
+        vault.add_entry(Entry::new(
+            "Legacy".to_string(),
+            "user".to_string(),
+            "pass".to_string(),
+            None,
+            None,
+            vec![],
+        ));
+
+        // Write a v1 file: pretty JSON, ChaCha20-Poly1305, no associated data
+        let session = Session::new("master_password", None, KdfCost::MIN).unwrap();
+        let cipher = CipherParams::with_algorithm(CipherAlgorithm::ChaCha20Poly1305).unwrap();
+        let plaintext = serde_json::to_vec(&vault).unwrap();
+        let v1 = VaultFile {
+            version: 1,
+            kdf: session.kdf().clone(),
+            ciphertext: session
+                .password_key()
+                .encrypt(&plaintext, &cipher, b"")
+                .unwrap(),
+            cipher,
+            wrapped_key: None,
+            recovery: None,
+            payload: PayloadFormat::RAW,
+        };
+        let v1_bytes = serde_json::to_vec_pretty(&v1).unwrap();
+        fs::write(&vault_path, &v1_bytes).unwrap();
+
+        let (loaded, session) = VaultFile::open(&vault_path, "master_password", None).unwrap();
+        assert_eq!(loaded.entries[0].name, "Legacy");
+
+        VaultFile::save(&vault_path, &loaded, &session, &BackupPolicy::default()).unwrap();
+        let v2_bytes = fs::read(&vault_path).unwrap();
+        assert!(v2_bytes.starts_with(MAGIC));
+        assert!(v2_bytes.len() < v1_bytes.len());
+        let (v2, _) = VaultFile::from_bytes(&v2_bytes).unwrap();
+        assert_eq!(v2.cipher.algorithm, "xchacha20poly1305");
+
+        let reloaded = VaultFile::load(&vault_path, "master_password", None).unwrap();
+        assert_eq!(reloaded.entries[0].name, "Legacy");
+    }
+
+    #[test]
+    fn test_cipher_kept_across_saves() {
+        let temp_dir = TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("test_vault.enc");
+        let policy = BackupPolicy::disabled();
+        let algorithm = |path: &Path| {
+            let (vault_file, _) = VaultFile::from_bytes(&fs::read(path).unwrap()).unwrap();
+            vault_file.cipher.algorithm
+        };
+
+        let mut session = Session::new("master_password", None, KdfCost::MIN).unwrap();
+        assert_eq!(session.cipher(), CipherAlgorithm::XChaCha20Poly1305);
+        session.set_cipher(CipherAlgorithm::Aes256GcmSiv);
+        VaultFile::save(&vault_path, &Vault::new(), &session, &policy).unwrap();
+
+        let (vault, session) = VaultFile::open(&vault_path, "master_password", None).unwrap();
+        assert_eq!(session.cipher(), CipherAlgorithm::Aes256GcmSiv);
+        VaultFile::save(&vault_path, &vault, &session, &policy).unwrap();
+        assert_eq!(algorithm(&vault_path), "aes-256-gcm-siv");
+
+        // Switching on password change
+        VaultFile::change_password(
+            &vault_path,
+            "master_password",
+            None,
+            "master_password",
+            None,
+            None,
+            Some(CipherAlgorithm::XChaCha20Poly1305),
+        )
+        .unwrap();
+        assert_eq!(algorithm(&vault_path), "xchacha20poly1305");
+        assert!(VaultFile::load(&vault_path, "master_password", None).is_ok());
+    }
+
+    #[test]
+    fn test_header_is_authenticated() {
+        let temp_dir = TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("test_vault.enc");
+
+        let session = Session::new("master_password", None, KdfCost::MIN).unwrap();
+        VaultFile::save(
+            &vault_path,
+            &Vault::new(),
+            &session,
+            &BackupPolicy::default(),
+        )
+        .unwrap();
+        let contents = fs::read(&vault_path).unwrap();
+        let (vault_file, header) = VaultFile::from_bytes(&contents).unwrap();
+        assert_eq!(vault_file.version, FORMAT_VERSION);
+
+        // Swap the cipher algorithm name for one of equal length
+        let mut tampered = vault_file;
+        tampered.cipher.algorithm = tampered.cipher.algorithm.to_uppercase();
+        let tampered_bytes = tampered.to_bytes().unwrap();
+        assert_eq!(tampered_bytes.len(), contents.len());
+        assert_ne!(&tampered_bytes[..header.len()], &header[..]);
+
+        fs::write(&vault_path, &tampered_bytes).unwrap();
+        assert!(VaultFile::load(&vault_path, "master_password", None).is_err());
+    }
+
+    #[test]
+    fn test_header_parsing_errors() {
+        let session = Session::new("master_password", None, KdfCost::MIN).unwrap();
+        let vault_file = VaultFile {
+            version: FORMAT_VERSION,
+            kdf: session.kdf().clone(),
+            cipher: CipherParams::new().unwrap(),
+            wrapped_key: Some(session.wrapped_key().to_vec()),
+            recovery: None,
+            payload: PayloadFormat::CURRENT,
+            ciphertext: vec![1, 2, 3],
+        };
+        let bytes = vault_file.to_bytes().unwrap();
+        assert!(VaultFile::from_bytes(&bytes).is_ok());
+
+        // Truncated inside the header
+        assert!(VaultFile::from_bytes(&bytes[..PREFIX_SIZE + 5]).is_err());
+
+        // Unknown version
+        let mut future = bytes.clone();
+        future[8] = 9;
+        assert!(VaultFile::from_bytes(&future).is_err());
+
+        // Unknown header field
+        let mut unknown = bytes[..PREFIX_SIZE].to_vec();
+        let mut header = bytes[PREFIX_SIZE..bytes.len() - 3].to_vec();
+        put_field(&mut header, 0x7f, b"?").unwrap();
+        unknown[10..14].copy_from_slice(&(header.len() as u32).to_le_bytes());
+        unknown.extend_from_slice(&header);
+        assert!(VaultFile::from_bytes(&unknown).is_err());
+    }
+
+    #[test]
+    fn test_payload_is_padded() {
+        let temp_dir = TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("test_vault.enc");
+        let policy = BackupPolicy::disabled();
+        let session = Session::new("master_password", None, KdfCost::MIN).unwrap();
+
+        // One entry more doesn't change the file size
+        let mut vault = Vault::new();
+        let mut sizes = Vec::new();
+        for name in ["First", "Second"] {
+            vault.add_entry(Entry::new(
+                name.to_string(),
+                "user".to_string(),
+                "pass".to_string(),
+                None,
+                None,
+                vec![],
+            ));
+            VaultFile::save(&vault_path, &vault, &session, &policy).unwrap();
+            sizes.push(fs::metadata(&vault_path).unwrap().len());
+        }
+        assert_eq!(sizes[0], sizes[1]);
+
+        let (vault_file, _) = VaultFile::from_bytes(&fs::read(&vault_path).unwrap()).unwrap();
+        assert_eq!(vault_file.payload, PayloadFormat::CURRENT);
+
+        // Files from before framing hold raw JSON
+        let mut raw = VaultFile {
+            payload: PayloadFormat::RAW,
+            ..vault_file
+        };
+        let header = raw.encode_header().unwrap();
+        raw.ciphertext = session
+            .key()
+            .encrypt(&serde_json::to_vec(&vault).unwrap(), &raw.cipher, &header)
+            .unwrap();
+        fs::write(&vault_path, raw.to_bytes().unwrap()).unwrap();
+        let loaded = VaultFile::load(&vault_path, "master_password", None).unwrap();
+        assert_eq!(loaded.entries.len(), 2);
+    }
+
+    #[test]
+    fn test_recovery_resets_password() {
+        let temp_dir = TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("test_vault.enc");
+        let policy = BackupPolicy::disabled();
+
+        let mut vault = Vault::new();
+        vault.add_entry(Entry::new(
+            "Recovered".to_string(),
+            "user".to_string(),
+            "pass".to_string(),
+            None,
+            None,
+            vec![],
+        ));
+        let session = Session::new("forgotten_password", None, KdfCost::MIN).unwrap();
+        VaultFile::save(&vault_path, &vault, &session, &policy).unwrap();
+        assert!(VaultFile::recovery(&vault_path).unwrap().is_none());
+        assert!(VaultFile::recover(&vault_path, &[], "new_password", None).is_err());
+
+        let shares =
+            VaultFile::setup_recovery(&vault_path, "forgotten_password", None, 3, 5).unwrap();
+        let slot = VaultFile::recovery(&vault_path).unwrap().unwrap();
+        assert_eq!((slot.threshold, slot.shares), (3, 5));
+
+        // Shares survive a normal open and save
+        let (vault, session) = VaultFile::open(&vault_path, "forgotten_password", None).unwrap();
+        VaultFile::save(&vault_path, &vault, &session, &policy).unwrap();
+
+        assert!(VaultFile::recover(&vault_path, &shares[..2], "new_password", None).is_err());
+        VaultFile::recover(&vault_path, &shares[2..], "new_password", None).unwrap();
+
+        assert!(VaultFile::load(&vault_path, "forgotten_password", None).is_err());
+        let loaded = VaultFile::load(&vault_path, "new_password", None).unwrap();
+        assert_eq!(loaded.entries[0].name, "Recovered");
+        assert_eq!(VaultFile::recovery(&vault_path).unwrap(), Some(slot));
+    }
+
+    #[test]
+    fn test_save_refuses_newer_schema() {
+        let temp_dir = TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("test_vault.enc");
+
+        let session = Session::new("master_password", None, KdfCost::MIN).unwrap();
+        let policy = BackupPolicy::default();
+        let mut vault = Vault::new();
+        vault.version += 1;
+
+        assert!(VaultFile::save(&vault_path, &vault, &session, &policy).is_err());
+        assert!(!VaultFile::exists(&vault_path));
+
+        // No backup is taken of the vault that stays in place
+        vault.version -= 1;
+        VaultFile::save(&vault_path, &vault, &session, &policy).unwrap();
+        vault.version += 1;
+        assert!(VaultFile::save(&vault_path, &vault, &session, &policy).is_err());
+        assert!(backup::list(&vault_path).unwrap().is_empty());
+    }
 }
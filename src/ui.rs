@@ -1,11 +1,13 @@
 //! Terminal UI rendering
 
-use passmngr::app::{App, Mode};
+use chrono::Utc;
+use passmngr::app::{App, Mode, UnlockField};
+use passmngr::model::ExpiryState;
 use ratatui::{
     layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Line, Span, Text},
-    widgets::{Block, Borders, List, ListItem, Paragraph, Wrap},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
     Frame,
 };
 
@@ -47,8 +49,18 @@ pub fn render(f: &mut Frame, app: &mut App) {
 /// Render the header with app name and status
 fn render_header(f: &mut Frame, app: &App, area: Rect) {
     let mode_text = format!("[{}]", app.mode.as_str());
-    let count_text = format!("{} entries", app.filtered_entries.len());
-    let status_text = if app.dirty { "modified" } else { "saved" };
+    let count_text = format!(
+        "{} entries, by {}",
+        app.filtered_entries.len(),
+        app.sort_order.as_str()
+    );
+    let status_text = if app.read_only_pid().is_some() {
+        "read-only"
+    } else if app.dirty {
+        "modified"
+    } else {
+        "saved"
+    };
 
     let header = Paragraph::new(Line::from(vec![
         Span::styled(
@@ -57,6 +69,10 @@ fn render_header(f: &mut Frame, app: &App, area: Rect) {
                 .fg(Color::Cyan)
                 .add_modifier(Modifier::BOLD),
         ),
+        Span::styled(
+            format!("{} ", app.vault_name),
+            Style::default().fg(Color::White),
+        ),
         Span::styled(
             &mode_text,
             Style::default()
@@ -67,6 +83,11 @@ fn render_header(f: &mut Frame, app: &App, area: Rect) {
                     Mode::Command => Color::Magenta,
                     Mode::Detail => Color::Cyan,
                     Mode::Locked => Color::Red,
+                    Mode::Passwd => Color::Red,
+                    Mode::Conflict => Color::Red,
+                    Mode::Trash => Color::Magenta,
+                    Mode::Folders => Color::Blue,
+                    Mode::Templates => Color::Yellow,
                 })
                 .add_modifier(Modifier::BOLD),
         ),
@@ -75,7 +96,9 @@ fn render_header(f: &mut Frame, app: &App, area: Rect) {
         Span::raw(" | "),
         Span::styled(
             status_text,
-            Style::default().fg(if app.dirty {
+            Style::default().fg(if app.read_only_pid().is_some() {
+                Color::Red
+            } else if app.dirty {
                 Color::Yellow
             } else {
                 Color::Green
@@ -115,10 +138,69 @@ fn render_main_content(f: &mut Frame, app: &mut App, area: Rect) {
         Mode::Insert => render_form_view(f, app, area),
         Mode::Detail => render_detail_view(f, app, area),
         Mode::Locked => render_locked_view(f, app, area),
+        Mode::Passwd => render_passwd_view(f, app, area),
+        Mode::Conflict => render_conflict_view(f, app, area),
+        Mode::Trash => render_trash_view(f, app, area),
+        Mode::Templates => render_template_picker(f, app, area),
+        _ if app.mode == Mode::Folders || !app.vault.folders.is_empty() => {
+            let chunks = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Length(28), Constraint::Min(0)])
+                .split(area);
+            render_folder_pane(f, app, chunks[0]);
+            render_list_view(f, app, chunks[1]);
+        }
         _ => render_list_view(f, app, area),
     }
 }
 
+/// Render the folder tree next to the entry list
+fn render_folder_pane(f: &mut Frame, app: &App, area: Rect) {
+    let focused = app.mode == Mode::Folders;
+    let width = area.width.saturating_sub(4) as usize;
+
+    let items: Vec<ListItem> = app
+        .folder_rows()
+        .into_iter()
+        .enumerate()
+        .map(|(i, row)| {
+            let marker = match (row.has_children, row.collapsed) {
+                (true, true) => "▸ ",
+                (true, false) => "▾ ",
+                (false, _) => "  ",
+            };
+            let text = format!("{}{}{}", "  ".repeat(row.depth), marker, row.name);
+            let is_current = row.id == app.folder_filter;
+            let style = if focused && i == app.folder_selected {
+                Style::default().fg(Color::White).bg(Color::DarkGray)
+            } else if is_current {
+                Style::default()
+                    .fg(Color::Cyan)
+                    .add_modifier(Modifier::BOLD)
+            } else {
+                Style::default().fg(Color::Gray)
+            };
+            ListItem::new(Line::from(Span::styled(
+                truncate_string(&text, width),
+                style,
+            )))
+        })
+        .collect();
+
+    let border = if focused {
+        Color::Blue
+    } else {
+        Color::DarkGray
+    };
+    let list = List::new(items).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(border))
+            .title("Folders"),
+    );
+    f.render_widget(list, area);
+}
+
 /// Render the locked view
 fn render_locked_view(f: &mut Frame, app: &mut App, area: Rect) {
     let block = Block::default()
@@ -133,6 +215,7 @@ fn render_locked_view(f: &mut Frame, app: &mut App, area: Rect) {
         .constraints([
             Constraint::Percentage(40),
             Constraint::Length(3),
+            Constraint::Length(3),
             Constraint::Percentage(40),
         ])
         .split(inner_area);
@@ -150,11 +233,280 @@ fn render_locked_view(f: &mut Frame, app: &mut App, area: Rect) {
 
     f.render_widget(title, chunks[0]);
 
+    let focused_style = |field| {
+        Style::default().fg(if app.unlock_field == field {
+            Color::White
+        } else {
+            Color::Gray
+        })
+    };
+
     let input = Paragraph::new(format!("Password: {}", "*".repeat(app.unlock_input.len())))
-        .style(Style::default().fg(Color::White))
+        .style(focused_style(UnlockField::Password))
         .block(Block::default().borders(Borders::ALL).title("Unlock"));
 
     f.render_widget(input, chunks[1]);
+
+    let keyfile = Paragraph::new(format!("Keyfile:  {}", app.unlock_keyfile))
+        .style(focused_style(UnlockField::Keyfile))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Keyfile (optional)"),
+        );
+
+    f.render_widget(keyfile, chunks[2]);
+}
+
+/// Render the master password change prompt
+fn render_passwd_view(f: &mut Frame, app: &App, area: Rect) {
+    use passmngr::app::PasswdStage;
+
+    let stages = [PasswdStage::Current, PasswdStage::New, PasswdStage::Confirm];
+
+    let mut lines = vec![Line::from("")];
+
+    for stage in stages.iter() {
+        let is_focused = &app.passwd_form.stage == stage;
+        let masked = "*".repeat(app.passwd_form.input(*stage).chars().count());
+
+        lines.push(Line::from(vec![
+            Span::styled(
+                format!("{:<18} ", stage.as_str()),
+                Style::default().fg(if is_focused {
+                    Color::Yellow
+                } else {
+                    Color::Cyan
+                }),
+            ),
+            Span::styled(
+                if is_focused {
+                    format!("{}_", masked)
+                } else {
+                    masked
+                },
+                Style::default().fg(if is_focused {
+                    Color::White
+                } else {
+                    Color::Gray
+                }),
+            ),
+        ]));
+        lines.push(Line::from(""));
+    }
+
+    let paragraph = Paragraph::new(Text::from(lines)).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title("Change Master Password"),
+    );
+
+    f.render_widget(paragraph, area);
+}
+
+/// Render the entries changed both here and on disk
+fn render_conflict_view(f: &mut Frame, app: &App, area: Rect) {
+    use passmngr::merge::Side;
+
+    let state = match &app.merge_state {
+        Some(state) => state,
+        None => return,
+    };
+
+    let describe = |entry: Option<&passmngr::model::Entry>| match entry {
+        Some(e) => format!(
+            "{} ({}) modified {}",
+            e.name,
+            e.username,
+            e.modified.format("%Y-%m-%d %H:%M:%S")
+        ),
+        None => "deleted".to_string(),
+    };
+
+    let mut lines = vec![
+        Line::from(format!(
+            "The vault changed on disk. {} of {} conflicts unresolved.",
+            state.unresolved(),
+            state.choices.len()
+        )),
+        Line::from(""),
+    ];
+
+    for (i, conflict) in state.merge.conflicts.iter().enumerate() {
+        let is_selected = i == state.selected;
+        let choice = match state.choices[i] {
+            Some(Side::Mine) => "[mine]  ",
+            Some(Side::Theirs) => "[theirs]",
+            None => "[?]     ",
+        };
+
+        lines.push(Line::from(vec![
+            Span::styled(
+                if is_selected { "> " } else { "  " },
+                Style::default()
+                    .fg(Color::Green)
+                    .add_modifier(Modifier::BOLD),
+            ),
+            Span::styled(
+                format!("{} ", choice),
+                Style::default().fg(if state.choices[i].is_some() {
+                    Color::Green
+                } else {
+                    Color::Yellow
+                }),
+            ),
+            Span::styled(
+                conflict.name().to_string(),
+                Style::default()
+                    .fg(if is_selected {
+                        Color::White
+                    } else {
+                        Color::Gray
+                    })
+                    .add_modifier(Modifier::BOLD),
+            ),
+        ]));
+        lines.push(Line::from(vec![
+            Span::styled("      mine:   ", Style::default().fg(Color::Cyan)),
+            Span::raw(describe(conflict.side(Side::Mine))),
+        ]));
+        lines.push(Line::from(vec![
+            Span::styled("      theirs: ", Style::default().fg(Color::Cyan)),
+            Span::raw(describe(conflict.side(Side::Theirs))),
+        ]));
+    }
+
+    let paragraph = Paragraph::new(Text::from(lines))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Resolve Conflicts"),
+        )
+        .wrap(Wrap { trim: false });
+
+    f.render_widget(paragraph, area);
+}
+
+/// Render the trashed entries, newest last
+fn render_trash_view(f: &mut Frame, app: &App, area: Rect) {
+    let name_width = (area.width.saturating_sub(6) as usize * 40 / 100).clamp(15, 40);
+
+    let items: Vec<ListItem> = app
+        .vault
+        .trash
+        .iter()
+        .enumerate()
+        .map(|(i, trashed)| {
+            let is_selected = i == app.trash_selected;
+            let purge = match app.days_until_purge(trashed.deleted) {
+                Some(0) => "purged on next save".to_string(),
+                Some(days) => format!("purged in {} days", days),
+                None => "kept".to_string(),
+            };
+
+            let line = Line::from(vec![
+                Span::styled(
+                    if is_selected { "> " } else { "  " },
+                    Style::default()
+                        .fg(Color::Green)
+                        .add_modifier(Modifier::BOLD),
+                ),
+                Span::styled(
+                    truncate_string(&trashed.entry.name, name_width),
+                    Style::default().fg(if is_selected {
+                        Color::White
+                    } else {
+                        Color::Gray
+                    }),
+                ),
+                Span::raw(" "),
+                Span::styled(
+                    format!("deleted {}", trashed.deleted.format("%Y-%m-%d %H:%M")),
+                    Style::default().fg(Color::DarkGray),
+                ),
+                Span::raw("  "),
+                Span::styled(purge, Style::default().fg(Color::Blue)),
+            ]);
+
+            ListItem::new(line).style(if is_selected {
+                Style::default().bg(Color::DarkGray)
+            } else {
+                Style::default()
+            })
+        })
+        .collect();
+
+    let title = if items.is_empty() {
+        "Trash (empty)".to_string()
+    } else {
+        format!("Trash ({})", items.len())
+    };
+    let list = List::new(items).block(Block::default().borders(Borders::ALL).title(title));
+
+    let mut state = ListState::default();
+    state.select(Some(app.trash_selected));
+    f.render_stateful_widget(list, area, &mut state);
+}
+
+/// Render the choice of templates for a new entry
+fn render_template_picker(f: &mut Frame, app: &App, area: Rect) {
+    let name_width = (area.width.saturating_sub(6) as usize * 40 / 100).clamp(15, 40);
+
+    let rows = std::iter::once(("Blank entry".to_string(), String::new())).chain(
+        app.vault.templates.iter().map(|template| {
+            let mut summary = vec![template.kind.as_str().to_string()];
+            if !template.fields.is_empty() {
+                let labels: Vec<_> = template.fields.iter().map(|f| f.label.as_str()).collect();
+                summary.push(labels.join(", "));
+            }
+            if !template.tags.is_empty() {
+                summary.push(format!("[{}]", template.tags.join(", ")));
+            }
+            summary.push(format!("password {}", template.password));
+            (template.name.clone(), summary.join("  "))
+        }),
+    );
+
+    let items: Vec<ListItem> = rows
+        .enumerate()
+        .map(|(i, (name, summary))| {
+            let is_selected = i == app.template_selected;
+            let line = Line::from(vec![
+                Span::styled(
+                    if is_selected { "> " } else { "  " },
+                    Style::default()
+                        .fg(Color::Green)
+                        .add_modifier(Modifier::BOLD),
+                ),
+                Span::styled(
+                    truncate_string(&name, name_width),
+                    Style::default().fg(if is_selected {
+                        Color::White
+                    } else {
+                        Color::Gray
+                    }),
+                ),
+                Span::raw(" "),
+                Span::styled(summary, Style::default().fg(Color::DarkGray)),
+            ]);
+
+            ListItem::new(line).style(if is_selected {
+                Style::default().bg(Color::DarkGray)
+            } else {
+                Style::default()
+            })
+        })
+        .collect();
+
+    let list = List::new(items).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title("New entry from template"),
+    );
+
+    let mut state = ListState::default();
+    state.select(Some(app.template_selected));
+    f.render_stateful_widget(list, area, &mut state);
 }
 
 /// Render the list of entries
@@ -168,6 +520,7 @@ fn render_list_view(f: &mut Frame, app: &mut App, area: Rect) {
     let name_width = (available_width * 40 / 100).clamp(15, 40);
     let username_width = (available_width * 35 / 100).clamp(15, 35);
     let tags_width = available_width.saturating_sub(name_width + username_width);
+    let now = Utc::now();
 
     let items: Vec<ListItem> = app
         .filtered_entries
@@ -183,7 +536,17 @@ fn render_list_view(f: &mut Frame, app: &mut App, area: Rect) {
                 format!("[{}]", entry.tags.join(", "))
             };
 
-            let name_display = truncate_string(&entry.name, name_width);
+            let name_display = if entry.favorite {
+                truncate_string(&format!("★ {}", entry.name), name_width)
+            } else {
+                truncate_string(&entry.name, name_width)
+            };
+            let name_color = match entry.expiry_state(now) {
+                Some(ExpiryState::Expired) => Color::Red,
+                Some(ExpiryState::Soon) => Color::Yellow,
+                None if is_selected => Color::White,
+                None => Color::Gray,
+            };
             let username_display = truncate_string(&entry.username, username_width);
             let tags_display = truncate_string(&tags_str, tags_width);
 
@@ -194,14 +557,7 @@ fn render_list_view(f: &mut Frame, app: &mut App, area: Rect) {
                         .fg(Color::Green)
                         .add_modifier(Modifier::BOLD),
                 ),
-                Span::styled(
-                    name_display,
-                    Style::default().fg(if is_selected {
-                        Color::White
-                    } else {
-                        Color::Gray
-                    }),
-                ),
+                Span::styled(name_display, Style::default().fg(name_color)),
                 Span::raw(" "),
                 Span::styled(username_display, Style::default().fg(Color::DarkGray)),
                 Span::raw(" "),
@@ -241,26 +597,120 @@ fn render_detail_view(f: &mut Frame, app: &App, area: Rect) {
             Span::raw(&entry.name),
         ]),
         Line::from(""),
-        Line::from(vec![
+    ];
+
+    if let Some(folder) = entry.folder {
+        lines.push(Line::from(vec![
+            Span::styled("Folder: ", Style::default().fg(Color::Cyan)),
+            Span::raw(app.vault.folder_path(&folder)),
+        ]));
+        lines.push(Line::from(""));
+    }
+
+    if !entry.kind.is_login() {
+        lines.push(Line::from(vec![
+            Span::styled("Kind: ", Style::default().fg(Color::Cyan)),
+            Span::raw(entry.kind.as_str()),
+        ]));
+        lines.push(Line::from(""));
+    }
+
+    if entry.kind.has_username() {
+        lines.push(Line::from(vec![
             Span::styled("Username: ", Style::default().fg(Color::Cyan)),
             Span::raw(&entry.username),
-        ]),
-        Line::from(""),
-        Line::from(vec![
-            Span::styled("Password: ", Style::default().fg(Color::Cyan)),
+        ]));
+        lines.push(Line::from(""));
+    }
+
+    if let Some(label) = entry.kind.password_label() {
+        lines.push(Line::from(vec![
+            Span::styled(format!("{}: ", label), Style::default().fg(Color::Cyan)),
             if app.show_password {
                 Span::raw(&entry.password)
             } else {
                 Span::raw("*".repeat(entry.password.len()))
             },
-        ]),
-        Line::from(""),
-    ];
+        ]));
+        lines.push(Line::from(""));
+    }
 
-    if let Some(url) = &entry.url {
+    for (label, value, secret) in entry.kind.details() {
+        if value.is_empty() {
+            continue;
+        }
+        let label = Span::styled(format!("{}: ", label), Style::default().fg(Color::Cyan));
+        if secret && !app.show_password {
+            lines.push(Line::from(vec![label, Span::raw("*".repeat(value.len()))]));
+        } else if value.contains('\n') {
+            // Keys keep their line breaks
+            lines.push(Line::from(vec![label]));
+            lines.extend(value.lines().map(|line| Line::from(line.to_string())));
+        } else {
+            lines.push(Line::from(vec![label, Span::raw(value)]));
+        }
+        lines.push(Line::from(""));
+    }
+
+    if let Some(field) = passmngr::otp::otp_field(entry) {
+        lines.push(render_otp_line(&field.value));
+        lines.push(Line::from(""));
+    }
+
+    if !entry.password_history.is_empty() {
+        lines.push(Line::from(vec![Span::styled(
+            "Previous passwords: ",
+            Style::default().fg(Color::Cyan),
+        )]));
+        for (i, previous) in entry.password_history.iter().enumerate() {
+            lines.push(Line::from(vec![
+                Span::raw(format!("  {}. ", (i + 1) % 10)),
+                if app.show_password {
+                    Span::raw(&previous.password)
+                } else {
+                    Span::raw("*".repeat(previous.password.len()))
+                },
+                Span::styled(
+                    format!(
+                        "  (replaced {})",
+                        previous.replaced.format("%Y-%m-%d %H:%M")
+                    ),
+                    Style::default().fg(Color::DarkGray),
+                ),
+            ]));
+        }
+        lines.push(Line::from(""));
+    }
+
+    if !entry.urls.is_empty() {
+        let label = entry.kind.url_label().unwrap_or("URLs");
+        lines.push(Line::from(vec![Span::styled(
+            format!("{}: ", label),
+            Style::default().fg(Color::Cyan),
+        )]));
+        for url in &entry.urls {
+            lines.push(Line::from(vec![
+                Span::raw(format!("  {}", url.url)),
+                Span::styled(
+                    format!("  ({})", url.rule.as_str()),
+                    Style::default().fg(Color::DarkGray),
+                ),
+            ]));
+        }
+        lines.push(Line::from(""));
+    }
+
+    for field in &entry.fields {
         lines.push(Line::from(vec![
-            Span::styled("URL: ", Style::default().fg(Color::Cyan)),
-            Span::raw(url),
+            Span::styled(
+                format!("{}: ", field.label),
+                Style::default().fg(Color::Cyan),
+            ),
+            if field.kind.is_secret() && !app.show_password {
+                Span::raw("*".repeat(field.value.len()))
+            } else {
+                Span::raw(&field.value)
+            },
         ]));
         lines.push(Line::from(""));
     }
@@ -282,6 +732,43 @@ fn render_detail_view(f: &mut Frame, app: &App, area: Rect) {
         lines.push(Line::from(""));
     }
 
+    if !entry.attachments.is_empty() {
+        lines.push(Line::from(vec![Span::styled(
+            "Attachments: ",
+            Style::default().fg(Color::Cyan),
+        )]));
+        for (i, attachment) in entry.attachments.iter().enumerate() {
+            lines.push(Line::from(vec![
+                Span::raw(format!("  {}. {}", i + 1, attachment.name)),
+                Span::styled(
+                    format!("  ({} bytes)", attachment.size),
+                    Style::default().fg(Color::DarkGray),
+                ),
+            ]));
+        }
+        lines.push(Line::from(""));
+    }
+
+    if let Some(expires) = entry.expires() {
+        let color = match entry.expiry_state(Utc::now()) {
+            Some(ExpiryState::Expired) => Color::Red,
+            Some(ExpiryState::Soon) => Color::Yellow,
+            None => Color::Reset,
+        };
+        lines.push(Line::from(vec![
+            Span::styled("Expires: ", Style::default().fg(Color::Cyan)),
+            Span::styled(
+                expires.format("%Y-%m-%d").to_string(),
+                Style::default().fg(color),
+            ),
+            Span::styled(
+                format!("  ({})", entry.expiry.expect("entry expires")),
+                Style::default().fg(Color::DarkGray),
+            ),
+        ]));
+        lines.push(Line::from(""));
+    }
+
     lines.push(Line::from(""));
     lines.push(Line::from(vec![
         Span::styled("Created: ", Style::default().fg(Color::DarkGray)),
@@ -297,6 +784,16 @@ fn render_detail_view(f: &mut Frame, app: &App, area: Rect) {
             Style::default().fg(Color::DarkGray),
         ),
     ]));
+    lines.push(Line::from(vec![
+        Span::styled("Password changed: ", Style::default().fg(Color::DarkGray)),
+        Span::styled(
+            entry
+                .password_changed
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string(),
+            Style::default().fg(Color::DarkGray),
+        ),
+    ]));
 
     let text = Text::from(lines);
     let paragraph = Paragraph::new(text)
@@ -310,6 +807,51 @@ fn render_detail_view(f: &mut Frame, app: &App, area: Rect) {
     f.render_widget(paragraph, area);
 }
 
+/// The live one-time code of an OTP field, with its countdown
+fn render_otp_line(value: &str) -> Line<'static> {
+    use passmngr::otp::{self, Otp};
+
+    let label = Span::styled("One-time code: ", Style::default().fg(Color::Cyan));
+    let generator = match Otp::parse(value) {
+        Ok(generator) => generator,
+        Err(e) => {
+            return Line::from(vec![
+                label,
+                Span::styled(e.to_string(), Style::default().fg(Color::Red)),
+            ])
+        }
+    };
+
+    let now = otp::now();
+    let code = generator.code_at(now);
+    // Group the digits like authenticator apps do: "123 456", "1234 5678"
+    let (head, tail) = code.split_at(code.len() / 2);
+    let mut spans = vec![
+        label,
+        Span::styled(
+            format!("{} {}", head, tail),
+            Style::default()
+                .fg(Color::White)
+                .add_modifier(Modifier::BOLD),
+        ),
+    ];
+    match generator.seconds_left(now) {
+        Some(left) => spans.push(Span::styled(
+            format!("  ({}s)", left),
+            Style::default().fg(if left <= 5 {
+                Color::Red
+            } else {
+                Color::DarkGray
+            }),
+        )),
+        None => spans.push(Span::styled(
+            "  (counter-based, o to use)",
+            Style::default().fg(Color::DarkGray),
+        )),
+    }
+    Line::from(spans)
+}
+
 /// Render the form for creating/editing entries
 fn render_form_view(f: &mut Frame, app: &App, area: Rect) {
     use passmngr::app::FormField;
@@ -320,28 +862,35 @@ fn render_form_view(f: &mut Frame, app: &App, area: Rect) {
         "New Entry"
     };
 
-    let fields = [
-        FormField::Name,
-        FormField::Username,
-        FormField::Password,
-        FormField::Url,
-        FormField::Notes,
-        FormField::Tags,
-    ];
+    let kind = &app.form_data.kind;
+    let fields = FormField::order(kind, app.form_data.fields.len());
 
-    let mut lines = vec![Line::from("")];
+    let mut lines = vec![
+        Line::from(vec![
+            Span::styled(format!("{:<12} ", "Kind"), Style::default().fg(Color::Cyan)),
+            Span::raw(kind.as_str()),
+        ]),
+        Line::from(""),
+    ];
 
     for field in fields.iter() {
         let is_focused = &app.focused_field == field;
-        let label = field.as_str();
+        let label = match field {
+            FormField::CustomLabel(i) => format!("Field {}", i + 1),
+            FormField::CustomValue(i) => format!("  ({})", app.form_data.fields[*i].kind.as_str()),
+            _ => field.label(kind).to_string(),
+        };
         let value = app.get_field_value(*field);
 
-        let display_value =
-            if field == &FormField::Password && !value.is_empty() && !app.show_password {
-                "*".repeat(value.len())
-            } else {
-                value.to_string()
-            };
+        let masked = match field {
+            FormField::CustomValue(i) => app.form_data.fields[*i].kind.is_secret(),
+            _ => field.is_secret(),
+        };
+        let display_value = if masked && !value.is_empty() && !app.show_password {
+            "*".repeat(value.len())
+        } else {
+            value.to_string()
+        };
 
         lines.push(Line::from(vec![
             Span::styled(
@@ -381,6 +930,16 @@ fn render_form_view(f: &mut Frame, app: &App, area: Rect) {
         Span::styled("Esc:", Style::default().fg(Color::Green)),
         Span::raw(" Cancel"),
     ]));
+    lines.push(Line::from(vec![
+        Span::styled("Ctrl+A:", Style::default().fg(Color::Green)),
+        Span::raw(" Add field  "),
+        Span::styled("Ctrl+D:", Style::default().fg(Color::Green)),
+        Span::raw(" Remove field  "),
+        Span::styled("Ctrl+T:", Style::default().fg(Color::Green)),
+        Span::raw(" Field kind  "),
+        Span::styled("Ctrl+K:", Style::default().fg(Color::Green)),
+        Span::raw(" Entry kind"),
+    ]));
 
     let text = Text::from(lines);
     let paragraph = Paragraph::new(text)
@@ -396,9 +955,28 @@ fn render_footer(f: &mut Frame, app: &App, area: Rect) {
         Mode::Locked => Line::from(vec![
             Span::styled("Enter:", Style::default().fg(Color::White)),
             Span::raw(" Unlock  "),
+            Span::styled("Tab:", Style::default().fg(Color::White)),
+            Span::raw(" Password/Keyfile  "),
             Span::styled("Esc/q:", Style::default().fg(Color::White)),
             Span::raw(" Quit"),
         ]),
+        Mode::Passwd => {
+            if let Some(status) = &app.status_message {
+                Line::from(Span::styled(
+                    status,
+                    Style::default()
+                        .fg(Color::Yellow)
+                        .add_modifier(Modifier::BOLD),
+                ))
+            } else {
+                Line::from(vec![
+                    Span::styled("Enter:", Style::default().fg(Color::Green)),
+                    Span::raw("next  "),
+                    Span::styled("Esc:", Style::default().fg(Color::Green)),
+                    Span::raw("cancel"),
+                ])
+            }
+        }
         Mode::Command => {
             let mut spans = vec![
                 Span::styled(":", Style::default().fg(Color::Magenta)),
@@ -418,6 +996,62 @@ fn render_footer(f: &mut Frame, app: &App, area: Rect) {
 
             Line::from(spans)
         }
+        Mode::Conflict => Line::from(vec![
+            Span::styled("j/k:", Style::default().fg(Color::Green)),
+            Span::raw("nav  "),
+            Span::styled("m/t:", Style::default().fg(Color::Green)),
+            Span::raw("keep mine/theirs  "),
+            Span::styled("M/T:", Style::default().fg(Color::Green)),
+            Span::raw("all mine/theirs  "),
+            Span::styled("Esc:", Style::default().fg(Color::Green)),
+            Span::raw("cancel"),
+        ]),
+        Mode::Trash => {
+            if let Some(status) = &app.status_message {
+                Line::from(Span::styled(
+                    status,
+                    Style::default()
+                        .fg(Color::Yellow)
+                        .add_modifier(Modifier::BOLD),
+                ))
+            } else {
+                Line::from(vec![
+                    Span::styled("j/k:", Style::default().fg(Color::Green)),
+                    Span::raw("nav  "),
+                    Span::styled("r:", Style::default().fg(Color::Green)),
+                    Span::raw("restore  "),
+                    Span::styled("D:", Style::default().fg(Color::Green)),
+                    Span::raw("delete forever  "),
+                    Span::styled("u:", Style::default().fg(Color::Green)),
+                    Span::raw("undo  "),
+                    Span::styled("Esc/q:", Style::default().fg(Color::Green)),
+                    Span::raw("back"),
+                ])
+            }
+        }
+        Mode::Templates => Line::from(vec![
+            Span::styled("j/k:", Style::default().fg(Color::Green)),
+            Span::raw("nav  "),
+            Span::styled("Enter:", Style::default().fg(Color::Green)),
+            Span::raw("new entry  "),
+            Span::styled("Esc:", Style::default().fg(Color::Green)),
+            Span::raw("cancel"),
+        ]),
+        Mode::Folders => Line::from(vec![
+            Span::styled("j/k:", Style::default().fg(Color::Green)),
+            Span::raw("nav  "),
+            Span::styled("Enter:", Style::default().fg(Color::Green)),
+            Span::raw("open  "),
+            Span::styled("Space:", Style::default().fg(Color::Green)),
+            Span::raw("collapse  "),
+            Span::styled(
+                ":mkdir/:rename-folder/:delete-folder",
+                Style::default().fg(Color::Green),
+            ),
+            Span::raw("  "),
+            Span::styled("Esc:", Style::default().fg(Color::Green)),
+            Span::raw("back"),
+        ]),
         Mode::Detail => Line::from(vec![
             Span::styled("Esc/q:", Style::default().fg(Color::Green)),
             Span::raw("back  "),
@@ -426,7 +1060,13 @@ fn render_footer(f: &mut Frame, app: &App, area: Rect) {
             Span::styled("y/Y:", Style::default().fg(Color::Green)),
             Span::raw("copy pass/user  "),
             Span::styled("v:", Style::default().fg(Color::Green)),
-            Span::raw("show/hide"),
+            Span::raw("show/hide  "),
+            Span::styled("o:", Style::default().fg(Color::Green)),
+            Span::raw("copy code  "),
+            Span::styled("1-0:", Style::default().fg(Color::Green)),
+            Span::raw("copy old pass  "),
+            Span::styled(":attach save N PATH:", Style::default().fg(Color::Green)),
+            Span::raw("save attachment"),
         ]),
         _ => {
             if let Some(status) = &app.status_message {
@@ -442,14 +1082,20 @@ fn render_footer(f: &mut Frame, app: &App, area: Rect) {
                     Span::raw("nav  "),
                     Span::styled("/:", Style::default().fg(Color::Green)),
                     Span::raw("search  "),
+                    Span::styled("f:", Style::default().fg(Color::Green)),
+                    Span::raw("folders  "),
                     Span::styled("n:", Style::default().fg(Color::Green)),
                     Span::raw("new  "),
                     Span::styled("e:", Style::default().fg(Color::Green)),
                     Span::raw("edit  "),
                     Span::styled("d:", Style::default().fg(Color::Green)),
                     Span::raw("delete  "),
+                    Span::styled("u:", Style::default().fg(Color::Green)),
+                    Span::raw("undo  "),
                     Span::styled("y:", Style::default().fg(Color::Green)),
                     Span::raw("copy-pass  "),
+                    Span::styled("p:", Style::default().fg(Color::Green)),
+                    Span::raw("pin  "),
                     Span::styled(":q:", Style::default().fg(Color::Green)),
                     Span::raw("quit"),
                 ])
@@ -0,0 +1,273 @@
+//! Matching entry URLs against the address of a page
+//!
+//! Each of an entry's URLs has a [`UrlMatch`] rule. Base domains are looked up
+//! in the public suffix list bundled as `data/public_suffix_list.dat` (from
+//! <https://publicsuffix.org/list/>, MPL-2.0); replace that file to update it.
+
+use crate::model::{Entry, EntryUrl, UrlMatch, Vault};
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use regex::Regex;
+use std::collections::HashSet;
+use std::sync::OnceLock;
+use url::{Host, Url};
+
+const PUBLIC_SUFFIX_LIST: &str = include_str!("../data/public_suffix_list.dat");
+
+/// Rules of the public suffix list, with internationalized names in punycode
+fn suffix_rules() -> &'static HashSet<String> {
+    static RULES: OnceLock<HashSet<String>> = OnceLock::new();
+    RULES.get_or_init(|| {
+        PUBLIC_SUFFIX_LIST
+            .lines()
+            .filter_map(|line| line.split_whitespace().next())
+            .filter(|rule| !rule.starts_with("//"))
+            .filter_map(|rule| {
+                if rule.is_ascii() {
+                    return Some(rule.to_string());
+                }
+                // Wildcard and exception markers are kept out of the conversion
+                let (marker, name) = match rule.strip_prefix("*.") {
+                    Some(name) => ("*.", name),
+                    None => rule
+                        .strip_prefix('!')
+                        .map_or(("", rule), |name| ("!", name)),
+                };
+                match Host::parse(name).ok()? {
+                    Host::Domain(name) => Some(format!("{}{}", marker, name)),
+                    _ => None,
+                }
+            })
+            .collect()
+    })
+}
+
+/// The registrable domain of a host name, e.g. `example.co.uk` for
+/// `login.example.co.uk`
+///
+/// `None` for hosts that are a public suffix themselves.
+pub fn base_domain(host: &str) -> Option<&str> {
+    let rules = suffix_rules();
+    let host = host.trim_end_matches('.');
+    let labels: Vec<&str> = host.split('.').collect();
+
+    // The longest matching rule wins; exceptions beat wildcards.
+    // Without any match the last label is the suffix ("*" rule).
+    let suffix_labels = (0..labels.len())
+        .find_map(|i| {
+            let name = labels[i..].join(".");
+            let parent = labels[i + 1..].join(".");
+            if rules.contains(&format!("!{}", name)) {
+                Some(labels.len() - i - 1)
+            } else if rules.contains(&name) || rules.contains(&format!("*.{}", parent)) {
+                Some(labels.len() - i)
+            } else {
+                None
+            }
+        })
+        .unwrap_or(1);
+
+    let start = labels.len().checked_sub(suffix_labels + 1)?;
+    let offset: usize = labels[..start].iter().map(|label| label.len() + 1).sum();
+    Some(&host[offset..])
+}
+
+/// Parse an address, taking a bare host like `example.com` as `https://`
+pub fn parse_address(text: &str) -> Result<Url> {
+    let text = text.trim();
+    match Url::parse(text) {
+        Ok(url) if url.has_host() => Ok(url),
+        _ => Url::parse(&format!("https://{}", text))
+            .ok()
+            .filter(Url::has_host)
+            .ok_or_else(|| anyhow!("Not a URL: {}", text)),
+    }
+}
+
+/// Check that a URL can be used for matching (regexes have to compile)
+pub fn validate(url: &EntryUrl) -> Result<()> {
+    match url.rule {
+        UrlMatch::Regex => Regex::new(&url.url)
+            .map(|_| ())
+            .map_err(|e| anyhow!("Invalid regex {}: {}", url.url, e)),
+        _ => parse_address(&url.url).map(|_| ()),
+    }
+}
+
+/// Whether `address` matches an entry URL under its rule
+pub fn url_matches(url: &EntryUrl, address: &Url) -> bool {
+    if url.rule == UrlMatch::Regex {
+        return Regex::new(&url.url).is_ok_and(|regex| regex.is_match(address.as_str()));
+    }
+    let Ok(own) = parse_address(&url.url) else {
+        return false;
+    };
+
+    match url.rule {
+        UrlMatch::Exact => own == *address,
+        UrlMatch::Prefix => address.as_str().starts_with(own.as_str()),
+        UrlMatch::Host => {
+            own.host() == address.host()
+                && own.port_or_known_default() == address.port_or_known_default()
+        }
+        UrlMatch::Domain => match (own.host(), address.host()) {
+            (Some(Host::Domain(own)), Some(Host::Domain(other))) => {
+                match (base_domain(own), base_domain(other)) {
+                    (Some(own), Some(other)) => own == other,
+                    _ => own == other,
+                }
+            }
+            (own, other) => own == other,
+        },
+        UrlMatch::Regex => unreachable!("handled above"),
+    }
+}
+
+/// The strictest rule under which one of the entry's URLs matches `address`
+pub fn entry_match(entry: &Entry, address: &Url) -> Option<UrlMatch> {
+    entry
+        .urls
+        .iter()
+        .filter(|url| url_matches(url, address))
+        .map(|url| url.rule)
+        .min()
+}
+
+/// Entries with a URL matching `address`, best match first
+///
+/// Stricter rules rank higher; ties go to pinned, then frequently used entries.
+pub fn find_by_url<'a>(vault: &'a Vault, address: &str) -> Result<Vec<(&'a Entry, UrlMatch)>> {
+    let address = parse_address(address)?;
+    let now = Utc::now();
+    let mut found: Vec<_> = vault
+        .entries
+        .iter()
+        .filter_map(|entry| Some((entry, entry_match(entry, &address)?)))
+        .collect();
+    found.sort_by(|(a, a_rule), (b, b_rule)| {
+        a_rule
+            .cmp(b_rule)
+            .then_with(|| b.favorite.cmp(&a.favorite))
+            .then_with(|| b.frecency(now).cmp(&a.frecency(now)))
+    });
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(url: &str, rule: UrlMatch, address: &str) -> bool {
+        let url = EntryUrl {
+            url: url.to_string(),
+            rule,
+        };
+        url_matches(&url, &parse_address(address).unwrap())
+    }
+
+    #[test]
+    fn test_base_domain() {
+        assert_eq!(base_domain("login.example.co.uk"), Some("example.co.uk"));
+        assert_eq!(base_domain("example.com"), Some("example.com"));
+        assert_eq!(base_domain("a.b.example.com"), Some("example.com"));
+        assert_eq!(base_domain("co.uk"), None);
+        // Private suffixes separate sites on shared hosting
+        assert_eq!(base_domain("alice.github.io"), Some("alice.github.io"));
+        // Wildcard and exception rules (*.ck, !www.ck)
+        assert_eq!(base_domain("shop.example.ck"), Some("shop.example.ck"));
+        assert_eq!(base_domain("www.ck"), Some("www.ck"));
+        // Unlisted top-level domains
+        assert_eq!(base_domain("router.internal"), Some("router.internal"));
+    }
+
+    #[test]
+    fn test_match_rules() {
+        let address = "https://login.example.co.uk/path?x=1";
+
+        assert!(matches("example.co.uk", UrlMatch::Domain, address));
+        assert!(!matches("other.co.uk", UrlMatch::Domain, address));
+
+        assert!(matches(
+            "https://login.example.co.uk",
+            UrlMatch::Host,
+            address
+        ));
+        assert!(!matches("https://example.co.uk", UrlMatch::Host, address));
+        assert!(!matches(
+            "https://login.example.co.uk:8443",
+            UrlMatch::Host,
+            address
+        ));
+
+        assert!(matches(
+            "https://login.example.co.uk/path",
+            UrlMatch::Prefix,
+            address
+        ));
+        assert!(!matches(
+            "https://login.example.co.uk/other",
+            UrlMatch::Prefix,
+            address
+        ));
+
+        assert!(matches(
+            "https://LOGIN.example.co.uk/path?x=1",
+            UrlMatch::Exact,
+            address
+        ));
+        assert!(!matches(
+            "https://login.example.co.uk/path",
+            UrlMatch::Exact,
+            address
+        ));
+
+        assert!(matches(
+            r"^https://[a-z]+\.example\.co\.uk/",
+            UrlMatch::Regex,
+            address
+        ));
+        assert!(!matches(r"^http://", UrlMatch::Regex, address));
+        assert!(!matches("(", UrlMatch::Regex, address));
+
+        // IP addresses have no base domain
+        assert!(matches(
+            "http://10.0.0.1",
+            UrlMatch::Domain,
+            "http://10.0.0.1:8080/"
+        ));
+        assert!(!matches(
+            "http://10.0.0.1",
+            UrlMatch::Domain,
+            "http://10.0.0.2/"
+        ));
+    }
+
+    #[test]
+    fn test_find_by_url() {
+        let mut vault = Vault::new();
+        let entry = |name: &str, urls: &str| {
+            let mut entry = Entry::new(
+                name.to_string(),
+                "user".to_string(),
+                "pass".to_string(),
+                None,
+                None,
+                vec![],
+            );
+            entry.urls = EntryUrl::parse_list(urls);
+            entry
+        };
+        vault.add_entry(entry("Domain", "example.co.uk"));
+        vault.add_entry(entry("Exact", "exact:https://login.example.co.uk/sso"));
+        vault.add_entry(entry("Other", "example.com"));
+        vault.add_entry(entry("SSO", "sso.example.net host:login.example.co.uk"));
+        vault.add_entry(entry("Pattern", "regex:example"));
+
+        let found = find_by_url(&vault, "https://login.example.co.uk/sso").unwrap();
+        let names: Vec<_> = found.iter().map(|(e, _)| e.name.as_str()).collect();
+        assert_eq!(names, vec!["Exact", "SSO", "Domain", "Pattern"]);
+        assert_eq!(found[1].1, UrlMatch::Host);
+
+        assert!(find_by_url(&vault, "not a url").is_err());
+    }
+}